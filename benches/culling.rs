@@ -0,0 +1,92 @@
+//! Frame time and culling counts of the 100x100 cube grid from main.rs, rendered offscreen
+//! with the camera looking along the grid rows so most cubes hide behind the first ones.
+//!
+//! Compares CPU frustum culling, GPU frustum culling and GPU frustum + Hi-Z occlusion culling.
+//!
+//! `cargo bench --bench culling`, set `CULLING_GRID=<n>` for an n x n grid on slow
+//! (e.g. software) adapters
+
+use std::time::{Duration, Instant};
+
+use glam::{Mat4, Quat, Vec3};
+use project::renderer::backend::definitions::{Camera, CullingMode, FrameStats};
+use project::renderer::renderer::RendererState;
+
+const DEFAULT_GRID: usize = 100;
+const SPACING: f32 = 50.0;
+const WARMUP_FRAMES: usize = 10;
+const FRAMES: usize = 100;
+
+fn grid_size() -> usize {
+    std::env::var("CULLING_GRID")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_GRID)
+}
+
+fn spawn_grid(state: &mut RendererState) {
+    let grid = grid_size();
+    for y in 0..grid {
+        for x in 0..grid {
+            let pos = Vec3::new(x as f32 * SPACING, 0.0, y as f32 * SPACING);
+            let rot = Quat::from_rotation_z((x * grid + y) as f32);
+            state
+                .spawn_instance("companion_cube", Mat4::from_rotation_translation(rot, pos))
+                .unwrap();
+        }
+    }
+}
+
+fn measure(mode: CullingMode, occlusion: bool) -> (Duration, FrameStats) {
+    let mut state = pollster::block_on(RendererState::new_headless(800, 600));
+    state
+        .load_assets("companion_cube", "assets/companion_cube/companion_cube.obj")
+        .unwrap();
+    spawn_grid(&mut state);
+    state.set_culling_mode(mode);
+    state.set_occlusion_culling(occlusion);
+
+    // in front of the grid, level with its middle row, looking down the rows
+    let mut camera = Camera::new();
+    camera.position = Vec3::new(-100.0, 0.0, grid_size() as f32 * SPACING * 0.5);
+
+    for _ in 0..WARMUP_FRAMES {
+        state.render(&camera).unwrap();
+    }
+    // drain the queue so every run starts from an idle GPU
+    state.read_frame();
+
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        state.render(&camera).unwrap();
+    }
+    // count the frames still queued on the GPU too
+    state.read_frame();
+    let frame_time = start.elapsed() / FRAMES as u32;
+
+    let stats = match mode {
+        CullingMode::Gpu => state.read_gpu_cull_stats().unwrap(),
+        _ => state.frame_stats(),
+    };
+    (frame_time, stats)
+}
+
+fn main() {
+    let grid = grid_size();
+    println!("{grid}x{grid} cubes, {FRAMES} frames");
+
+    for (name, mode, occlusion) in [
+        ("cpu frustum", CullingMode::Cpu, false),
+        ("gpu frustum", CullingMode::Gpu, false),
+        ("gpu frustum + hi-z", CullingMode::Gpu, true),
+    ] {
+        let (frame_time, stats) = measure(mode, occlusion);
+        println!(
+            "{name:<20} {:>8.3} ms/frame  visible {:>6}  frustum culled {:>6}  occluded {:>6}",
+            frame_time.as_secs_f64() * 1e3,
+            stats.visible_instances,
+            stats.frustum_culled_instances,
+            stats.occlusion_culled_instances,
+        );
+    }
+}
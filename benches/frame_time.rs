@@ -0,0 +1,103 @@
+//! Frame time of the 100x100 cube grid from main.rs, rendered offscreen.
+//!
+//! Compares waiting for every frame to finish (frame latency 1, what `render` used to do
+//! with its blocking `device.poll`) against letting frames overlap with the CPU.
+//!
+//! `cargo bench --bench frame_time`, set `FRAME_TIME_GRID=<n>` for an n x n grid on slow
+//! (e.g. software) adapters
+
+use std::time::{Duration, Instant};
+
+use glam::{Mat4, Quat, Vec3};
+use project::renderer::backend::definitions::Camera;
+use project::renderer::backend::instances::InstanceHandle;
+use project::renderer::renderer::RendererState;
+
+const DEFAULT_GRID: usize = 100;
+const SPACING: f32 = 50.0;
+const WARMUP_FRAMES: usize = 20;
+const FRAMES: usize = 200;
+/// instances moved every frame, the rest stay static
+const MOVING: usize = 100;
+
+fn grid_size() -> usize {
+    std::env::var("FRAME_TIME_GRID")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_GRID)
+}
+
+fn spawn_grid(state: &mut RendererState) -> Vec<InstanceHandle> {
+    let grid = grid_size();
+    let mut handles = Vec::with_capacity(grid * grid);
+    for y in 0..grid {
+        for x in 0..grid {
+            let pos = Vec3::new(x as f32 * SPACING, 0.0, y as f32 * SPACING);
+            let rot = Quat::from_rotation_z((x * grid + y) as f32);
+            handles.push(
+                state
+                    .spawn_instance("companion_cube", Mat4::from_rotation_translation(rot, pos))
+                    .unwrap(),
+            );
+        }
+    }
+    handles
+}
+
+/// simulates some CPU work per frame: moving a few instances
+fn step(state: &mut RendererState, handles: &[InstanceHandle], frame: usize) {
+    for (i, handle) in handles.iter().take(MOVING).enumerate() {
+        let pos = Vec3::new(i as f32 * SPACING, (frame as f32 * 0.1).sin() * 10.0, 0.0);
+        state.set_transform(*handle, Mat4::from_translation(pos));
+    }
+}
+
+fn measure(frame_latency: u32) -> Duration {
+    let mut state = pollster::block_on(RendererState::new_headless(800, 600));
+    state.set_frame_latency(frame_latency);
+    state
+        .load_assets("companion_cube", "assets/companion_cube/companion_cube.obj")
+        .unwrap();
+    let handles = spawn_grid(&mut state);
+
+    let mut camera = Camera::new();
+    camera.position = Vec3::new(-100.0, -100.0, 300.0);
+
+    for frame in 0..WARMUP_FRAMES {
+        step(&mut state, &handles, frame);
+        state.render(&camera).unwrap();
+    }
+    // drain the queue so both runs start from an idle GPU
+    state.read_frame();
+
+    let start = Instant::now();
+    for frame in 0..FRAMES {
+        step(&mut state, &handles, frame);
+        state.render(&camera).unwrap();
+    }
+    // count the frames still queued on the GPU too
+    state.read_frame();
+
+    start.elapsed() / FRAMES as u32
+}
+
+fn main() {
+    let grid = grid_size();
+    println!("{grid}x{grid} cubes, {FRAMES} frames");
+
+    let blocking = measure(1);
+    let pipelined = measure(3);
+
+    println!(
+        "frame latency 1 (blocking): {:>8.3} ms/frame",
+        blocking.as_secs_f64() * 1e3
+    );
+    println!(
+        "frame latency 3 (ring):     {:>8.3} ms/frame",
+        pipelined.as_secs_f64() * 1e3
+    );
+    println!(
+        "speedup: {:.2}x",
+        blocking.as_secs_f64() / pipelined.as_secs_f64()
+    );
+}
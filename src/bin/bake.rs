@@ -0,0 +1,30 @@
+//! Bakes every OBJ below an asset directory (plus its MTLs and textures) into the
+//! renderer's binary formats and writes a manifest for `RendererState::load_manifest`.
+//!
+//! usage: `bake [asset_dir] [out_dir]`, defaults to `assets` and `baked`
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use project::renderer::backend::bake::{MANIFEST_NAME, bake};
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let asset_dir = PathBuf::from(args.next().unwrap_or("assets".to_string()));
+    let out_dir = PathBuf::from(args.next().unwrap_or("baked".to_string()));
+
+    let report = bake(&asset_dir, &out_dir);
+
+    for (id, mesh) in &report.manifest.meshes {
+        println!("baked {} from {}", id, mesh.source);
+    }
+    println!("wrote {}", out_dir.join(MANIFEST_NAME).to_string_lossy());
+
+    if report.problems.is_empty() {
+        return ExitCode::SUCCESS;
+    }
+    for problem in &report.problems {
+        eprintln!("error: {}", problem);
+    }
+    ExitCode::FAILURE
+}
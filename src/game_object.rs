@@ -1,8 +1,6 @@
-use crate::physics::physics::*;
-use crate::renderer::renderer::RendererState;
-
 // trait GameObject {
 
 // }
 /// describes an object instance with its physics representation, rendering data etc
+#[allow(dead_code)]
 struct GameObject {}
@@ -0,0 +1,4 @@
+pub mod game_object;
+pub mod physics;
+pub mod renderer;
+pub mod window;
@@ -6,25 +6,26 @@
 
 use std::sync::Mutex;
 
-use glfw::*;
-mod renderer;
-use renderer::backend::definitions::{Camera, InstanceData};
-use renderer::renderer::RendererState;
-mod physics;
-use crate::physics::physics::PhysicsWorld;
 use glam::*;
-use physics::*;
+use project::physics::physics::PhysicsWorld;
+use project::renderer::backend::assets::{DirectoryAssets, EmbeddedAssets};
+use project::renderer::backend::bake::MANIFEST_NAME;
+use project::renderer::backend::definitions::Camera;
+use project::renderer::backend::mesh_cache;
+use project::renderer::renderer::RendererState;
+use project::window::SurfaceProvider;
 use rand::Rng;
 use rapier3d::prelude::*;
-use std::sync::Arc;
-mod game_object;
 // use crate::ASSETS_DIR;
-use game_object::*;
 use include_dir::{Dir, include_dir};
 
 static ASSETS_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/assets");
 
-// TODO: implement occlusion and frustum culling
+/// output of `cargo run --bin bake`, used instead of `ASSETS_DIR` when it exists
+const BAKED_DIR: &str = "baked";
+
+#[cfg(not(any(feature = "glfw", feature = "winit")))]
+compile_error!("the binary needs a windowing backend, enable the `glfw` or `winit` feature");
 
 pub struct AppState {
     pub phys_world: Mutex<PhysicsWorld>,
@@ -39,25 +40,38 @@ impl AppState {
     }
 }
 
-async fn run() {
-    let mut camera = Camera::new();
-
-    let mut glfw = glfw::init(fail_on_errors!()).unwrap();
-    glfw.window_hint(WindowHint::ClientApi(ClientApiHint::NoApi));
-    let (mut window, events) = glfw
-        .create_window(800, 600, "wgpu", glfw::WindowMode::Windowed)
-        .unwrap();
-
-    let mut state = RendererState::new(&mut window).await;
+/// edits to the shaders in this directory show up while running, when `HOT_RELOAD_SHADERS` is set
+const SHADER_DIR: &str = "src/shaders";
 
-    state.window.set_framebuffer_size_polling(true);
-    state.window.set_key_polling(true);
-    state.window.set_mouse_button_polling(true);
-    state.window.set_pos_polling(true);
-    state.window.set_cursor_mode(glfw::CursorMode::Hidden);
+/// loads the models and spawns a grid of randomly rotated cubes
+fn spawn_scene(state: &mut RendererState) {
+    if std::env::var_os("HOT_RELOAD_SHADERS").is_some() {
+        state.enable_shader_hot_reload(SHADER_DIR);
+    }
 
-    state.load_assets("companion_cube", "assets/companion_cube/companion_cube.obj");
-    state.load_assets("spaceship", "assets/spaceship/spaceship.obj");
+    // prefer the output of the `bake` binary when there is one
+    let mut baked = false;
+    if std::path::Path::new(BAKED_DIR)
+        .join(MANIFEST_NAME)
+        .is_file()
+    {
+        state.set_asset_source(DirectoryAssets::new(BAKED_DIR));
+        match state.load_manifest(MANIFEST_NAME) {
+            Ok(()) => baked = true,
+            Err(error) => eprintln!("could not load the baked assets: {}", error),
+        }
+    }
+    if !baked {
+        state.set_asset_source(EmbeddedAssets::new(&ASSETS_DIR));
+        // parsed meshes are cached in the working directory, like `BAKED_DIR` is read from it
+        state.set_mesh_cache_dir(Some(std::path::PathBuf::from(mesh_cache::CACHE_DIR)));
+        state
+            .load_assets("companion_cube", "companion_cube/companion_cube.obj")
+            .unwrap();
+        state
+            .load_assets("spaceship", "spaceship/spaceship.obj")
+            .unwrap();
+    }
 
     // spawn a bunch of instances
     let mut rng = rand::rng();
@@ -79,21 +93,55 @@ async fn run() {
 
             let rot = glam::Quat::from_axis_angle(rand_axis, rand_angle);
 
-            if let Some(instances) = state.instances.get_mut("companion_cube") {
-                instances.push(InstanceData::from_pos_rot(pos, rot, 1.0));
-            }
+            state
+                .spawn_instance("companion_cube", Mat4::from_rotation_translation(rot, pos))
+                .unwrap();
+        }
+    }
+}
+
+fn render_frame(state: &mut RendererState, camera: &Camera, window: &mut impl SurfaceProvider) {
+    match state.render(camera) {
+        Ok(_) => {}
+        Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
+            state.update_surface(window);
+            state.resize(state.size);
         }
+        Err(e) => eprintln!("{:?}", e),
     }
+}
+
+#[cfg(feature = "glfw")]
+async fn run() {
+    use glfw::*;
+
+    let mut camera = Camera::new();
+
+    let mut glfw = glfw::init(fail_on_errors!()).unwrap();
+    glfw.window_hint(WindowHint::ClientApi(ClientApiHint::NoApi));
+    let (mut window, events) = glfw
+        .create_window(800, 600, "wgpu", glfw::WindowMode::Windowed)
+        .unwrap();
 
-    while !state.window.should_close() {
+    let mut state = RendererState::new(&mut window).await;
+
+    window.set_framebuffer_size_polling(true);
+    window.set_key_polling(true);
+    window.set_mouse_button_polling(true);
+    window.set_pos_polling(true);
+    window.set_cursor_mode(glfw::CursorMode::Hidden);
+
+    spawn_scene(&mut state);
+
+    while !window.should_close() {
         glfw.poll_events();
-        camera.update(1000.0 / 60.0, state.window);
+        camera.update(1000.0 / 60.0, state.size, &mut window);
 
         for (_, event) in glfw::flush_messages(&events) {
             match event {
                 // esc
                 glfw::WindowEvent::Key(Key::Escape, _, Action::Press, _) => {
-                    state.window.set_should_close(true)
+                    window.set_should_close(true)
                 }
 
                 // // window moved
@@ -111,17 +159,109 @@ async fn run() {
             }
         }
 
-        match state.render(&camera) {
-            Ok(_) => {}
-            Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                state.update_surface();
-                state.resize(state.size);
+        render_frame(&mut state, &camera, &mut window);
+    }
+}
+
+/// winit drives the loop through callbacks instead of a poll loop
+#[cfg(all(feature = "winit", not(feature = "glfw")))]
+mod winit_app {
+    use std::sync::Arc;
+
+    use project::renderer::backend::definitions::Camera;
+    use project::renderer::renderer::RendererState;
+    use project::window::winit_backend::WinitInput;
+    use winit::application::ApplicationHandler;
+    use winit::event::{DeviceEvent, DeviceId, ElementState, WindowEvent};
+    use winit::event_loop::{ActiveEventLoop, EventLoop};
+    use winit::keyboard::{KeyCode, PhysicalKey};
+    use winit::window::{CursorGrabMode, Window, WindowId};
+
+    #[derive(Default)]
+    struct App {
+        window: Option<Arc<Window>>,
+        state: Option<RendererState>,
+        camera: Camera,
+        input: WinitInput,
+    }
+
+    impl ApplicationHandler for App {
+        fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+            if self.window.is_some() {
+                return;
+            }
+
+            let attributes = Window::default_attributes()
+                .with_title("wgpu")
+                .with_inner_size(winit::dpi::PhysicalSize::new(800, 600));
+            let mut window = Arc::new(event_loop.create_window(attributes).unwrap());
+            window.set_cursor_visible(false);
+            let _ = window
+                .set_cursor_grab(CursorGrabMode::Confined)
+                .or_else(|_| window.set_cursor_grab(CursorGrabMode::Locked));
+
+            let mut state = pollster::block_on(RendererState::new(&mut window));
+            super::spawn_scene(&mut state);
+
+            window.request_redraw();
+            self.window = Some(window);
+            self.state = Some(state);
+        }
+
+        fn window_event(
+            &mut self,
+            event_loop: &ActiveEventLoop,
+            _window_id: WindowId,
+            event: WindowEvent,
+        ) {
+            self.input.handle_window_event(&event);
+            let (Some(window), Some(state)) = (&mut self.window, &mut self.state) else {
+                return;
+            };
+
+            match event {
+                WindowEvent::CloseRequested => event_loop.exit(),
+
+                // esc
+                WindowEvent::KeyboardInput { event, .. }
+                    if event.physical_key == PhysicalKey::Code(KeyCode::Escape)
+                        && event.state == ElementState::Pressed =>
+                {
+                    event_loop.exit()
+                }
+
+                // window resized
+                WindowEvent::Resized(size) => {
+                    state.resize((size.width as i32, size.height as i32));
+                }
+
+                WindowEvent::RedrawRequested => {
+                    self.camera
+                        .update(1000.0 / 60.0, state.size, &mut self.input);
+                    super::render_frame(state, &self.camera, window);
+                    window.request_redraw();
+                }
+                _ => {}
             }
-            Err(e) => eprintln!("{:?}", e),
         }
+
+        fn device_event(
+            &mut self,
+            _event_loop: &ActiveEventLoop,
+            _device_id: DeviceId,
+            event: DeviceEvent,
+        ) {
+            self.input.handle_device_event(&event);
+        }
+    }
+
+    pub fn run() {
+        let event_loop = EventLoop::new().unwrap();
+        event_loop.run_app(&mut App::default()).unwrap();
     }
 }
 
+#[allow(dead_code)]
 fn physics_thread(appstate: AppState) {
     println!("Physics thread starting");
 
@@ -160,5 +300,8 @@ fn main() {
     // let global_app_state = AppState::new(physics);
 
     // std::thread::spawn(move || physics_thread(global_app_state));
+    #[cfg(feature = "glfw")]
     pollster::block_on(run());
+    #[cfg(all(feature = "winit", not(feature = "glfw")))]
+    winit_app::run();
 }
@@ -1,2 +1,3 @@
 // pub mod assets;
+#[allow(clippy::module_inception)]
 pub mod physics;
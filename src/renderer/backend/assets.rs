@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+use include_dir::Dir;
+
+use super::mesh_cache;
+
+/// where models, materials, textures and shaders are read from.
+/// paths use `/` separators and are relative to the source's root
+pub trait AssetSource: Send + Sync {
+    /// contents of `path`, `None` if there is no such file
+    fn read(&self, path: &str) -> Option<Vec<u8>>;
+
+    fn read_to_string(&self, path: &str) -> Option<String> {
+        String::from_utf8(self.read(path)?).ok()
+    }
+}
+
+/// `path` with `.` and `..` resolved without touching the disk, so the same file reached
+/// through different relative paths gets the same name
+pub fn normalize(path: &Path) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    let mut absolute = false;
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => {
+                parts.push(prefix.as_os_str().to_string_lossy().to_string())
+            }
+            Component::RootDir => absolute = true,
+            Component::CurDir => {}
+            Component::ParentDir => match parts.last() {
+                Some(last) if last != ".." => {
+                    parts.pop();
+                }
+                _ => parts.push("..".to_string()),
+            },
+            Component::Normal(part) => parts.push(part.to_string_lossy().to_string()),
+        }
+    }
+
+    let joined = parts.join("/");
+    match absolute {
+        true => format!("/{}", joined),
+        false => joined,
+    }
+}
+
+/// `path` relative to the directory `relative_to` is in, normalized
+pub fn sibling(relative_to: &str, path: &str) -> String {
+    let dir = Path::new(relative_to).parent().unwrap_or(Path::new(""));
+    normalize(&dir.join(path))
+}
+
+/// files compiled into the binary with `include_dir!`
+pub struct EmbeddedAssets {
+    dir: &'static Dir<'static>,
+}
+
+impl EmbeddedAssets {
+    pub fn new(dir: &'static Dir<'static>) -> Self {
+        EmbeddedAssets { dir }
+    }
+}
+
+impl AssetSource for EmbeddedAssets {
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.dir
+            .get_file(normalize(Path::new(path)))
+            .map(|file| file.contents().to_vec())
+    }
+}
+
+/// files below a directory on disk, relative roots are relative to the working directory
+pub struct DirectoryAssets {
+    root: PathBuf,
+}
+
+impl DirectoryAssets {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        DirectoryAssets { root: root.into() }
+    }
+
+    /// `mesh_cache::CACHE_DIR` below the root, for `RendererState::set_mesh_cache_dir`
+    pub fn mesh_cache_dir(&self) -> PathBuf {
+        self.root.join(mesh_cache::CACHE_DIR)
+    }
+}
+
+impl AssetSource for DirectoryAssets {
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        std::fs::read(self.root.join(path)).ok()
+    }
+}
+
+/// a whole directory packed into one lz4 compressed file by `ArchiveAssets::pack`
+pub struct ArchiveAssets {
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl ArchiveAssets {
+    /// reads an archive written by `pack`, `None` if it is missing or damaged
+    pub fn open(archive: &Path) -> Option<Self> {
+        mesh_cache::read_compressed(archive).map(|files| ArchiveAssets { files })
+    }
+
+    /// packs every file below `dir` into `archive`
+    pub fn pack(dir: &Path, archive: &Path) -> std::io::Result<()> {
+        let mut files = BTreeMap::new();
+        collect_files(dir, dir, &mut files)?;
+        mesh_cache::write_compressed(archive, &files)
+    }
+}
+
+impl AssetSource for ArchiveAssets {
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.files.get(&normalize(Path::new(path))).cloned()
+    }
+}
+
+fn collect_files(
+    root: &Path,
+    dir: &Path,
+    files: &mut BTreeMap<String, Vec<u8>>,
+) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+        } else {
+            let name = normalize(path.strip_prefix(root).unwrap());
+            files.insert(name, std::fs::read(&path)?);
+        }
+    }
+    Ok(())
+}
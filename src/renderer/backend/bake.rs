@@ -0,0 +1,282 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use glam::Mat4;
+use serde::{Deserialize, Serialize};
+
+use super::assets::{AssetSource, DirectoryAssets, normalize, sibling};
+use super::mesh_builder::{ObjLoader, load_obj, map_file};
+use super::mesh_cache::{self, CompiledMesh};
+
+/// extension of baked meshes, lz4 compressed bincode of `CompiledMesh`
+pub const MESH_EXTENSION: &str = "mesh";
+/// extension of baked textures, lz4 compressed bincode of `CompiledTexture`
+pub const TEXTURE_EXTENSION: &str = "tex";
+/// file name of the manifest inside the output directory
+pub const MANIFEST_NAME: &str = "manifest.toml";
+
+/// an RGBA8 image with its full mip chain, largest level first
+#[derive(Serialize, Deserialize)]
+pub struct CompiledTexture {
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl CompiledTexture {
+    /// decodes an image file and halves it level by level down to 1x1
+    pub fn from_image(bytes: &[u8]) -> Result<Self, String> {
+        let mut level = image::load_from_memory(bytes)
+            .map_err(|e| e.to_string())?
+            .to_rgba8();
+        let (width, height) = level.dimensions();
+
+        let mut levels = vec![level.as_raw().clone()];
+        while level.width() > 1 || level.height() > 1 {
+            let w = (level.width() / 2).max(1);
+            let h = (level.height() / 2).max(1);
+            level = image::imageops::resize(&level, w, h, image::imageops::FilterType::Triangle);
+            levels.push(level.as_raw().clone());
+        }
+
+        Ok(CompiledTexture {
+            width,
+            height,
+            levels,
+        })
+    }
+}
+
+/// one baked model, paths are relative to the manifest
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestMesh {
+    /// the OBJ it was baked from, relative to the asset directory
+    pub source: String,
+    /// the baked `CompiledMesh`
+    pub mesh: String,
+    /// baked textures its materials use
+    pub textures: Vec<String>,
+}
+
+/// what `bake` produced, consumed by `RendererState::load_manifest`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    /// models by the id they get loaded under, the OBJ's file stem
+    pub meshes: BTreeMap<String, ManifestMesh>,
+    /// baked texture for every source image of the asset directory
+    pub textures: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    pub fn write(&self, filename: &Path) -> std::io::Result<()> {
+        std::fs::write(filename, toml::to_string_pretty(self).unwrap())
+    }
+}
+
+/// result of a `bake` run
+#[derive(Debug, Default)]
+pub struct BakeReport {
+    pub manifest: Manifest,
+    /// everything that kept a model from being baked, one line each
+    pub problems: Vec<String>,
+}
+
+impl BakeReport {
+    fn meshes_named(&self, id: &str) -> Option<&str> {
+        self.manifest
+            .meshes
+            .get(id)
+            .map(|mesh| mesh.source.as_str())
+    }
+}
+
+/// every file below `dir` with the extension `extension`
+fn find_files(dir: &Path, extension: &str, found: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            find_files(&path, extension, found);
+        } else if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case(extension))
+        {
+            found.push(path);
+        }
+    }
+}
+
+/// checks what tobj would silently paper over: missing MTLs, out of range material indices,
+/// and textures that don't exist
+fn validate(assets: &dyn AssetSource, filename: &str) -> Vec<String> {
+    let (models, materials) = match load_obj(assets, filename) {
+        Ok(loaded) => loaded,
+        Err(e) => return vec![format!("{}: {}", filename, e)],
+    };
+
+    let mut problems = Vec::new();
+    let materials = match materials {
+        Ok(materials) => materials,
+        Err(e) => {
+            problems.push(format!("{}: could not load materials: {}", filename, e));
+            Vec::new()
+        }
+    };
+
+    for model in &models {
+        if let Some(id) = model.mesh.material_id
+            && id >= materials.len()
+        {
+            problems.push(format!(
+                "{}: mesh '{}' uses material {} but only {} are defined",
+                filename,
+                model.name,
+                id,
+                materials.len()
+            ));
+        }
+    }
+
+    for material in &materials {
+        let maps = [
+            material.diffuse_texture.as_ref(),
+            material.unknown_param.get("map_Pr"),
+            material.unknown_param.get("map_Pm"),
+        ];
+        for texture in maps.into_iter().flatten() {
+            if assets.read(&sibling(filename, map_file(texture))).is_none() {
+                problems.push(format!(
+                    "{}: material '{}' references missing texture {}",
+                    filename, material.name, texture
+                ));
+            }
+        }
+    }
+
+    problems
+}
+
+/// converts every OBJ below `asset_dir` and the textures it references into `out_dir`,
+/// and writes a manifest listing them. models with problems are reported and left out
+pub fn bake(asset_dir: &Path, out_dir: &Path) -> BakeReport {
+    let mut report = BakeReport::default();
+    let mut objs = Vec::new();
+    find_files(asset_dir, "obj", &mut objs);
+    objs.sort();
+
+    let assets = Arc::new(DirectoryAssets::new(asset_dir));
+    let mut loader = ObjLoader::new();
+    loader.set_optimize(true);
+    loader.set_asset_source(assets.clone());
+
+    for obj in &objs {
+        let source = normalize(obj.strip_prefix(asset_dir).unwrap());
+        let id = obj.file_stem().unwrap().to_string_lossy().to_string();
+
+        let mut problems = validate(assets.as_ref(), &source);
+        if let Some(other) = report.meshes_named(&id) {
+            problems.push(format!("{}: has the same name as {}", source, other));
+        }
+        if !problems.is_empty() {
+            report.problems.extend(problems);
+            continue;
+        }
+
+        let Some(source_hash) = loader.source_hash(&source, &Mat4::IDENTITY) else {
+            report
+                .problems
+                .push(format!("{}: could not be read", source));
+            continue;
+        };
+        let mut compiled: CompiledMesh = match loader.compile(&source, &Mat4::IDENTITY, source_hash)
+        {
+            Ok(compiled) => compiled,
+            Err(e) => {
+                report.problems.push(e);
+                continue;
+            }
+        };
+
+        // point the materials at the baked textures
+        let mut textures = Vec::new();
+        let mut broken = false;
+        for material in &mut compiled.materials {
+            for image in material.textures_mut() {
+                let baked = match report.manifest.textures.get(image.as_str()) {
+                    Some(baked) => baked.clone(),
+                    None => match bake_texture(assets.as_ref(), image, out_dir) {
+                        Ok(baked) => {
+                            report
+                                .manifest
+                                .textures
+                                .insert(image.clone(), baked.clone());
+                            baked
+                        }
+                        Err(e) => {
+                            report
+                                .problems
+                                .push(format!("{}: {}: {}", source, image, e));
+                            broken = true;
+                            continue;
+                        }
+                    },
+                };
+                *image = baked.clone();
+                textures.push(baked);
+            }
+        }
+        if broken {
+            continue;
+        }
+
+        let mesh = baked_path("meshes", &source, MESH_EXTENSION);
+        if let Err(e) = mesh_cache::write_compressed(&out_dir.join(&mesh), &compiled) {
+            report
+                .problems
+                .push(format!("{}: could not write {}: {}", source, mesh, e));
+            continue;
+        }
+
+        report.manifest.meshes.insert(
+            id,
+            ManifestMesh {
+                source,
+                mesh,
+                textures,
+            },
+        );
+    }
+
+    let manifest = out_dir.join(MANIFEST_NAME);
+    if let Err(e) = std::fs::create_dir_all(out_dir).and_then(|_| report.manifest.write(&manifest))
+    {
+        report.problems.push(format!(
+            "could not write {}: {}",
+            manifest.to_string_lossy(),
+            e
+        ));
+    }
+    report
+}
+
+/// decodes `image` and writes its mip chain into `out_dir`, returns where it went
+fn bake_texture(assets: &dyn AssetSource, image: &str, out_dir: &Path) -> Result<String, String> {
+    let bytes = assets.read(image).ok_or("could not be read")?;
+    let texture = CompiledTexture::from_image(&bytes)?;
+    let baked = baked_path("textures", image, TEXTURE_EXTENSION);
+    mesh_cache::write_compressed(&out_dir.join(&baked), &texture)
+        .map_err(|e| format!("could not write {}: {}", baked, e))?;
+    Ok(baked)
+}
+
+/// where the baked form of `source` goes, relative to the output directory
+fn baked_path(dir: &str, source: &str, extension: &str) -> String {
+    normalize(&mesh_cache::cache_path(Path::new(dir), source, extension))
+}
@@ -9,7 +9,7 @@ impl<'a> Builder<'a> {
         Builder {
             entries: Vec::new(),
             layout: None,
-            device: device,
+            device,
         }
     }
 
@@ -33,12 +33,26 @@ impl<'a> Builder<'a> {
         });
     }
 
+    pub fn add_texture_view(&mut self, view: &'a wgpu::TextureView) {
+        self.entries.push(wgpu::BindGroupEntry {
+            binding: self.entries.len() as u32,
+            resource: wgpu::BindingResource::TextureView(view),
+        });
+    }
+
+    pub fn add_sampler(&mut self, sampler: &'a wgpu::Sampler) {
+        self.entries.push(wgpu::BindGroupEntry {
+            binding: self.entries.len() as u32,
+            resource: wgpu::BindingResource::Sampler(sampler),
+        });
+    }
+
     pub fn add_buffer(&mut self, buffer: &'a wgpu::Buffer, offset: u64) {
         self.entries.push(wgpu::BindGroupEntry {
             binding: self.entries.len() as u32,
             resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                buffer: &buffer,
-                offset: offset,
+                buffer,
+                offset,
                 size: None,
             }),
         })
@@ -1,17 +1,22 @@
-/// wraps a wgpu::BindGroupLayoutEntry and a wgpu::device.
-pub struct Builder<'a> {
+/// collects wgpu::BindGroupLayoutEntry's, the device is only needed to `build` them,
+/// so layouts can be described (and checked against shaders) without one.
+#[derive(Default)]
+pub struct Builder {
     entries: Vec<wgpu::BindGroupLayoutEntry>,
-    device: &'a wgpu::Device,
 }
 
-impl<'a> Builder<'a> {
-    pub fn new(device: &'a wgpu::Device) -> Self {
+impl Builder {
+    pub fn new() -> Self {
         Builder {
             entries: Vec::new(),
-            device: device,
         }
     }
 
+    /// the entries added so far, binding `i` at index `i`
+    pub fn entries(&self) -> &[wgpu::BindGroupLayoutEntry] {
+        &self.entries
+    }
+
     fn reset(&mut self) {
         self.entries.clear();
     }
@@ -36,7 +41,44 @@ impl<'a> Builder<'a> {
         });
     }
 
-    pub fn add_vec4(&mut self) {
+    /// a texture sampled with the sampler of an earlier `add_texture`
+    pub fn add_texture_view(&mut self) {
+        self.entries.push(wgpu::BindGroupLayoutEntry {
+            binding: self.entries.len() as u32,
+            visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        });
+    }
+
+    /// a depth texture array and the comparison sampler its depths are tested with,
+    /// read by fragment shaders
+    pub fn add_shadow_map(&mut self) {
+        self.entries.push(wgpu::BindGroupLayoutEntry {
+            binding: self.entries.len() as u32,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2Array,
+                sample_type: wgpu::TextureSampleType::Depth,
+            },
+            count: None,
+        });
+
+        self.entries.push(wgpu::BindGroupLayoutEntry {
+            binding: self.entries.len() as u32,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+            count: None,
+        });
+    }
+
+    /// a uniform buffer read by vertex and fragment shaders
+    pub fn add_uniform(&mut self) {
         self.entries.push(wgpu::BindGroupLayoutEntry {
             binding: self.entries.len() as u32,
             visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
@@ -62,13 +104,81 @@ impl<'a> Builder<'a> {
         });
     }
 
-    pub fn build(&mut self, label: &str) -> wgpu::BindGroupLayout {
-        let layout = self
-            .device
-            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &self.entries,
-                label: Some(label),
-            });
+    /// a uniform buffer read by compute shaders
+    pub fn add_compute_uniform(&mut self) {
+        self.entries.push(wgpu::BindGroupLayoutEntry {
+            binding: self.entries.len() as u32,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        });
+    }
+
+    /// a read-only storage buffer read by fragment shaders
+    pub fn add_fragment_storage_buffer(&mut self) {
+        self.entries.push(wgpu::BindGroupLayoutEntry {
+            binding: self.entries.len() as u32,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        });
+    }
+
+    /// a storage buffer read (and written unless `read_only`) by compute shaders
+    pub fn add_storage_buffer(&mut self, read_only: bool) {
+        self.entries.push(wgpu::BindGroupLayoutEntry {
+            binding: self.entries.len() as u32,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        });
+    }
+
+    /// an unfilterable float texture read with `textureLoad` by compute shaders
+    pub fn add_compute_texture(&mut self) {
+        self.entries.push(wgpu::BindGroupLayoutEntry {
+            binding: self.entries.len() as u32,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        });
+    }
+
+    /// a write-only storage texture written by compute shaders
+    pub fn add_storage_texture(&mut self, format: wgpu::TextureFormat) {
+        self.entries.push(wgpu::BindGroupLayoutEntry {
+            binding: self.entries.len() as u32,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        });
+    }
+
+    pub fn build(&mut self, device: &wgpu::Device, label: &str) -> wgpu::BindGroupLayout {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &self.entries,
+            label: Some(label),
+        });
 
         self.reset();
 
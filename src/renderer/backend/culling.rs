@@ -0,0 +1,43 @@
+use glam::*;
+
+use super::definitions::BoundingSphere;
+
+/// the six clip planes of a view-projection matrix, normals pointing inwards
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// extracts the planes straight from the matrix rows (Gribb & Hartmann).
+    /// expects wgpu's 0..1 clip space depth
+    pub fn from_view_projection(view_proj: &Mat4) -> Self {
+        let r0 = view_proj.row(0);
+        let r1 = view_proj.row(1);
+        let r2 = view_proj.row(2);
+        let r3 = view_proj.row(3);
+
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2].map(|plane| {
+            // infinite projections have a degenerate (0, 0, 0, w) far plane that always passes
+            let length = plane.truncate().length();
+            if length > f32::EPSILON {
+                plane / length
+            } else {
+                plane
+            }
+        });
+
+        Frustum { planes }
+    }
+
+    /// (nx, ny, nz, d) with unit normals, except for a degenerate far plane
+    pub fn planes(&self) -> &[Vec4; 6] {
+        &self.planes
+    }
+
+    /// false only if the sphere is entirely outside one of the planes
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(sphere.center) + plane.w >= -sphere.radius)
+    }
+}
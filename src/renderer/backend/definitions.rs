@@ -1,41 +1,395 @@
+use crate::window::{InputSource, Key};
 use glam::*;
-use glfw::*;
+use serde::{Deserialize, Serialize};
 
-#[derive(Eq, Hash, PartialEq)]
+use super::materials::MaterialId;
+
+#[derive(Eq, Hash, PartialEq, Clone, Copy, Debug)]
 pub enum BindScope {
-    Texture,
-    Color,
+    /// group 0, a material's parameters and maps
+    Material,
+    /// group 1, what every draw of a frame shares
+    Frame,
+}
+
+/// how the alpha of a material, its base color's times its base color map's, covers what
+/// is behind it
+#[derive(Eq, Hash, PartialEq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum AlphaMode {
+    /// alpha is ignored
+    #[default]
+    Opaque,
+    /// cutout, fragments with alpha below `PbrMaterial::alpha_cutoff` are discarded and the
+    /// rest are opaque
+    Mask,
+    /// blended over what is behind, after every opaque submesh and without writing depth,
+    /// see `TransparencyMode`
+    Blend,
+}
+
+/// what a material needs from its pipeline. every combination is its own variant of the
+/// model shader, built the first time a material with it is loaded
+#[derive(Eq, Hash, PartialEq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct MaterialFeatures {
+    /// the base color is sampled from `Material::filename` and scaled by the base color
+    pub base_texture: bool,
+    /// the roughness is sampled from `Material::roughness_texture`
+    pub roughness_texture: bool,
+    /// the metallic factor is sampled from `Material::metallic_texture`
+    pub metallic_texture: bool,
+    /// the normal is bent by the tangent space map `Material::normal_texture`
+    pub normal_texture: bool,
+    /// the reflectance is scaled by `Material::specular_texture`
+    pub specular_texture: bool,
+    /// the emissive color is scaled by `Material::emissive_texture`
+    pub emissive_texture: bool,
+    /// back faces are drawn too, lit from their own side. `double_sided on` in an MTL
+    pub double_sided: bool,
+    /// whether alpha is ignored, cuts out or blends
+    pub alpha_mode: AlphaMode,
+}
+
+impl MaterialFeatures {
+    /// the `#define`s that turn the features on in the model shader
+    pub fn defines(&self) -> Vec<&'static str> {
+        let mut defines = Vec::new();
+        if self.base_texture {
+            defines.push("BASE_TEXTURE");
+        }
+        if self.roughness_texture {
+            defines.push("ROUGHNESS_TEXTURE");
+        }
+        if self.metallic_texture {
+            defines.push("METALLIC_TEXTURE");
+        }
+        if self.normal_texture {
+            defines.push("NORMAL_TEXTURE");
+        }
+        if self.specular_texture {
+            defines.push("SPECULAR_TEXTURE");
+        }
+        if self.emissive_texture {
+            defines.push("EMISSIVE_TEXTURE");
+        }
+        if self.double_sided {
+            defines.push("DOUBLE_SIDED");
+        }
+        match self.alpha_mode {
+            AlphaMode::Opaque => {}
+            AlphaMode::Mask => defines.push("ALPHA_MASK"),
+            AlphaMode::Blend => defines.push("ALPHA_BLEND"),
+        }
+        defines
+    }
+}
+
+/// metallic/roughness parameters of a material, laid out like `PbrMaterial` in the model
+/// shader. OBJ materials get them from their MTL statements, see `from_mtl`
+#[repr(C)]
+#[derive(
+    Clone, Copy, Debug, PartialEq, Serialize, Deserialize, bytemuck::Pod, bytemuck::Zeroable,
+)]
+pub struct PbrMaterial {
+    /// linear rgb, alpha is the opacity
+    pub base_color: Vec4,
+    /// light given off by the surface itself, added after lighting
+    pub emissive: Vec3,
+    /// 0 for dielectrics, 1 for metals, which reflect their base color and diffuse nothing
+    pub metallic: f32,
+    /// perceptual roughness, 0 is a mirror, 1 fully matte
+    pub roughness: f32,
+    /// how much light a dielectric reflects head on, 0.04 for most of them
+    pub reflectance: f32,
+    /// the alpha below which `AlphaMode::Mask` discards fragments
+    pub alpha_cutoff: f32,
+    /// rounds the struct up to the 16 byte alignment of `base_color`
+    #[serde(skip)]
+    pub padding: f32,
+}
+
+impl PbrMaterial {
+    pub fn new(base_color: Vec4) -> Self {
+        PbrMaterial {
+            base_color,
+            emissive: Vec3::ZERO,
+            metallic: 0.0,
+            roughness: 0.5,
+            reflectance: 0.04,
+            alpha_cutoff: 0.5,
+            padding: 0.0,
+        }
+    }
+
+    /// maps a Phong MTL material onto metallic/roughness, with Blender's conventions where MTL
+    /// has no answer (its exporter wrote our MTLs). statements missing from the MTL keep the
+    /// values of `self`:
+    ///
+    /// - `Kd` is the base color and `d` its alpha, `Tr` (1 - `d`) is read when `d` is missing.
+    ///   a `map_Kd` replaces `Kd`, which Blender fills with the viewport color
+    /// - `Pr` is the roughness, without it `Ns` (0-1000) stands in: 1 - sqrt(`Ns` / 1000)
+    /// - `Pm` is the metallic factor
+    /// - `Ks` is the reflectance as Blender's specular level, its average 0.5 is the usual 4%.
+    ///   without `Ks`, `Ni` gives it through the Fresnel equations: ((`Ni` - 1) / (`Ni` + 1))²
+    /// - `illum` 0 and 1 have no highlights, they reflect nothing
+    /// - `Ke` is the emissive color. a `map_Ke` without `Ke` emits the map as it is
+    ///
+    /// the maps are picked up by the loader, `map_Pr`, `map_Pm`, `map_Ks` and `map_Ke` multiply
+    /// the factors above, `map_Bump` (or `bump`, `norm`) is a tangent space normal map.
+    /// the loader blends materials with an alpha below 1 and cuts out those with a `map_d`,
+    /// their alpha comes from the base color map, as Blender writes `map_d` when the image's
+    /// alpha is used.
+    /// `Ka`, `map_Ka` and `Tf` have no counterpart and are ignored
+    pub fn from_mtl(mut self, mtl: &tobj::Material) -> Self {
+        let param = |name: &str| -> Option<f32> { mtl.unknown_param.get(name)?.parse().ok() };
+        let color = |name: &str| -> Option<Vec3> {
+            let values: Vec<f32> = mtl
+                .unknown_param
+                .get(name)?
+                .split_whitespace()
+                .map(|v| v.parse().ok())
+                .collect::<Option<_>>()?;
+            match values[..] {
+                [r, g, b] => Some(Vec3::new(r, g, b)),
+                [gray] => Some(Vec3::splat(gray)),
+                _ => None,
+            }
+        };
+
+        if let Some(diffuse) = mtl.diffuse {
+            self.base_color = Vec3::from(diffuse).extend(self.base_color.w);
+        }
+        if mtl.diffuse_texture.is_some() {
+            self.base_color = Vec3::ONE.extend(self.base_color.w);
+        }
+        if let Some(alpha) = mtl.dissolve.or(param("Tr").map(|tr| 1.0 - tr)) {
+            self.base_color.w = alpha;
+        }
+
+        if let Some(roughness) = param("Pr") {
+            self.roughness = roughness;
+        } else if let Some(shininess) = mtl.shininess {
+            self.roughness = 1.0 - (shininess.clamp(0.0, 1000.0) / 1000.0).sqrt();
+        }
+        if let Some(metallic) = param("Pm") {
+            self.metallic = metallic;
+        }
+
+        if let Some(specular) = mtl.specular {
+            self.reflectance = 0.08 * Vec3::from(specular).element_sum() / 3.0;
+        } else if let Some(ior) = mtl.optical_density {
+            self.reflectance = ((ior - 1.0) / (ior + 1.0)).powi(2);
+        }
+        if matches!(mtl.illumination_model, Some(0 | 1)) {
+            self.reflectance = 0.0;
+        }
+
+        if let Some(emissive) = color("Ke") {
+            self.emissive = emissive;
+        } else if mtl.unknown_param.contains_key("map_Ke") {
+            self.emissive = Vec3::ONE;
+        }
+
+        self
+    }
 }
 
-#[derive(Eq, Hash, PartialEq, Clone, Copy)]
-pub enum PipelineType {
-    TexturedModel, // if the model has a texture
-    ColoredModel,  // fallback
+/// how blended materials are composited over the opaque ones
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TransparencyMode {
+    /// every instance of a blended submesh is drawn on its own, back to front by the distance
+    /// of its bounds from the camera. exact unless blended surfaces intersect or interleave
+    Sorted,
+    /// weighted blended order independent transparency (McGuire and Bavoil 2013): blended
+    /// surfaces are summed into two extra targets in any order, weighted by their depth, and
+    /// composited over the opaque ones in one pass. no sorting, but only an approximation
+    /// of the order where several of them overlap
+    WeightedBlended,
 }
 
+/// what every draw of a frame reads, laid out like `Frame` in the shaders
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FrameUniforms {
+    pub view: Mat4,
+    /// takes clip space back to view space, to find the bounds of the light clusters
+    pub inverse_projection: Mat4,
+    /// w unused
+    pub camera_position: Vec4,
+    /// `lights::CLUSTER_COUNT`, then the number of lights
+    pub cluster_count: UVec4,
+    /// the pixel size of a cluster's screen tile, then the view depths the depth slices span
+    pub cluster_extent: Vec4,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Material {
-    pub pipeline_type: PipelineType,
-    pub color: Option<Vec4>,
+    pub features: MaterialFeatures,
+    pub pbr: PbrMaterial,
+    /// the base color map
     pub filename: Option<String>,
+    pub roughness_texture: Option<String>,
+    pub metallic_texture: Option<String>,
+    /// tangent space, +y pointing along increasing v like OpenGL and Blender
+    pub normal_texture: Option<String>,
+    pub specular_texture: Option<String>,
+    pub emissive_texture: Option<String>,
+    #[serde(skip)]
     pub bind_group: Option<wgpu::BindGroup>,
 }
 
 impl Material {
     pub fn new() -> Self {
         Material {
-            pipeline_type: PipelineType::ColoredModel,
-            color: Some(Vec4::new(0.5, 0.0, 0.5, 1.0)),
+            features: MaterialFeatures::default(),
+            pbr: PbrMaterial::new(Vec4::new(0.5, 0.0, 0.5, 1.0)),
             filename: None,
+            roughness_texture: None,
+            metallic_texture: None,
+            normal_texture: None,
+            specular_texture: None,
+            emissive_texture: None,
             bind_group: None,
         }
     }
+
+    /// the maps the material samples, to rewrite where they are read from
+    pub fn textures_mut(&mut self) -> impl Iterator<Item = &mut String> {
+        [
+            &mut self.filename,
+            &mut self.roughness_texture,
+            &mut self.metallic_texture,
+            &mut self.normal_texture,
+            &mut self.specular_texture,
+            &mut self.emissive_texture,
+        ]
+        .into_iter()
+        .flatten()
+    }
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// axis aligned bounding box in model space
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// contains nothing, the identity for `union`
+    pub const EMPTY: Aabb = Aabb {
+        min: Vec3::splat(f32::INFINITY),
+        max: Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    pub fn from_points(points: &[Vec3]) -> Self {
+        points.iter().fold(Self::EMPTY, |aabb, p| Aabb {
+            min: aabb.min.min(*p),
+            max: aabb.max.max(*p),
+        })
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+}
+
+/// bounding sphere, cheaper than a box to test against the frustum once per instance
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// the sphere after `transform`, grown by the largest axis scale so it stays conservative
+    pub fn transformed(&self, transform: &Mat4) -> Self {
+        let max_scale = transform
+            .x_axis
+            .truncate()
+            .length_squared()
+            .max(transform.y_axis.truncate().length_squared())
+            .max(transform.z_axis.truncate().length_squared())
+            .sqrt();
+
+        BoundingSphere {
+            center: transform.transform_point3(self.center),
+            radius: self.radius * max_scale,
+        }
+    }
+}
+
+/// model space bounds of a mesh, filled in by `ObjLoader`
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
+}
+
+impl Bounds {
+    /// sphere centered on the box, tighter than the box's circumscribed sphere
+    pub fn from_points(points: &[Vec3]) -> Self {
+        let aabb = Aabb::from_points(points);
+        if aabb.is_empty() {
+            return Bounds {
+                aabb,
+                sphere: BoundingSphere {
+                    center: Vec3::ZERO,
+                    radius: 0.0,
+                },
+            };
+        }
+
+        let center = aabb.center();
+        let radius = points
+            .iter()
+            .map(|p| p.distance_squared(center))
+            .fold(0.0, f32::max)
+            .sqrt();
+
+        Bounds {
+            aabb,
+            sphere: BoundingSphere { center, radius },
+        }
+    }
+
+    /// bounds containing both, the sphere is refit around the merged box
+    pub fn union(&self, other: &Bounds) -> Self {
+        let aabb = self.aabb.union(&other.aabb);
+        let center = aabb.center();
+        let radius = (center.distance(self.sphere.center) + self.sphere.radius)
+            .max(center.distance(other.sphere.center) + other.sphere.radius);
+
+        Bounds {
+            aabb,
+            sphere: BoundingSphere { center, radius },
+        }
+    }
 }
 
 #[derive(Clone, Copy)]
 pub struct Submesh {
-    pub first_index: i32,
+    /// offset into the model's indices, which refer to the whole model's vertices
+    pub first_index: u32,
     pub index_count: u32,
-    pub material_id: usize,
+    pub material_id: MaterialId,
+    pub bounds: Bounds,
 }
 
 /// 3d models
@@ -43,23 +397,61 @@ pub struct Model {
     pub buffer: wgpu::Buffer,
     ///location where the Element Buffer Object (index buffer) starts in `buffer`
     pub ebo_offset: u64,
+    /// 16 bit when the model has few enough vertices
+    pub index_format: wgpu::IndexFormat,
     pub submeshes: Vec<Submesh>,
+    /// bounds of all submeshes together
+    pub bounds: Bounds,
+}
+
+/// how instances outside the view get rejected before drawing
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CullingMode {
+    /// draw every instance
+    Off,
+    /// test each instance's bounding sphere against the view frustum on the CPU
+    /// and upload only the visible ones
+    Cpu,
+    /// upload every instance and let a compute pass compact the visible ones and
+    /// write the instance counts of indirect draws. `frame_stats` can't see the result,
+    /// it reports every instance as visible, `read_gpu_cull_stats` has the real counts.
+    /// can also reject instances hidden in the previous frame, see `set_occlusion_culling`
+    Gpu,
+}
+
+/// counters from the last `render` call
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStats {
+    /// instances that were drawn
+    pub visible_instances: u32,
+    /// instances skipped because they were outside the view frustum
+    pub frustum_culled_instances: u32,
+    /// instances skipped because they were hidden behind the previous frame's depth. always 0
+    /// outside `CullingMode::Gpu`, the only mode occlusion culling runs in
+    pub occlusion_culled_instances: u32,
+    /// light clusters that more than `lights::MAX_LIGHTS_PER_CLUSTER` lights reached, the
+    /// others are left unshaded there. counted on the GPU, so this is from a frame or two ago
+    pub overflowing_light_clusters: u32,
 }
 
-/// describes a vertex with its position, texture coordinates, and normal
+/// describes a vertex with its position, texture coordinates, normal and tangent
+#[derive(Clone, Copy, Serialize, Deserialize)]
 #[repr(C)] // C-style data layout
 pub struct VertexData {
     pub position: Vec3,
     pub tex_coord: Vec2,
     pub normal: Vec3,
+    /// MikkTSpace tangent, w is the sign of the bitangent: `w * normal.cross(tangent.xyz)`
+    pub tangent: Vec4,
 }
 
 impl VertexData {
     pub fn get_layout() -> wgpu::VertexBufferLayout<'static> {
-        const ATTRIBUTES: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+        const ATTRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
             0 => Float32x3,
             1 => Float32x2,
-            2 => Float32x3];
+            2 => Float32x3,
+            3 => Float32x4];
 
         wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<VertexData>() as wgpu::BufferAddress,
@@ -79,15 +471,112 @@ pub struct InstanceData {
 }
 
 impl InstanceData {
+    /// the model matrix column by column, at locations 4-7 after `VertexData`'s
+    pub fn get_layout() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+            4 => Float32x4,
+            5 => Float32x4,
+            6 => Float32x4,
+            7 => Float32x4];
+
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<InstanceData>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+
     pub fn from_pos_rot(pos: glam::Vec3, rot: glam::Quat, scale: f32) -> Self {
         let model = glam::Mat4::from_scale_rotation_translation(glam::Vec3::splat(scale), rot, pos);
 
+        Self::from(model)
+    }
+
+    pub fn transform(&self) -> Mat4 {
+        Mat4::from_cols_array_2d(&self.model)
+    }
+}
+
+impl From<Mat4> for InstanceData {
+    fn from(model: Mat4) -> Self {
         Self {
             model: model.to_cols_array_2d(),
         }
     }
 }
 
+/// how the camera maps view space to clip space
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// `fov_y` is the vertical field of view in degrees
+    Perspective { fov_y: f32, z_near: f32, z_far: f32 },
+    /// perspective without a far plane, depth goes from 1 at `z_near` to 0 at infinity.
+    /// floats are densest near 0, so this keeps precision over huge view distances
+    ReverseZInfinite { fov_y: f32, z_near: f32 },
+    /// parallel projection showing `height` world units vertically
+    Orthographic {
+        height: f32,
+        z_near: f32,
+        z_far: f32,
+    },
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection::Perspective {
+            fov_y: 80.0,
+            z_near: 0.5,
+            z_far: 10000.0,
+        }
+    }
+}
+
+impl Projection {
+    /// `aspect` is width / height of the render target
+    pub fn matrix(&self, aspect: f32) -> Mat4 {
+        match *self {
+            Projection::Perspective {
+                fov_y,
+                z_near,
+                z_far,
+            } => Mat4::perspective_rh(fov_y.to_radians(), aspect, z_near, z_far),
+            Projection::ReverseZInfinite { fov_y, z_near } => {
+                Mat4::perspective_infinite_reverse_rh(fov_y.to_radians(), aspect, z_near)
+            }
+            Projection::Orthographic {
+                height,
+                z_near,
+                z_far,
+            } => {
+                let (half_width, half_height) = (height * aspect * 0.5, height * 0.5);
+                Mat4::orthographic_rh(
+                    -half_width,
+                    half_width,
+                    -half_height,
+                    half_height,
+                    z_near,
+                    z_far,
+                )
+            }
+        }
+    }
+
+    /// the nearest and farthest view depth that is drawn, infinite projections end at
+    /// `infinite_far` instead
+    pub fn depth_range(&self, infinite_far: f32) -> (f32, f32) {
+        match *self {
+            Projection::Perspective { z_near, z_far, .. }
+            | Projection::Orthographic { z_near, z_far, .. } => (z_near, z_far),
+            Projection::ReverseZInfinite { z_near, .. } => (z_near, infinite_far),
+        }
+    }
+
+    /// true if nearer surfaces have larger depth values
+    pub fn is_reverse_z(&self) -> bool {
+        matches!(self, Projection::ReverseZInfinite { .. })
+    }
+}
+
 pub struct Camera {
     pub position: Vec3,
     pub forwards: Vec3,
@@ -95,6 +584,13 @@ pub struct Camera {
     pub up: Vec3,
     pub yaw: f32,
     pub pitch: f32,
+    pub projection: Projection,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Camera {
@@ -112,39 +608,61 @@ impl Camera {
             up,
             yaw,
             pitch,
+            projection: Projection::default(),
         }
     }
 
-    pub fn update(&mut self, dt: f32, window: &mut glfw::Window) {
-        if !window.is_focused() {
+    pub fn view(&self) -> Mat4 {
+        // Vectors for view matrix columns
+        let c0 = Vec4::new(self.right.x, self.up.x, -self.forwards.x, 0.0);
+        let c1 = Vec4::new(self.right.y, self.up.y, -self.forwards.y, 0.0);
+        let c2 = Vec4::new(self.right.z, self.up.z, -self.forwards.z, 0.0);
+        let a: f32 = -self.right.dot(self.position);
+        let b: f32 = -self.up.dot(self.position);
+        let c: f32 = self.forwards.dot(self.position);
+        let c3 = Vec4::new(a, b, c, 1.0);
+
+        Mat4::from_cols(c0, c1, c2, c3)
+    }
+
+    /// `aspect` is width / height of the render target
+    pub fn view_projection(&self, aspect: f32) -> Mat4 {
+        self.projection.matrix(aspect) * self.view()
+    }
+
+    /// moves and turns the camera from `input`, moving the mouse across half of a surface of
+    /// `surface_size` pixels turns it by 40 degrees
+    pub fn update(&mut self, dt: f32, surface_size: (i32, i32), input: &mut impl InputSource) {
+        if !input.is_focused() {
             // TODO: make character trait and make this check in the "super" call
             return;
         }
 
         let speed = 0.5 * dt;
 
-        let mouse_pos = window.get_cursor_pos();
-        window.set_cursor_pos(400.0, 300.0);
-        let dx = (-40.0 * (mouse_pos.0 - 400.0) / 400.0) as f32;
-        let dy = (-40.0 * (mouse_pos.1 - 300.0) / 300.0) as f32;
+        let mouse_delta = input.take_mouse_delta();
+        let half_width = (surface_size.0 as f64 / 2.0).max(1.0);
+        let half_height = (surface_size.1 as f64 / 2.0).max(1.0);
+        let dx = (-40.0 * mouse_delta.0 / half_width) as f32;
+        let dy = (-40.0 * mouse_delta.1 / half_height) as f32;
         self.look(dx, dy);
 
-        if window.get_key(Key::W) == Action::Press {
+        if input.is_key_down(Key::W) {
             self.position += self.forwards * speed;
         }
-        if window.get_key(Key::S) == Action::Press {
+        if input.is_key_down(Key::S) {
             self.position -= self.forwards * speed;
         }
-        if window.get_key(Key::A) == Action::Press {
+        if input.is_key_down(Key::A) {
             self.position -= self.right * speed;
         }
-        if window.get_key(Key::D) == Action::Press {
+        if input.is_key_down(Key::D) {
             self.position += self.right * speed;
         }
-        if window.get_key(Key::Space) == Action::Press {
+        if input.is_key_down(Key::Space) {
             self.position += self.up * speed;
         }
-        if window.get_key(Key::LeftShift) == Action::Press {
+        if input.is_key_down(Key::LeftShift) {
             self.position -= self.up * speed;
         }
     }
@@ -154,13 +672,7 @@ impl Camera {
         if self.yaw < 0.0 {
             self.yaw += 360.0;
         }
-        self.pitch = self.pitch + d_pitch;
-        if self.pitch > 89.0 {
-            self.pitch = 89.0;
-        }
-        if self.pitch < -89.0 {
-            self.pitch = -89.0;
-        }
+        self.pitch = (self.pitch + d_pitch).clamp(-89.0, 89.0);
 
         let c = self.yaw.to_radians().cos();
         let s = self.yaw.to_radians().sin();
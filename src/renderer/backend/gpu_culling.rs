@@ -0,0 +1,351 @@
+use glam::*;
+
+use super::assets::AssetSource;
+use super::bind_group::Builder as BindGroupBuilder;
+use super::bind_group_layout::Builder as BindGroupLayoutBuilder;
+use super::culling::Frustum;
+use super::definitions::{BoundingSphere, FrameStats, InstanceData, Model};
+use super::hi_z::{DepthPyramid, PyramidBuilder};
+use super::pipeline::load_shader_module;
+use super::texture::Texture;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// per-frame inputs of `cull_instances.wgsl`
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullParams {
+    /// the view-projection the depth pyramid was rendered with
+    occlusion_view_proj: [[f32; 4]; 4],
+    planes: [[f32; 4]; 6],
+    sphere: [f32; 4],
+    instance_count: u32,
+    draw_count: u32,
+    /// 1 to test against the depth pyramid
+    occlusion: u32,
+    /// 1 if the projections use reverse-Z, the pyramid itself is always stored near = 0
+    reverse_z: u32,
+}
+
+/// the culling shader, relative to the shader source
+pub const SHADER: &str = "cull_instances.wgsl";
+
+/// visible, frustum culled and occluded instance counters, summed over all models
+const STATS_SIZE: u64 = 3 * std::mem::size_of::<u32>() as u64;
+
+/// the buffers a model is drawn from when culling on the GPU
+pub struct CullTargets {
+    /// visible instances, packed at the front
+    pub visible: wgpu::Buffer,
+    /// one `DrawIndexedIndirectArgs` per submesh, in `Model` then submesh order
+    pub draws: wgpu::Buffer,
+    params: wgpu::Buffer,
+    /// built by the first `cull`, see `CullBindGroup`
+    bind_group: Option<CullBindGroup>,
+}
+
+/// the bind group of one model's `CullTargets`, along with the buffers it was built from
+/// that live outside of them. it is only rebuilt once one of those gets replaced: the
+/// instance buffer when it grows, the pyramid on a resize and the stats with the culler
+struct CullBindGroup {
+    instances: wgpu::Buffer,
+    pyramid: wgpu::TextureView,
+    stats: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl CullTargets {
+    pub fn new(device: &wgpu::Device, key: &str, capacity: u64, draw_count: u64) -> Self {
+        let visible = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("Visible Instances: {}", key)),
+            size: capacity.max(1) * std::mem::size_of::<InstanceData>() as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let draws = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("Indirect Draws: {}", key)),
+            size: draw_count.max(1)
+                * std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>() as u64,
+            usage: wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("Cull Params: {}", key)),
+            size: std::mem::size_of::<CullParams>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        CullTargets {
+            visible,
+            draws,
+            params,
+            bind_group: None,
+        }
+    }
+
+    /// true if these buffers can't hold `capacity` instances or `draw_count` draws
+    pub fn too_small(&self, capacity: u64, draw_count: u64) -> bool {
+        self.visible.size() < capacity * std::mem::size_of::<InstanceData>() as u64
+            || self.draws.size()
+                < draw_count * std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>() as u64
+    }
+}
+
+/// compute pipelines that cull instances against the frustum, and optionally the previous
+/// frame's depth, and fill indirect draws
+pub struct GpuCuller {
+    layout: wgpu::BindGroupLayout,
+    cull_pipeline: wgpu::ComputePipeline,
+    write_draws_pipeline: wgpu::ComputePipeline,
+    pyramid_builder: PyramidBuilder,
+    pyramid: DepthPyramid,
+    stats: wgpu::Buffer,
+}
+
+impl GpuCuller {
+    /// group 0 of `SHADER`: params, instances, visible instances, draws, Hi-Z and stats
+    pub fn bind_group_layout() -> BindGroupLayoutBuilder {
+        let mut builder = BindGroupLayoutBuilder::new();
+        builder.add_compute_uniform();
+        builder.add_storage_buffer(true);
+        builder.add_storage_buffer(false);
+        builder.add_storage_buffer(false);
+        builder.add_compute_texture();
+        builder.add_storage_buffer(false);
+        builder
+    }
+
+    /// `width` and `height` are the size of the depth buffer
+    pub fn new(device: &wgpu::Device, shaders: &dyn AssetSource, width: u32, height: u32) -> Self {
+        let layout = Self::bind_group_layout().build(device, "Cull Bind Group Layout");
+
+        let stats = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cull Stats"),
+            size: STATS_SIZE,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let shader_module = load_shader_module(device, shaders, SHADER, &[]);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Cull Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let build = |entry_point: &str, label: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader_module,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+
+        GpuCuller {
+            cull_pipeline: build("cull", "Cull Instances Pipeline"),
+            write_draws_pipeline: build("write_draws", "Write Draws Pipeline"),
+            layout,
+            pyramid_builder: PyramidBuilder::new(device, shaders),
+            pyramid: DepthPyramid::new(device, width, height),
+            stats,
+        }
+    }
+
+    /// the depth pyramid has to match the depth buffer
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.pyramid = DepthPyramid::new(device, width, height);
+    }
+
+    /// resets the stats and, if `depth` is given, records the pyramid build from it.
+    /// call before any `cull` of the frame
+    pub fn begin_frame(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        staging_belt: &mut wgpu::util::StagingBelt,
+        depth: Option<&Texture>,
+        reverse_z: bool,
+    ) {
+        staging_belt
+            .write_buffer(
+                encoder,
+                &self.stats,
+                0,
+                wgpu::BufferSize::new(STATS_SIZE).unwrap(),
+                device,
+            )
+            .fill(0);
+
+        if let Some(depth) = depth {
+            self.pyramid_builder
+                .build(device, encoder, depth, reverse_z, &self.pyramid);
+        }
+    }
+
+    /// waits for the GPU and reads the counters of the last culled frame
+    pub fn read_stats(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> FrameStats {
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cull Stats Readback"),
+            size: STATS_SIZE,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Cull Stats Readback Encoder"),
+        });
+        encoder.copy_buffer_to_buffer(&self.stats, 0, &readback_buffer, 0, STATS_SIZE);
+        let submission = queue.submit(Some(encoder.finish()));
+
+        let slice = readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        let _ = device.poll(wgpu::PollType::Wait {
+            submission_index: Some(submission),
+            timeout: None,
+        });
+
+        let stats = {
+            let data = slice.get_mapped_range();
+            let counters: &[u32] = bytemuck::cast_slice(&data);
+            FrameStats {
+                visible_instances: counters[0],
+                frustum_culled_instances: counters[1],
+                occlusion_culled_instances: counters[2],
+                ..Default::default()
+            }
+        };
+        readback_buffer.unmap();
+
+        stats
+    }
+
+    /// stages this frame's params and zeroed draws, then records the compute pass that
+    /// culls `instance_count` instances from `instances` into `targets`.
+    /// `occlusion_view_proj` is what the pyramid's depth was rendered with, `None` skips the
+    /// occlusion test
+    #[allow(clippy::too_many_arguments)]
+    pub fn cull(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        staging_belt: &mut wgpu::util::StagingBelt,
+        frustum: &Frustum,
+        sphere: &BoundingSphere,
+        model_list: &[Model],
+        instances: &wgpu::Buffer,
+        instance_count: u32,
+        targets: &mut CullTargets,
+        occlusion_view_proj: Option<&Mat4>,
+        reverse_z: bool,
+    ) {
+        // indices are counted from the start of the model's buffer, see `RendererState::render`
+        let draws: Vec<wgpu::util::DrawIndexedIndirectArgs> = model_list
+            .iter()
+            .flat_map(|model| {
+                let index_size = match model.index_format {
+                    wgpu::IndexFormat::Uint16 => 2,
+                    wgpu::IndexFormat::Uint32 => 4,
+                };
+                let ebo_start = (model.ebo_offset / index_size) as u32;
+                model
+                    .submeshes
+                    .iter()
+                    .map(move |submesh| wgpu::util::DrawIndexedIndirectArgs {
+                        index_count: submesh.index_count,
+                        instance_count: 0,
+                        first_index: ebo_start + submesh.first_index,
+                        base_vertex: 0,
+                        first_instance: 0,
+                    })
+            })
+            .collect();
+        if draws.is_empty() {
+            return;
+        }
+
+        let params = CullParams {
+            occlusion_view_proj: occlusion_view_proj
+                .unwrap_or(&Mat4::IDENTITY)
+                .to_cols_array_2d(),
+            planes: frustum.planes().map(|plane| plane.to_array()),
+            sphere: sphere.center.extend(sphere.radius).to_array(),
+            instance_count,
+            draw_count: draws.len() as u32,
+            occlusion: occlusion_view_proj.is_some() as u32,
+            reverse_z: reverse_z as u32,
+        };
+
+        let draw_bytes: Vec<u8> = draws.iter().flat_map(|d| d.as_bytes()).copied().collect();
+        for (buffer, data) in [
+            (&targets.params, bytemuck::bytes_of(&params)),
+            (&targets.draws, draw_bytes.as_slice()),
+        ] {
+            staging_belt
+                .write_buffer(
+                    encoder,
+                    buffer,
+                    0,
+                    wgpu::BufferSize::new(data.len() as u64).unwrap(),
+                    device,
+                )
+                .copy_from_slice(data);
+        }
+
+        let bind_group = self.bind_group(device, instances, targets);
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Cull Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_bind_group(0, bind_group, &[]);
+
+        pass.set_pipeline(&self.cull_pipeline);
+        pass.dispatch_workgroups(instance_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+
+        pass.set_pipeline(&self.write_draws_pipeline);
+        pass.dispatch_workgroups((draws.len() as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    /// the bind group of `targets`, rebuilt if it was made for other buffers
+    fn bind_group<'a>(
+        &self,
+        device: &wgpu::Device,
+        instances: &wgpu::Buffer,
+        targets: &'a mut CullTargets,
+    ) -> &'a wgpu::BindGroup {
+        let outdated = targets.bind_group.as_ref().is_none_or(|cached| {
+            cached.instances != *instances
+                || cached.pyramid != self.pyramid.view
+                || cached.stats != self.stats
+        });
+        if outdated {
+            let mut builder = BindGroupBuilder::new(device);
+            builder.set_layout(&self.layout);
+            builder.add_buffer(&targets.params, 0);
+            builder.add_buffer(instances, 0);
+            builder.add_buffer(&targets.visible, 0);
+            builder.add_buffer(&targets.draws, 0);
+            builder.add_texture_view(&self.pyramid.view);
+            builder.add_buffer(&self.stats, 0);
+            targets.bind_group = Some(CullBindGroup {
+                instances: instances.clone(),
+                pyramid: self.pyramid.view.clone(),
+                stats: self.stats.clone(),
+                bind_group: builder.build("Cull Bind Group"),
+            });
+        }
+
+        &targets.bind_group.as_ref().unwrap().bind_group
+    }
+}
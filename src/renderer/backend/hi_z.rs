@@ -0,0 +1,181 @@
+use super::assets::AssetSource;
+use super::bind_group::Builder as BindGroupBuilder;
+use super::bind_group_layout::Builder as BindGroupLayoutBuilder;
+use super::pipeline::load_shader_module;
+use super::texture::Texture;
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+const WORKGROUP_SIZE: u32 = 8;
+/// the pyramid shader, relative to the shader source
+pub const SHADER: &str = "hi_z.wgsl";
+
+/// mip chain of the depth buffer where each texel keeps the farthest depth below it,
+/// so a single texel tells if anything behind it can be visible
+pub struct DepthPyramid {
+    texture: wgpu::Texture,
+    /// every level, read by the cull shader
+    pub view: wgpu::TextureView,
+    /// one single-mip texture per level, written while building and then copied into `texture`.
+    /// the GL backend emulates mip views by clamping the texture's base/max level, which drops
+    /// storage writes to any other level, so levels can't be written through views
+    levels: Vec<Texture>,
+}
+
+impl DepthPyramid {
+    /// level 0 matches the depth buffer size, down to 1x1
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let (width, height) = (width.max(1), height.max(1));
+        let level_count = 32 - width.max(height).leading_zeros();
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Depth Pyramid"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let levels = (0..level_count)
+            .map(|level| {
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("Depth Pyramid Level"),
+                    size: texture
+                        .size()
+                        .mip_level_size(level, wgpu::TextureDimension::D2),
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: FORMAT,
+                    usage: wgpu::TextureUsages::STORAGE_BINDING
+                        | wgpu::TextureUsages::TEXTURE_BINDING
+                        | wgpu::TextureUsages::COPY_SRC,
+                    view_formats: &[],
+                });
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                Texture { texture, view }
+            })
+            .collect();
+
+        DepthPyramid {
+            texture,
+            view,
+            levels,
+        }
+    }
+}
+
+/// compute pipelines that fill a `DepthPyramid` from a depth buffer
+pub struct PyramidBuilder {
+    layout: wgpu::BindGroupLayout,
+    copy_pipeline: wgpu::ComputePipeline,
+    copy_reversed_pipeline: wgpu::ComputePipeline,
+    downsample_pipeline: wgpu::ComputePipeline,
+}
+
+impl PyramidBuilder {
+    /// group 0 of `SHADER`: the level written and the texture it is read from
+    pub fn bind_group_layout() -> BindGroupLayoutBuilder {
+        let mut builder = BindGroupLayoutBuilder::new();
+        builder.add_storage_texture(FORMAT);
+        builder.add_compute_texture();
+        builder
+    }
+
+    pub fn new(device: &wgpu::Device, shaders: &dyn AssetSource) -> Self {
+        let layout = Self::bind_group_layout().build(device, "Hi-Z Bind Group Layout");
+
+        let shader_module = load_shader_module(device, shaders, SHADER, &[]);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Hi-Z Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let build = |entry_point: &str, label: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader_module,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+
+        PyramidBuilder {
+            copy_pipeline: build("copy_depth", "Hi-Z Copy Pipeline"),
+            copy_reversed_pipeline: build("copy_depth_reversed", "Hi-Z Reversed Copy Pipeline"),
+            downsample_pipeline: build("downsample", "Hi-Z Downsample Pipeline"),
+            layout,
+        }
+    }
+
+    /// records the passes that copy `depth` into level 0 and reduce it down the chain,
+    /// then the copies of every level into the pyramid's mips.
+    /// reverse-Z depth gets flipped so the pyramid always has near = 0
+    pub fn build(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        depth: &Texture,
+        reverse_z: bool,
+        pyramid: &DepthPyramid,
+    ) {
+        let mut bind_groups = Vec::with_capacity(pyramid.levels.len());
+
+        let mut builder = BindGroupBuilder::new(device);
+        builder.set_layout(&self.layout);
+        builder.add_texture_view(&pyramid.levels[0].view);
+        builder.add_texture_view(&depth.view);
+        bind_groups.push(builder.build("Hi-Z Copy Bind Group"));
+
+        for levels in pyramid.levels.windows(2) {
+            builder.add_texture_view(&levels[1].view);
+            builder.add_texture_view(&levels[0].view);
+            bind_groups.push(builder.build("Hi-Z Downsample Bind Group"));
+        }
+
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Hi-Z Pass"),
+                timestamp_writes: None,
+            });
+
+            for (i, (level, bind_group)) in pyramid.levels.iter().zip(&bind_groups).enumerate() {
+                let pipeline = match (i, reverse_z) {
+                    (0, false) => &self.copy_pipeline,
+                    (0, true) => &self.copy_reversed_pipeline,
+                    _ => &self.downsample_pipeline,
+                };
+
+                pass.set_pipeline(pipeline);
+                pass.set_bind_group(0, bind_group, &[]);
+                pass.dispatch_workgroups(
+                    level.texture.width().div_ceil(WORKGROUP_SIZE),
+                    level.texture.height().div_ceil(WORKGROUP_SIZE),
+                    1,
+                );
+            }
+        }
+
+        for (mip_level, level) in pyramid.levels.iter().enumerate() {
+            encoder.copy_texture_to_texture(
+                level.texture.as_image_copy(),
+                wgpu::TexelCopyTextureInfo {
+                    texture: &pyramid.texture,
+                    mip_level: mip_level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                level.texture.size(),
+            );
+        }
+    }
+}
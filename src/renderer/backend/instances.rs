@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::definitions::InstanceData;
+use super::slot_map::{SlotKey, SlotMap};
+
+/// past this many disjoint ranges they get merged into one, a few redundant bytes
+/// are cheaper than hundreds of tiny `write_buffer` calls
+const MAX_DIRTY_RANGES: usize = 16;
+
+/// sorted, non-overlapping index ranges that changed since the last upload
+#[derive(Default)]
+pub struct DirtyRanges {
+    ranges: Vec<Range<u32>>,
+}
+
+impl DirtyRanges {
+    pub fn insert(&mut self, range: Range<u32>) {
+        // first range that ends at or after the new one starts, touching ranges get merged too
+        let first = self.ranges.partition_point(|r| r.end < range.start);
+        let mut merged = range;
+        let mut last = first;
+        while last < self.ranges.len() && self.ranges[last].start <= merged.end {
+            merged.start = merged.start.min(self.ranges[last].start);
+            merged.end = merged.end.max(self.ranges[last].end);
+            last += 1;
+        }
+        self.ranges.splice(first..last, [merged]);
+
+        if self.ranges.len() > MAX_DIRTY_RANGES {
+            let start = self.ranges[0].start;
+            let end = self.ranges[self.ranges.len() - 1].end;
+            self.ranges.clear();
+            self.ranges.push(start..end);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Range<u32>> {
+        self.ranges.iter()
+    }
+}
+
+/// a stable reference to a spawned instance.
+/// stays valid until the instance is despawned, see `SlotKey`
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct InstanceHandle {
+    /// batches are never removed, so their index is stable
+    batch: u32,
+    key: SlotKey,
+}
+
+/// every instance of one model, packed so it can be uploaded to the GPU without holes
+pub struct InstanceBatch {
+    id: String,
+    data: SlotMap<InstanceData>,
+    /// entries of `data` that the GPU copy doesn't have yet
+    dirty: DirtyRanges,
+}
+
+impl InstanceBatch {
+    fn new(id: &str) -> Self {
+        InstanceBatch {
+            id: id.to_string(),
+            data: SlotMap::new(),
+            dirty: DirtyRanges::default(),
+        }
+    }
+
+    /// the model id this batch belongs to
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn instances(&self) -> &[InstanceData] {
+        self.data.values()
+    }
+
+    /// changed index ranges, clamped to the current length since swap-removes can leave
+    /// ranges pointing past the end
+    pub fn dirty_ranges(&self) -> impl Iterator<Item = Range<u32>> + '_ {
+        let len = self.data.len() as u32;
+        self.dirty
+            .iter()
+            .map(move |r| r.start.min(len)..r.end.min(len))
+            .filter(|r| !r.is_empty())
+    }
+
+    /// forces a full upload, e.g. after the GPU buffer was reallocated
+    pub fn mark_all_dirty(&mut self) {
+        self.dirty.clear();
+        if !self.data.is_empty() {
+            self.dirty.insert(0..self.data.len() as u32);
+        }
+    }
+
+    /// call once the dirty ranges have been written to the GPU
+    pub fn clear_dirty(&mut self) {
+        self.dirty.clear();
+    }
+}
+
+/// per-model instance storage addressed by generational handles.
+/// removal swap-removes from the packed array, so only the moved instance's slot changes
+#[derive(Default)]
+pub struct InstanceStore {
+    batches: Vec<InstanceBatch>,
+    batch_ids: HashMap<String, u32>,
+}
+
+impl InstanceStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// makes sure `model_id` has a (possibly empty) batch and returns its index
+    pub fn add_batch(&mut self, model_id: &str) -> u32 {
+        if let Some(batch) = self.batch_ids.get(model_id) {
+            return *batch;
+        }
+
+        let batch = self.batches.len() as u32;
+        self.batches.push(InstanceBatch::new(model_id));
+        self.batch_ids.insert(model_id.to_string(), batch);
+        batch
+    }
+
+    /// adds an instance to the batch of `model_id`, `None` if `add_batch` was never called
+    /// for it, nothing would draw the instance
+    pub fn spawn(&mut self, model_id: &str, instance: InstanceData) -> Option<InstanceHandle> {
+        let batch = *self.batch_ids.get(model_id)?;
+        let instances = &mut self.batches[batch as usize];
+        let index = instances.data.len() as u32;
+
+        let key = instances.data.insert(instance);
+        instances.dirty.insert(index..index + 1);
+
+        Some(InstanceHandle { batch, key })
+    }
+
+    fn batch(&self, handle: InstanceHandle) -> &InstanceBatch {
+        &self.batches[handle.batch as usize]
+    }
+
+    pub fn contains(&self, handle: InstanceHandle) -> bool {
+        self.batch(handle).data.contains(handle.key)
+    }
+
+    pub fn get(&self, handle: InstanceHandle) -> Option<&InstanceData> {
+        self.batch(handle).data.get(handle.key)
+    }
+
+    /// overwrites the instance data, returns false for stale handles
+    pub fn set(&mut self, handle: InstanceHandle, instance: InstanceData) -> bool {
+        let batch = &mut self.batches[handle.batch as usize];
+        let Some(index) = batch.data.index(handle.key) else {
+            return false;
+        };
+
+        *batch.data.get_mut(handle.key).unwrap() = instance;
+        batch.dirty.insert(index as u32..index as u32 + 1);
+        true
+    }
+
+    /// removes the instance, moving the last one of its batch into the hole.
+    /// returns false for stale handles
+    pub fn remove(&mut self, handle: InstanceHandle) -> bool {
+        let batch = &mut self.batches[handle.batch as usize];
+        let Some(index) = batch.data.remove(handle.key) else {
+            return false;
+        };
+
+        // the previous last entry now lives at `index`
+        if index < batch.data.len() {
+            batch.dirty.insert(index as u32..index as u32 + 1);
+        }
+        true
+    }
+
+    /// the packed instances of a model, in GPU buffer order
+    pub fn instances(&self, model_id: &str) -> &[InstanceData] {
+        match self.batch_ids.get(model_id) {
+            Some(batch) => self.batches[*batch as usize].instances(),
+            None => &[],
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[InstanceData])> {
+        self.batches
+            .iter()
+            .map(|batch| (batch.id.as_str(), batch.instances()))
+    }
+
+    pub fn iter_batches_mut(&mut self) -> impl Iterator<Item = &mut InstanceBatch> {
+        self.batches.iter_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.batches.iter().map(|b| b.data.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Mat4, Vec3};
+
+    use super::*;
+
+    fn at(x: f32) -> InstanceData {
+        InstanceData::from(Mat4::from_translation(Vec3::new(x, 0.0, 0.0)))
+    }
+
+    fn xs(store: &InstanceStore) -> Vec<f32> {
+        store
+            .instances("cube")
+            .iter()
+            .map(|instance| instance.transform().w_axis.x)
+            .collect()
+    }
+
+    /// as `(start, end)`, clippy takes a single range in an array for a mistake
+    fn pairs(ranges: impl Iterator<Item = Range<u32>>) -> Vec<(u32, u32)> {
+        ranges.map(|range| (range.start, range.end)).collect()
+    }
+
+    fn dirty(store: &mut InstanceStore) -> Vec<(u32, u32)> {
+        pairs(store.iter_batches_mut().next().unwrap().dirty_ranges())
+    }
+
+    fn clear_dirty(store: &mut InstanceStore) {
+        store
+            .iter_batches_mut()
+            .for_each(InstanceBatch::clear_dirty);
+    }
+
+    fn cubes(count: usize) -> (InstanceStore, Vec<InstanceHandle>) {
+        let mut store = InstanceStore::new();
+        store.add_batch("cube");
+        let handles = (0..count)
+            .map(|i| store.spawn("cube", at(i as f32)).unwrap())
+            .collect();
+        (store, handles)
+    }
+
+    #[test]
+    fn removed_handles_are_rejected() {
+        let (mut store, handles) = cubes(2);
+        assert!(store.remove(handles[0]));
+
+        assert!(!store.contains(handles[0]));
+        assert!(store.get(handles[0]).is_none());
+        assert!(!store.set(handles[0], at(5.0)));
+        assert!(!store.remove(handles[0]));
+        assert_eq!(xs(&store), [1.0]);
+    }
+
+    #[test]
+    fn removal_patches_the_moved_instance() {
+        let (mut store, handles) = cubes(3);
+        clear_dirty(&mut store);
+
+        // the last instance fills the hole, its handle follows it there
+        assert!(store.remove(handles[0]));
+        assert_eq!(xs(&store), [2.0, 1.0]);
+        assert_eq!(dirty(&mut store), [(0, 1)]);
+        assert_eq!(store.get(handles[2]).unwrap().transform().w_axis.x, 2.0);
+
+        assert!(store.set(handles[2], at(7.0)));
+        assert_eq!(xs(&store), [7.0, 1.0]);
+    }
+
+    #[test]
+    fn reused_slots_get_a_new_generation() {
+        let (mut store, handles) = cubes(1);
+        assert!(store.remove(handles[0]));
+
+        let reused = store.spawn("cube", at(3.0)).unwrap();
+        assert_ne!(reused, handles[0]);
+        assert!(store.get(handles[0]).is_none());
+        assert!(!store.remove(handles[0]));
+        assert_eq!(store.get(reused).unwrap().transform().w_axis.x, 3.0);
+    }
+
+    #[test]
+    fn unknown_models_get_no_instances() {
+        let (mut store, _) = cubes(0);
+        assert!(store.spawn("sphere", at(0.0)).is_none());
+        assert!(store.instances("sphere").is_empty());
+        assert_eq!(store.len(), 0);
+    }
+
+    #[test]
+    fn dirty_ranges_merge_when_they_touch() {
+        let mut ranges = DirtyRanges::default();
+        ranges.insert(0..2);
+        ranges.insert(2..4);
+        ranges.insert(6..8);
+        assert_eq!(pairs(ranges.iter().cloned()), [(0, 4), (6, 8)]);
+
+        ranges.insert(3..7);
+        assert_eq!(pairs(ranges.iter().cloned()), [(0, 8)]);
+    }
+
+    #[test]
+    fn dirty_ranges_collapse_past_the_limit() {
+        let mut ranges = DirtyRanges::default();
+        for i in 0..MAX_DIRTY_RANGES as u32 {
+            ranges.insert(i * 3..i * 3 + 1);
+        }
+        assert_eq!(ranges.iter().count(), MAX_DIRTY_RANGES);
+
+        ranges.insert(100..101);
+        assert_eq!(pairs(ranges.iter().cloned()), [(0, 101)]);
+    }
+
+    #[test]
+    fn dirty_ranges_are_clamped_to_the_instances() {
+        let (mut store, handles) = cubes(3);
+        clear_dirty(&mut store);
+
+        // the change to the last instance moves with it, its old place is gone
+        assert!(store.set(handles[2], at(9.0)));
+        assert!(store.remove(handles[0]));
+        assert_eq!(dirty(&mut store), [(0, 1)]);
+    }
+}
@@ -0,0 +1,507 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use glam::*;
+
+use super::assets::AssetSource;
+use super::bind_group::Builder as BindGroupBuilder;
+use super::bind_group_layout::Builder as BindGroupLayoutBuilder;
+use super::pipeline::load_shader_module;
+use super::slot_map::{SlotKey, SlotMap};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// the light assignment shader, relative to the shader source
+pub const SHADER: &str = "cluster_lights.wgsl";
+
+/// how many clusters the view frustum is split into: screen tiles across and down, then
+/// depth slices that grow exponentially with the distance
+pub const CLUSTER_COUNT: UVec3 = UVec3::new(16, 9, 24);
+
+/// lights past this many in one cluster are left out of it, `FrameStats` counts the
+/// clusters where that happens
+pub const MAX_LIGHTS_PER_CLUSTER: u32 = 63;
+
+/// the view depth the last cluster slice ends at for projections without a far plane,
+/// until `RendererState::set_cluster_distance`
+pub const DEFAULT_CLUSTER_DISTANCE: f32 = 10000.0;
+
+/// `u32`s per cluster in the cluster buffer, the light count followed by the light indices.
+/// `CLUSTER_STRIDE` in include/lights.wgsl has to match
+pub const CLUSTER_STRIDE: u64 = MAX_LIGHTS_PER_CLUSTER as u64 + 1;
+
+/// where a light shines from and in which direction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    /// infinitely far away like the sun, `direction` is the way its light travels
+    Directional { direction: Vec3 },
+    /// shines in every direction from `position`, fading out to nothing at `range`
+    Point { position: Vec3, range: f32 },
+    /// a point light limited to a cone around `direction`: full strength up to `inner_angle`
+    /// from it, fading out to nothing at `outer_angle` (both in radians)
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+/// a light added with `RendererState::add_light`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    /// linear rgb
+    pub color: Vec3,
+    /// scales `color`. a directional light of intensity π lights a white matte surface facing
+    /// it to white, point and spot lights reach that at 1 unit and fall off with the square
+    /// of the distance
+    pub intensity: f32,
+    /// whether objects block this light, off for new lights. only directional lights cast
+    /// shadows, and only the first one with them on, see `shadows::shadowed_light`
+    pub cast_shadows: bool,
+}
+
+impl Light {
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Light {
+            kind: LightKind::Directional { direction },
+            color,
+            intensity,
+            cast_shadows: false,
+        }
+    }
+
+    pub fn point(position: Vec3, range: f32, color: Vec3, intensity: f32) -> Self {
+        Light {
+            kind: LightKind::Point { position, range },
+            color,
+            intensity,
+            cast_shadows: false,
+        }
+    }
+
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+        color: Vec3,
+        intensity: f32,
+    ) -> Self {
+        Light {
+            kind: LightKind::Spot {
+                position,
+                direction,
+                range,
+                inner_angle,
+                outer_angle,
+            },
+            color,
+            intensity,
+            cast_shadows: false,
+        }
+    }
+}
+
+/// `Light` as `cluster_lights.wgsl` and the model shader read it
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuLight {
+    position: [f32; 3],
+    /// 0 directional, 1 point, 2 spot
+    kind: u32,
+    /// normalized
+    direction: [f32; 3],
+    range: f32,
+    /// color times intensity
+    radiance: [f32; 3],
+    cos_outer: f32,
+    cos_inner: f32,
+    padding: [f32; 3],
+}
+
+impl From<&Light> for GpuLight {
+    fn from(light: &Light) -> Self {
+        let mut gpu = GpuLight {
+            radiance: (light.color * light.intensity).to_array(),
+            ..Default::default()
+        };
+        match light.kind {
+            LightKind::Directional { direction } => {
+                gpu.kind = 0;
+                gpu.direction = direction.normalize_or(Vec3::NEG_Z).to_array();
+            }
+            LightKind::Point { position, range } => {
+                gpu.kind = 1;
+                gpu.position = position.to_array();
+                gpu.range = range;
+            }
+            LightKind::Spot {
+                position,
+                direction,
+                range,
+                inner_angle,
+                outer_angle,
+            } => {
+                gpu.kind = 2;
+                gpu.position = position.to_array();
+                gpu.direction = direction.normalize_or(Vec3::NEG_Z).to_array();
+                gpu.range = range;
+                gpu.cos_outer = outer_angle.cos();
+                // a hard edge still needs a sliver to fade over
+                gpu.cos_inner = inner_angle.min(outer_angle).cos().max(gpu.cos_outer + 1e-4);
+            }
+        }
+        gpu
+    }
+}
+
+/// a stable reference to an added light.
+/// stays valid until the light is removed, see `SlotKey`
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct LightHandle(SlotKey);
+
+/// every light, packed so it can be uploaded without holes and addressed by generational
+/// handles
+#[derive(Default)]
+pub struct LightStore {
+    lights: SlotMap<Light>,
+    /// the GPU copy is out of date
+    dirty: bool,
+}
+
+impl LightStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, light: Light) -> LightHandle {
+        self.dirty = true;
+        LightHandle(self.lights.insert(light))
+    }
+
+    pub fn get(&self, handle: LightHandle) -> Option<&Light> {
+        self.lights.get(handle.0)
+    }
+
+    /// overwrites the light, returns false for stale handles
+    pub fn set(&mut self, handle: LightHandle, light: Light) -> bool {
+        let Some(slot) = self.lights.get_mut(handle.0) else {
+            return false;
+        };
+        *slot = light;
+        self.dirty = true;
+        true
+    }
+
+    /// removes the light, moving the last one into the hole. returns false for stale handles
+    pub fn remove(&mut self, handle: LightHandle) -> bool {
+        let removed = self.lights.remove(handle.0).is_some();
+        self.dirty |= removed;
+        removed
+    }
+
+    /// the lights in GPU buffer order
+    pub fn lights(&self) -> &[Light] {
+        self.lights.values()
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// call once the lights have been written to the GPU
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+}
+
+/// the buffer the lights are uploaded to, big enough for `capacity` lights (at least one,
+/// empty bindings aren't allowed)
+pub fn new_light_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Lights"),
+        size: (capacity.max(1) * std::mem::size_of::<GpuLight>()) as u64,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+/// the light list of every cluster, filled by `LightClusterer::assign`
+pub fn new_cluster_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Light Clusters"),
+        size: cluster_buffer_size(),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    })
+}
+
+fn cluster_buffer_size() -> u64 {
+    CLUSTER_COUNT.element_product() as u64 * CLUSTER_STRIDE * std::mem::size_of::<u32>() as u64
+}
+
+/// the lights `LightClusterer::assign` found for one cluster
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClusterLights {
+    /// how many lights reach the cluster, more than `lights` holds if it overflowed
+    pub reaching: u32,
+    /// indices into `LightStore::lights` of the lights that are shaded, at most
+    /// `MAX_LIGHTS_PER_CLUSTER`
+    pub lights: Vec<u32>,
+}
+
+/// waits for the GPU and reads the lists in `clusters`, x fastest, then y from the top of
+/// the screen, then the depth slice
+pub fn read_clusters(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    clusters: &wgpu::Buffer,
+) -> Vec<ClusterLights> {
+    let size = cluster_buffer_size();
+    let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Light Clusters Readback"),
+        size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Light Clusters Readback Encoder"),
+    });
+    encoder.copy_buffer_to_buffer(clusters, 0, &readback_buffer, 0, size);
+    let submission = queue.submit(Some(encoder.finish()));
+
+    let slice = readback_buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| {});
+    let _ = device.poll(wgpu::PollType::Wait {
+        submission_index: Some(submission),
+        timeout: None,
+    });
+
+    let clusters = {
+        let data = slice.get_mapped_range();
+        let words: &[u32] = bytemuck::cast_slice(&data);
+        words
+            .chunks(CLUSTER_STRIDE as usize)
+            .map(|cluster| ClusterLights {
+                reaching: cluster[0],
+                lights: cluster[1..=cluster[0].min(MAX_LIGHTS_PER_CLUSTER) as usize].to_vec(),
+            })
+            .collect()
+    };
+    readback_buffer.unmap();
+
+    clusters
+}
+
+/// compute pipeline that lists the lights reaching each cluster of the view frustum, so
+/// fragments only shade the lights of their own cluster
+pub struct LightClusterer {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+    /// how many clusters overflowed this frame
+    overflow: wgpu::Buffer,
+    /// a copy of `overflow` from a recent frame, read without waiting for the GPU
+    overflow_readback: wgpu::Buffer,
+    readback: Readback,
+    /// `MAPPING`, then `MAPPED` or `MAP_FAILED` once the GPU is done with the readback
+    readback_mapped: Arc<AtomicU8>,
+    overflowing_clusters: u32,
+    /// built by the first `assign`, see `ClusterBindGroup`
+    bind_group: Option<ClusterBindGroup>,
+}
+
+/// the bind group of `SHADER` along with the renderer's buffers it was built from. it is only
+/// rebuilt once one of those gets replaced, e.g. the light buffer when it grows
+struct ClusterBindGroup {
+    frame: wgpu::Buffer,
+    lights: wgpu::Buffer,
+    clusters: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+/// where the overflow count is on its way back to the CPU
+#[derive(Clone, Copy, PartialEq)]
+enum Readback {
+    Idle,
+    /// the copy into `overflow_readback` is recorded, it can be mapped once submitted
+    Copied,
+    Mapping,
+}
+
+const MAPPING: u8 = 0;
+const MAPPED: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+impl LightClusterer {
+    /// group 0 of `SHADER`: the frame uniforms, lights, clusters and the overflow counter
+    pub fn bind_group_layout() -> BindGroupLayoutBuilder {
+        let mut builder = BindGroupLayoutBuilder::new();
+        builder.add_compute_uniform();
+        builder.add_storage_buffer(true);
+        builder.add_storage_buffer(false);
+        builder.add_storage_buffer(false);
+        builder
+    }
+
+    pub fn new(device: &wgpu::Device, shaders: &dyn AssetSource) -> Self {
+        let layout = Self::bind_group_layout().build(device, "Light Cluster Bind Group Layout");
+        let shader_module = load_shader_module(device, shaders, SHADER, &[]);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Light Cluster Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Light Cluster Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader_module,
+            entry_point: Some("assign"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        let overflow = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Cluster Overflow"),
+            size: std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let overflow_readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Cluster Overflow Readback"),
+            size: std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        LightClusterer {
+            layout,
+            pipeline,
+            overflow,
+            overflow_readback,
+            readback: Readback::Idle,
+            readback_mapped: Arc::new(AtomicU8::new(MAPPING)),
+            overflowing_clusters: 0,
+            bind_group: None,
+        }
+    }
+
+    /// records the pass that fills `clusters` from `lights`, with the camera and light count
+    /// of the frame uniforms in `frame`. call `map_overflow` once it is submitted
+    pub fn assign(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        frame: &wgpu::Buffer,
+        lights: &wgpu::Buffer,
+        clusters: &wgpu::Buffer,
+    ) {
+        encoder.clear_buffer(&self.overflow, 0, None);
+        let bind_group = self.bind_group(device, frame, lights, clusters);
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Light Cluster Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.set_pipeline(&self.pipeline);
+        pass.dispatch_workgroups(
+            CLUSTER_COUNT.element_product().div_ceil(WORKGROUP_SIZE),
+            1,
+            1,
+        );
+        drop(pass);
+
+        // one readback at a time, the frames in between aren't counted
+        if self.readback == Readback::Idle {
+            encoder.copy_buffer_to_buffer(
+                &self.overflow,
+                0,
+                &self.overflow_readback,
+                0,
+                std::mem::size_of::<u32>() as u64,
+            );
+            self.readback = Readback::Copied;
+        }
+    }
+
+    /// the bind group for these buffers, rebuilt if it was made for other ones
+    fn bind_group(
+        &mut self,
+        device: &wgpu::Device,
+        frame: &wgpu::Buffer,
+        lights: &wgpu::Buffer,
+        clusters: &wgpu::Buffer,
+    ) -> &wgpu::BindGroup {
+        let outdated = self.bind_group.as_ref().is_none_or(|cached| {
+            cached.frame != *frame || cached.lights != *lights || cached.clusters != *clusters
+        });
+        if outdated {
+            let mut builder = BindGroupBuilder::new(device);
+            builder.set_layout(&self.layout);
+            builder.add_buffer(frame, 0);
+            builder.add_buffer(lights, 0);
+            builder.add_buffer(clusters, 0);
+            builder.add_buffer(&self.overflow, 0);
+            self.bind_group = Some(ClusterBindGroup {
+                frame: frame.clone(),
+                lights: lights.clone(),
+                clusters: clusters.clone(),
+                bind_group: builder.build("Light Cluster Bind Group"),
+            });
+        }
+
+        &self.bind_group.as_ref().unwrap().bind_group
+    }
+
+    /// starts mapping the overflow count copied by the submitted `assign`
+    pub fn map_overflow(&mut self) {
+        if self.readback != Readback::Copied {
+            return;
+        }
+        self.readback = Readback::Mapping;
+        self.readback_mapped.store(MAPPING, Ordering::Release);
+        let mapped = self.readback_mapped.clone();
+        self.overflow_readback
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let state = if result.is_ok() { MAPPED } else { MAP_FAILED };
+                mapped.store(state, Ordering::Release);
+            });
+    }
+
+    /// clusters that more than `MAX_LIGHTS_PER_CLUSTER` lights reached, in the latest frame
+    /// whose count made it back from the GPU
+    pub fn overflowing_clusters(&mut self) -> u32 {
+        if self.readback != Readback::Mapping {
+            return self.overflowing_clusters;
+        }
+        match self.readback_mapped.load(Ordering::Acquire) {
+            MAPPED => {
+                let data = self.overflow_readback.slice(..).get_mapped_range();
+                self.overflowing_clusters = bytemuck::cast_slice::<u8, u32>(&data)[0];
+                drop(data);
+                self.overflow_readback.unmap();
+                self.readback = Readback::Idle;
+            }
+            MAP_FAILED => self.readback = Readback::Idle,
+            _ => {}
+        }
+        self.overflowing_clusters
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::assets::normalize;
+use super::definitions::{Material, MaterialFeatures};
+
+/// index of a material in a `MaterialRegistry`, valid across every model loaded into it
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct MaterialId(u32);
+
+/// what makes two materials the same, regardless of which OBJ they came from
+#[derive(Eq, Hash, PartialEq)]
+struct MaterialKey {
+    features: MaterialFeatures,
+    /// the bits of every `PbrMaterial` field
+    pbr: Vec<u32>,
+    /// the same file reached through different relative paths is the same map
+    textures: [Option<String>; 6],
+}
+
+impl MaterialKey {
+    fn of(material: &Material) -> Self {
+        let pbr = &material.pbr;
+        let texture = |filename: &Option<String>| {
+            filename
+                .as_ref()
+                .map(|filename| normalize(Path::new(filename)))
+        };
+        MaterialKey {
+            features: material.features,
+            pbr: [
+                pbr.base_color.to_array().as_slice(),
+                pbr.emissive.to_array().as_slice(),
+                &[
+                    pbr.metallic,
+                    pbr.roughness,
+                    pbr.reflectance,
+                    pbr.alpha_cutoff,
+                ],
+            ]
+            .concat()
+            .into_iter()
+            .map(f32::to_bits)
+            .collect(),
+            textures: [
+                texture(&material.filename),
+                texture(&material.roughness_texture),
+                texture(&material.metallic_texture),
+                texture(&material.normal_texture),
+                texture(&material.specular_texture),
+                texture(&material.emissive_texture),
+            ],
+        }
+    }
+}
+
+/// every material of every loaded model, deduplicated by content
+#[derive(Default)]
+pub struct MaterialRegistry {
+    materials: Vec<Material>,
+    ids: HashMap<MaterialKey, MaterialId>,
+    /// how many materials `take_new` has already handed out
+    taken: usize,
+}
+
+impl MaterialRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// adds `material` unless an equal one is already registered, returns the id either way
+    pub fn register(&mut self, material: Material) -> MaterialId {
+        let key = MaterialKey::of(&material);
+        if let Some(id) = self.ids.get(&key) {
+            return *id;
+        }
+
+        let id = MaterialId(self.materials.len() as u32);
+        self.materials.push(material);
+        self.ids.insert(key, id);
+        id
+    }
+
+    pub fn get(&self, id: MaterialId) -> &Material {
+        &self.materials[id.0 as usize]
+    }
+
+    /// the materials registered since the last call, so their GPU state is built only once
+    pub fn take_new(&mut self) -> impl Iterator<Item = &mut Material> {
+        let start = self.taken;
+        self.taken = self.materials.len();
+        self.materials[start..].iter_mut()
+    }
+
+    pub fn len(&self) -> usize {
+        self.materials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.materials.is_empty()
+    }
+}
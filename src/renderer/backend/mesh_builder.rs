@@ -1,75 +1,402 @@
-use crate::renderer::backend::definitions::{Model, PipelineType, Submesh};
+use crate::renderer::backend::definitions::{Bounds, Model, Submesh};
 // use crate::utility::string::split;
 use glam::*;
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
 use wgpu::util::DeviceExt;
 
-use super::definitions::{Material, VertexData};
+use super::assets::{AssetSource, DirectoryAssets, sibling};
+use super::definitions::{AlphaMode, Material, VertexData};
+use super::materials::{MaterialId, MaterialRegistry};
+use super::mesh_cache::{self, CompiledMesh, CompiledSubmesh};
+use super::mesh_optimizer::{optimize_overdraw, optimize_vertex_cache, optimize_vertex_fetch};
 
-// From: https://stackoverflow.com/questions/28127165/how-to-convert-struct-to-u8
-pub unsafe fn any_as_u8_slice<T: Sized>(p: &T) -> &[u8] {
-    unsafe {
-        ::core::slice::from_raw_parts((p as *const T) as *const u8, ::core::mem::size_of::<T>())
+/// a parsed mesh in CPU memory, ready to be uploaded with `upload`
+pub struct MeshData {
+    /// unique vertices, shared between triangles and submeshes
+    pub vertices: Vec<VertexData>,
+    pub indices: Vec<u32>,
+    pub submeshes: Vec<Submesh>,
+    pub bounds: Bounds,
+}
+
+impl MeshData {
+    /// registers the materials of a compiled mesh, submeshes get the registry's ids
+    pub fn from_compiled(compiled: CompiledMesh, materials: &mut MaterialRegistry) -> Self {
+        // `material_ids` maps the OBJ's material indices to registry ids
+        let material_ids: Vec<MaterialId> = compiled
+            .materials
+            .into_iter()
+            .map(|material| materials.register(material))
+            .collect();
+
+        let submeshes = compiled
+            .submeshes
+            .iter()
+            .map(|submesh| Submesh {
+                first_index: submesh.first_index,
+                index_count: submesh.index_count,
+                // meshes without a (known) material get the purple default
+                material_id: match submesh.material {
+                    Some(material) => material_ids[material as usize],
+                    None => materials.register(Material::new()),
+                },
+                bounds: submesh.bounds,
+            })
+            .collect();
+
+        MeshData {
+            vertices: compiled.vertices,
+            indices: compiled.indices,
+            submeshes,
+            bounds: compiled.bounds,
+        }
+    }
+
+    /// 16 bit indices reach every vertex of meshes with fewer than 65536 vertices
+    pub fn index_format(&self) -> wgpu::IndexFormat {
+        if self.vertices.len() < u16::MAX as usize + 1 {
+            wgpu::IndexFormat::Uint16
+        } else {
+            wgpu::IndexFormat::Uint32
+        }
+    }
+
+    /// puts the vertices and indices into a single GPU buffer
+    pub fn upload(&self, device: &wgpu::Device) -> Model {
+        // merge vertex + index data into a single buffer
+        let bytes_verts: &[u8] = unsafe {
+            core::slice::from_raw_parts(
+                self.vertices.as_ptr() as *const u8,
+                self.vertices.len() * core::mem::size_of::<VertexData>(),
+            )
+        };
+
+        let index_format = self.index_format();
+        let bytes_idx: Vec<u8> = match index_format {
+            wgpu::IndexFormat::Uint16 => self
+                .indices
+                .iter()
+                .flat_map(|i| (*i as u16).to_ne_bytes())
+                .collect(),
+            wgpu::IndexFormat::Uint32 => {
+                self.indices.iter().flat_map(|i| i.to_ne_bytes()).collect()
+            }
+        };
+
+        let merged = [bytes_verts, &bytes_idx].concat();
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Model vertex & index buffer"),
+            contents: &merged,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::INDEX,
+        });
+
+        let ebo_offset = bytes_verts.len() as u64;
+
+        Model {
+            buffer,
+            ebo_offset,
+            index_format,
+            submeshes: self.submeshes.clone(),
+            bounds: self.bounds,
+        }
     }
 }
 
-pub struct ObjLoader;
+/// bit pattern of a vertex, equal vertices get welded into one
+fn vertex_key(vertex: &VertexData) -> [u32; 12] {
+    let p = vertex.position;
+    let t = vertex.tex_coord;
+    let n = vertex.normal;
+    let g = vertex.tangent;
+    [p.x, p.y, p.z, t.x, t.y, n.x, n.y, n.z, g.x, g.y, g.z, g.w].map(f32::to_bits)
+}
+
+/// the unwelded triangle corners of a submesh, as MikkTSpace reads and writes them
+struct Corners<'a>(&'a mut [VertexData]);
+
+impl bevy_mikktspace::Geometry for Corners<'_> {
+    fn num_faces(&self) -> usize {
+        self.0.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.0[face * 3 + vert].position.into()
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.0[face * 3 + vert].normal.into()
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        // v back to the OBJ's, so the bitangent points up the image like normal maps expect
+        let t = self.0[face * 3 + vert].tex_coord;
+        [t.x, 1.0 - t.y]
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.0[face * 3 + vert].tangent = Vec4::from(tangent);
+    }
+}
+
+/// fills in the tangents of triangle `corners` with MikkTSpace, the same tangents Blender
+/// bakes normal maps with. without texture coordinates, and where the texture coordinates
+/// give no direction, any tangent perpendicular to the normal does
+fn generate_tangents(corners: &mut [VertexData], has_tex_coords: bool) {
+    if has_tex_coords {
+        bevy_mikktspace::generate_tangents(&mut Corners(corners));
+    }
+    for corner in corners {
+        let n = corner.normal;
+        let t = corner.tangent.truncate();
+        let tangent = (t - n * n.dot(t))
+            .try_normalize()
+            .or(n.try_normalize().map(|n| n.any_orthonormal_vector()))
+            .unwrap_or(Vec3::X);
+        let sign = if corner.tangent.w < 0.0 { -1.0 } else { 1.0 };
+        corner.tangent = tangent.extend(sign);
+    }
+}
+
+/// the file of an MTL map statement. its options (`-bm 1.0`, `-clamp on`, `-o 0.5 0.5`, ...)
+/// are skipped, everything after them is the path, spaces included
+pub fn map_file(statement: &str) -> &str {
+    let mut rest = statement.trim();
+    while let Some(option) = rest.strip_prefix('-') {
+        // how many values each option of the MTL spec takes, at least and at most
+        let (name, mut after) = next_word(option);
+        let (min, max) = match name {
+            "blendu" | "blendv" | "bm" | "boost" | "cc" | "clamp" | "imfchan" | "texres"
+            | "type" => (1, 1),
+            "mm" => (2, 2),
+            "o" | "s" | "t" => (1, 3),
+            // not an option we know, so part of the path
+            _ => break,
+        };
+        for i in 0..max {
+            let (value, remaining) = next_word(after);
+            if value.is_empty() || (i >= min && value.parse::<f32>().is_err()) {
+                break;
+            }
+            after = remaining;
+        }
+        rest = after.trim_start();
+    }
+    rest
+}
+
+/// the first whitespace separated word of `text` and what follows it
+fn next_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    text.split_at(text.find(char::is_whitespace).unwrap_or(text.len()))
+}
+
+/// parses an OBJ from `assets` with tobj, MTL libraries are looked up next to it
+pub fn load_obj(assets: &dyn AssetSource, filename: &str) -> tobj::LoadResult {
+    let obj = assets
+        .read(filename)
+        .ok_or(tobj::LoadError::OpenFileFailed)?;
+
+    tobj::load_obj_buf(
+        &mut &obj[..],
+        &tobj::LoadOptions {
+            triangulate: true,
+            single_index: true,
+            ..Default::default()
+        },
+        |mtl_path| {
+            let mtl = assets
+                .read(&sibling(filename, &mtl_path.to_string_lossy()))
+                .ok_or(tobj::LoadError::OpenFileFailed)?;
+            tobj::load_mtl_buf(&mut &mtl[..])
+        },
+    )
+}
+
+pub struct ObjLoader {
+    optimize: bool,
+    /// where compiled meshes are kept between runs, `None` always parses the OBJ
+    cache_dir: Option<PathBuf>,
+    /// where the OBJ, its MTLs and its textures are read from
+    assets: Arc<dyn AssetSource>,
+}
+
+impl Default for ObjLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl ObjLoader {
     pub fn new() -> Self {
-        ObjLoader
+        ObjLoader {
+            optimize: false,
+            cache_dir: None,
+            assets: Arc::new(DirectoryAssets::new(".")),
+        }
+    }
+
+    /// reorder triangles for the vertex cache and overdraw, and vertices for fetching
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// reuse meshes compiled into `cache_dir` while their OBJ and MTL files are unchanged
+    pub fn set_cache_dir(&mut self, cache_dir: Option<PathBuf>) {
+        self.cache_dir = cache_dir;
+    }
+
+    /// read everything from `assets` instead of the working directory
+    pub fn set_asset_source(&mut self, assets: Arc<dyn AssetSource>) {
+        self.assets = assets;
     }
 
     pub fn load(
         &mut self,
         filename: &str,
-        materials_out: &mut Vec<Material>,
+        materials: &mut MaterialRegistry,
         device: &wgpu::Device,
         pre_transform: &Mat4,
-    ) -> Model {
-        let obj_path = Path::new(filename);
-
-        let (models, materials) = tobj::load_obj(
-            obj_path,
-            &tobj::LoadOptions {
-                triangulate: true,
-                single_index: true,
-                ..Default::default()
-            },
-        )
-        .expect("tobj failed");
+    ) -> Result<Model, String> {
+        Ok(self
+            .parse(filename, materials, pre_transform)?
+            .upload(device))
+    }
+
+    /// reads the OBJ (or its compiled form from the cache) and registers its materials,
+    /// submeshes get the registry's ids
+    pub fn parse(
+        &mut self,
+        filename: &str,
+        materials: &mut MaterialRegistry,
+        pre_transform: &Mat4,
+    ) -> Result<MeshData, String> {
+        let compiled = self.compile_cached(filename, pre_transform)?;
+        Ok(MeshData::from_compiled(compiled, materials))
+    }
+
+    /// the compiled mesh from the cache, compiling and caching it if it isn't there.
+    /// nothing is registered yet
+    pub fn compile_cached(
+        &mut self,
+        filename: &str,
+        pre_transform: &Mat4,
+    ) -> Result<CompiledMesh, String> {
+        // an OBJ that can't be read isn't cached, `compile` reports it
+        let cache = self
+            .cache_dir
+            .as_ref()
+            .zip(self.source_hash(filename, pre_transform));
+        match cache {
+            Some((cache_dir, source_hash)) => {
+                let path = mesh_cache::cache_path(cache_dir, filename, "mesh");
+                if let Some(compiled) = mesh_cache::read(&path, source_hash) {
+                    return Ok(compiled);
+                }
+                let compiled = self.compile(filename, pre_transform, source_hash)?;
+                mesh_cache::write(&path, &compiled);
+                Ok(compiled)
+            }
+            None => self.compile(filename, pre_transform, 0),
+        }
+    }
+
+    /// identifies the OBJ, its MTLs and the settings that change how it compiles,
+    /// `None` if the OBJ can't be read
+    pub fn source_hash(&self, filename: &str, pre_transform: &Mat4) -> Option<u64> {
+        let mut settings = vec![self.optimize as u8];
+        settings.extend(
+            pre_transform
+                .to_cols_array()
+                .map(f32::to_bits)
+                .map(u32::to_le_bytes)
+                .concat(),
+        );
+        mesh_cache::source_hash(self.assets.as_ref(), filename, &settings)
+    }
+
+    /// parses the OBJ with tobj and builds the welded (and optionally optimized) mesh.
+    /// an OBJ or MTL that can't be read or parsed is an error
+    pub fn compile(
+        &self,
+        filename: &str,
+        pre_transform: &Mat4,
+        source_hash: u64,
+    ) -> Result<CompiledMesh, String> {
+        let (models, obj_materials) =
+            load_obj(self.assets.as_ref(), filename).map_err(|e| format!("{}: {}", filename, e))?;
+        let obj_materials =
+            obj_materials.map_err(|e| format!("{}: material library: {}", filename, e))?;
 
         // convert materials
-        let mtl_dir = obj_path.parent().unwrap_or(Path::new(""));
-        for m in materials.unwrap_or_default() {
+        let mut compiled_materials = Vec::new();
+        for m in obj_materials {
             let mut mat = Material::new();
+            mat.pbr = mat.pbr.from_mtl(&m);
+
+            // make sure the pipeline samples the maps the material has
+            if let Some(path) = &m.diffuse_texture {
+                mat.features.base_texture = true;
+                mat.filename = Some(sibling(filename, map_file(path)));
+            }
+            if let Some(path) = m.unknown_param.get("map_Pr") {
+                mat.features.roughness_texture = true;
+                mat.roughness_texture = Some(sibling(filename, map_file(path)));
+            }
+            if let Some(path) = m.unknown_param.get("map_Pm") {
+                mat.features.metallic_texture = true;
+                mat.metallic_texture = Some(sibling(filename, map_file(path)));
+            }
+            // tobj reads `map_Bump` and `bump` as the normal map, `norm` is left to us
+            if let Some(path) = m.normal_texture.as_ref().or(m.unknown_param.get("norm")) {
+                mat.features.normal_texture = true;
+                mat.normal_texture = Some(sibling(filename, map_file(path)));
+            }
+            if let Some(path) = &m.specular_texture {
+                mat.features.specular_texture = true;
+                mat.specular_texture = Some(sibling(filename, map_file(path)));
+            }
+            if let Some(path) = m.unknown_param.get("map_Ke") {
+                mat.features.emissive_texture = true;
+                mat.emissive_texture = Some(sibling(filename, map_file(path)));
+            }
+
+            // not an MTL statement, ours: `double_sided on` draws the back faces too
+            if m.unknown_param.get("double_sided").map(|v| v.trim()) == Some("on") {
+                mat.features.double_sided = true;
+            }
 
-            // if the model has a texture, make sure it runs through the TexturedModel pipeline
-            if let Some(path) = m.diffuse_texture {
-                mat.pipeline_type = PipelineType::TexturedModel;
-                mat.filename = Some(mtl_dir.join(path).to_string_lossy().to_string());
-            } else if let Some(diffuse) = /* does the mat specify color? */ m.diffuse {
-                mat.pipeline_type = PipelineType::ColoredModel;
-                mat.color = Some(Vec4::new(diffuse[0], diffuse[1], diffuse[2], 1.0));
-            } // otherwise, don't change default material (defaults to purple)
+            // see `PbrMaterial::from_mtl`
+            if mat.pbr.base_color.w < 1.0 {
+                mat.features.alpha_mode = AlphaMode::Blend;
+            } else if m.dissolve_texture.is_some() {
+                mat.features.alpha_mode = AlphaMode::Mask;
+            }
 
-            materials_out.push(mat);
+            compiled_materials.push(mat);
         }
 
         // collect all vertices + indices + submeshes
         let mut vertex_data: Vec<VertexData> = Vec::new();
         let mut index_data: Vec<u32> = Vec::new();
-        let mut submeshes: Vec<Submesh> = Vec::new();
+        let mut submeshes: Vec<CompiledSubmesh> = Vec::new();
+        let mut welded: HashMap<[u32; 12], u32> = HashMap::new();
 
         for m in &models {
             let mesh = &m.mesh;
-            let first_index = index_data.len() as i32;
+            let first_index = index_data.len() as u32;
 
+            let mut corners: Vec<VertexData> = Vec::with_capacity(mesh.indices.len());
             for idx in &mesh.indices {
                 let i = *idx as usize;
 
-                let vx = mesh.positions[i * 3 + 0];
+                let vx = mesh.positions[i * 3];
                 let vy = mesh.positions[i * 3 + 1];
                 let vz = mesh.positions[i * 3 + 2];
                 let p = *pre_transform * Vec4::new(vx, vy, vz, 1.0);
@@ -82,54 +409,61 @@ impl ObjLoader {
                 let nz = mesh.normals.get(i * 3 + 2).cloned().unwrap_or(0.0);
                 let n = (*pre_transform * Vec4::new(nx, ny, nz, 0.0)).normalize();
 
-                vertex_data.push(VertexData {
+                corners.push(VertexData {
                     position: Vec3::new(p.x, p.y, p.z),
                     tex_coord: Vec2::new(tx, 1.0 - ty),
                     normal: Vec3::new(n.x, n.y, n.z),
+                    tangent: Vec4::ZERO,
+                });
+            }
+            generate_tangents(&mut corners, !mesh.texcoords.is_empty());
+
+            for vertex in corners {
+                // tobj's single_index duplicates corners per face, reuse the first copy
+                let index = *welded.entry(vertex_key(&vertex)).or_insert_with(|| {
+                    vertex_data.push(vertex);
+                    vertex_data.len() as u32 - 1
                 });
+                index_data.push(index);
+            }
 
-                index_data.push(index_data.len() as u32);
+            let index_count = index_data.len() as u32 - first_index;
+            if self.optimize {
+                let range = first_index as usize..index_data.len();
+                optimize_vertex_cache(&mut index_data[range.clone()], vertex_data.len());
+                optimize_overdraw(&mut index_data[range], &vertex_data);
             }
 
-            let index_count = (index_data.len() as i32 - first_index) as u32;
-            let mat_id = mesh.material_id.unwrap_or(0);
+            let positions: Vec<Vec3> = index_data[first_index as usize..]
+                .iter()
+                .map(|i| vertex_data[*i as usize].position)
+                .collect();
 
-            submeshes.push(Submesh {
+            submeshes.push(CompiledSubmesh {
                 first_index,
                 index_count,
-                material_id: mat_id,
+                material: mesh
+                    .material_id
+                    .filter(|id| *id < compiled_materials.len())
+                    .map(|id| id as u32),
+                bounds: Bounds::from_points(&positions),
             });
         }
 
-        // merge vertex + index data into a single buffer
-        let bytes_verts: &[u8] = unsafe {
-            core::slice::from_raw_parts(
-                vertex_data.as_ptr() as *const u8,
-                vertex_data.len() * core::mem::size_of::<VertexData>(),
-            )
-        };
-
-        let bytes_idx: &[u8] = unsafe {
-            core::slice::from_raw_parts(
-                index_data.as_ptr() as *const u8,
-                index_data.len() * core::mem::size_of::<u32>(),
-            )
-        };
-
-        let merged = [bytes_verts, bytes_idx].concat();
-
-        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Model vertex & index buffer"),
-            contents: &merged,
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::INDEX,
-        });
+        if self.optimize {
+            optimize_vertex_fetch(&mut vertex_data, &mut index_data);
+        }
 
-        let ebo_offset = bytes_verts.len() as u64;
+        let positions: Vec<Vec3> = vertex_data.iter().map(|v| v.position).collect();
+        let bounds = Bounds::from_points(&positions);
 
-        Model {
-            buffer,
-            ebo_offset,
+        Ok(CompiledMesh {
+            source_hash,
+            vertices: vertex_data,
+            indices: index_data,
             submeshes,
-        }
+            materials: compiled_materials,
+            bounds,
+        })
     }
 }
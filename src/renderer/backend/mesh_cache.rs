@@ -0,0 +1,110 @@
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use super::assets::{AssetSource, normalize, sibling};
+use super::definitions::{Bounds, Material, VertexData};
+
+/// bump whenever `CompiledMesh` or the way it is produced changes, old cache files get rebuilt
+const FORMAT_VERSION: u32 = 6;
+
+/// where a `DirectoryAssets` caches compiled meshes, relative to its root
+pub const CACHE_DIR: &str = "cache/meshes";
+
+/// a submesh before its material is registered, `material` indexes `CompiledMesh::materials`
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct CompiledSubmesh {
+    pub first_index: u32,
+    pub index_count: u32,
+    /// `None` for faces without a material
+    pub material: Option<u32>,
+    pub bounds: Bounds,
+}
+
+/// everything `ObjLoader` gets out of an OBJ and its MTL, in the form that is written to disk
+#[derive(Serialize, Deserialize)]
+pub struct CompiledMesh {
+    /// `source_hash` of the files this was compiled from
+    pub source_hash: u64,
+    pub vertices: Vec<VertexData>,
+    pub indices: Vec<u32>,
+    pub submeshes: Vec<CompiledSubmesh>,
+    pub materials: Vec<Material>,
+    pub bounds: Bounds,
+}
+
+/// 64 bit FNV-1a, stable across builds unlike `DefaultHasher`
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// hash of an OBJ, the MTL libraries it references, and `settings` (anything else that
+/// changes the compiled output). `None` if the OBJ can't be read, there is nothing to cache
+pub fn source_hash(assets: &dyn AssetSource, filename: &str, settings: &[u8]) -> Option<u64> {
+    let obj = assets.read(filename)?;
+    let mut hash = fnv1a(FNV_OFFSET, &FORMAT_VERSION.to_le_bytes());
+    hash = fnv1a(hash, settings);
+    hash = fnv1a(hash, &obj);
+
+    for line in String::from_utf8_lossy(&obj).lines() {
+        if let Some(libraries) = line.trim().strip_prefix("mtllib ") {
+            for library in libraries.split_whitespace() {
+                // a missing MTL still gets hashed as missing, tobj reports it when parsing
+                let mtl = assets.read(&sibling(filename, library)).unwrap_or_default();
+                hash = fnv1a(hash, &mtl);
+            }
+        }
+    }
+    Some(hash)
+}
+
+/// where the compiled form of `filename` lives inside `cache_dir`, `extension` picks the format
+pub fn cache_path(cache_dir: &Path, filename: &str, extension: &str) -> PathBuf {
+    let name = Path::new(filename)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let key = fnv1a(FNV_OFFSET, normalize(Path::new(filename)).as_bytes());
+    cache_dir.join(format!("{}-{:016x}.{}", name, key, extension))
+}
+
+/// the cached mesh at `path`, `None` if there is none or it was compiled from other sources
+pub fn read(path: &Path, source_hash: u64) -> Option<CompiledMesh> {
+    read_compressed::<CompiledMesh>(path).filter(|mesh| mesh.source_hash == source_hash)
+}
+
+/// writes `mesh` to `path`, failing to cache only costs a re-parse next time so errors are ignored
+pub fn write(path: &Path, mesh: &CompiledMesh) {
+    write_compressed(path, mesh).ok();
+}
+
+/// reads a value written by `write_compressed`, `None` if it is missing or unreadable
+pub fn read_compressed<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    decode_compressed(&std::fs::read(path).ok()?)
+}
+
+/// decodes the contents of a file written by `write_compressed`
+pub fn decode_compressed<T: DeserializeOwned>(compressed: &[u8]) -> Option<T> {
+    let bytes = lz4_flex::decompress_size_prepended(compressed).ok()?;
+    let (value, _) = bincode::serde::decode_from_slice(&bytes, bincode::config::standard()).ok()?;
+    Some(value)
+}
+
+/// bincode encodes `value` and lz4 compresses it into `path`, creating missing directories
+pub fn write_compressed<T: Serialize>(path: &Path, value: &T) -> std::io::Result<()> {
+    let bytes = bincode::serde::encode_to_vec(value, bincode::config::standard()).unwrap();
+    let compressed = lz4_flex::compress_prepend_size(&bytes);
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // write next to the target and rename, so concurrent loads never read half a file
+    let partial = path.with_extension(format!("{}.partial", std::process::id()));
+    std::fs::write(&partial, compressed)?;
+    std::fs::rename(&partial, path)
+}
@@ -0,0 +1,215 @@
+use glam::*;
+
+use super::definitions::VertexData;
+
+/// entries of the simulated post-transform cache used for vertex cache optimization
+const CACHE_SIZE: usize = 32;
+
+/// Tom Forsyth's vertex score: recently used vertices and vertices with few triangles left
+/// score high, so triangles around them get emitted first
+fn vertex_score(cache_position: Option<usize>, remaining_triangles: u32) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_position {
+        // the last triangle's vertices get a fixed score so strips don't get favoured
+        Some(position) if position < 3 => 0.75,
+        Some(position) => {
+            let scaler = 1.0 / (CACHE_SIZE - 3) as f32;
+            (1.0 - (position - 3) as f32 * scaler).powf(1.5)
+        }
+        None => 0.0,
+    };
+
+    cache_score + 2.0 * (remaining_triangles as f32).powf(-0.5)
+}
+
+/// reorders the triangles of `indices` so that consecutive triangles share vertices,
+/// which lets the GPU reuse already transformed vertices
+pub fn optimize_vertex_cache(indices: &mut [u32], vertex_count: usize) {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return;
+    }
+
+    // triangles around each vertex, packed into one array
+    let mut remaining = vec![0u32; vertex_count];
+    for index in indices.iter() {
+        remaining[*index as usize] += 1;
+    }
+    let mut offsets = vec![0usize; vertex_count + 1];
+    for v in 0..vertex_count {
+        offsets[v + 1] = offsets[v] + remaining[v] as usize;
+    }
+    let mut adjacency = vec![0u32; indices.len()];
+    let mut filled = offsets.clone();
+    for (i, index) in indices.iter().enumerate() {
+        adjacency[filled[*index as usize]] = (i / 3) as u32;
+        filled[*index as usize] += 1;
+    }
+
+    let mut cache_position: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut scores: Vec<f32> = (0..vertex_count)
+        .map(|v| vertex_score(None, remaining[v]))
+        .collect();
+    let triangle_score = |scores: &[f32], t: usize| {
+        scores[indices[t * 3] as usize]
+            + scores[indices[t * 3 + 1] as usize]
+            + scores[indices[t * 3 + 2] as usize]
+    };
+
+    let mut emitted = vec![false; triangle_count];
+    let mut output = Vec::with_capacity(indices.len());
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut cursor = 0;
+
+    while output.len() < indices.len() {
+        // the best triangle touching the cache, or the next unused one if the cache is cold
+        let mut best: Option<(usize, f32)> = None;
+        for v in &cache {
+            let v = *v as usize;
+            for t in &adjacency[offsets[v]..offsets[v + 1]] {
+                let t = *t as usize;
+                if emitted[t] {
+                    continue;
+                }
+                let score = triangle_score(&scores, t);
+                if best.is_none_or(|(_, best_score)| score > best_score) {
+                    best = Some((t, score));
+                }
+            }
+        }
+        let triangle = match best {
+            Some((t, _)) => t,
+            None => {
+                while emitted[cursor] {
+                    cursor += 1;
+                }
+                cursor
+            }
+        };
+
+        emitted[triangle] = true;
+        let corners = [
+            indices[triangle * 3],
+            indices[triangle * 3 + 1],
+            indices[triangle * 3 + 2],
+        ];
+        output.extend_from_slice(&corners);
+        for v in corners {
+            remaining[v as usize] -= 1;
+        }
+
+        // the new triangle moves to the front of the LRU cache
+        let evicted: Vec<u32> = {
+            let mut next: Vec<u32> = corners.to_vec();
+            next.extend(cache.iter().filter(|v| !corners.contains(v)));
+            let evicted = next.split_off(CACHE_SIZE.min(next.len()));
+            cache = next;
+            evicted
+        };
+        for v in evicted {
+            cache_position[v as usize] = None;
+            scores[v as usize] = vertex_score(None, remaining[v as usize]);
+        }
+        for (position, v) in cache.iter().enumerate() {
+            cache_position[*v as usize] = Some(position);
+            scores[*v as usize] = vertex_score(Some(position), remaining[*v as usize]);
+        }
+    }
+
+    indices.copy_from_slice(&output);
+}
+
+/// sorts clusters of triangles so the ones facing away from the mesh center are drawn first,
+/// they tend to occlude the rest and cut down on overdraw, keeps the cache order within clusters
+pub fn optimize_overdraw(indices: &mut [u32], vertices: &[VertexData]) {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return;
+    }
+
+    // a new cluster starts wherever a triangle misses the cache with all three vertices
+    let mut clusters = vec![0];
+    let mut cache: Vec<u32> = Vec::with_capacity(16);
+    for t in 0..triangle_count {
+        let corners = &indices[t * 3..t * 3 + 3];
+        let misses = corners.iter().filter(|v| !cache.contains(v)).count();
+        if misses == 3 && t > 0 {
+            clusters.push(t);
+        }
+        for v in corners {
+            if !cache.contains(v) {
+                if cache.len() == 16 {
+                    cache.remove(0);
+                }
+                cache.push(*v);
+            }
+        }
+    }
+    clusters.push(triangle_count);
+
+    let position = |index: u32| vertices[index as usize].position;
+    let center = indices.iter().map(|i| position(*i)).sum::<Vec3>() / indices.len() as f32;
+
+    let mut keyed: Vec<(f32, std::ops::Range<usize>)> = clusters
+        .windows(2)
+        .map(|bounds| {
+            let mut centroid = Vec3::ZERO;
+            let mut normal = Vec3::ZERO;
+            let mut area = 0.0;
+            for t in bounds[0]..bounds[1] {
+                let a = position(indices[t * 3]);
+                let b = position(indices[t * 3 + 1]);
+                let c = position(indices[t * 3 + 2]);
+                let n = (b - a).cross(c - a);
+                let weight = n.length();
+                centroid += (a + b + c) / 3.0 * weight;
+                normal += n;
+                area += weight;
+            }
+            let centroid = if area > 0.0 { centroid / area } else { center };
+            let key = (centroid - center).dot(normal.normalize_or_zero());
+            (key, bounds[0] * 3..bounds[1] * 3)
+        })
+        .collect();
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let output: Vec<u32> = keyed
+        .iter()
+        .flat_map(|(_, range)| indices[range.clone()].to_vec())
+        .collect();
+    indices.copy_from_slice(&output);
+}
+
+/// renumbers vertices in the order the indices first use them, so vertex fetches stay linear
+pub fn optimize_vertex_fetch(vertices: &mut Vec<VertexData>, indices: &mut [u32]) {
+    let mut remap = vec![u32::MAX; vertices.len()];
+    let mut reordered = Vec::with_capacity(vertices.len());
+    for index in indices.iter_mut() {
+        let old = *index as usize;
+        if remap[old] == u32::MAX {
+            remap[old] = reordered.len() as u32;
+            reordered.push(vertices[old]);
+        }
+        *index = remap[old];
+    }
+    *vertices = reordered;
+}
+
+/// average cache miss ratio, transformed vertices per triangle with a FIFO cache of `cache_size`
+pub fn average_cache_miss_ratio(indices: &[u32], cache_size: usize) -> f32 {
+    let mut cache: Vec<u32> = Vec::with_capacity(cache_size);
+    let mut misses = 0;
+    for index in indices {
+        if !cache.contains(index) {
+            misses += 1;
+            if cache.len() == cache_size {
+                cache.remove(0);
+            }
+            cache.push(*index);
+        }
+    }
+    misses as f32 / (indices.len() / 3).max(1) as f32
+}
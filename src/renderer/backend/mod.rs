@@ -1,6 +1,21 @@
+pub mod assets;
+pub mod bake;
 pub mod bind_group;
 pub mod bind_group_layout;
+pub mod culling;
 pub mod definitions;
+pub mod gpu_culling;
+pub mod hi_z;
+pub mod instances;
+pub mod lights;
+pub mod materials;
 pub mod mesh_builder;
+pub mod mesh_cache;
+pub mod mesh_optimizer;
 pub mod pipeline;
+pub mod shader_preprocessor;
+pub mod shader_watcher;
+pub mod shadows;
+pub mod slot_map;
 pub mod texture;
+pub mod transparency;
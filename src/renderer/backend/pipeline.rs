@@ -1,34 +1,115 @@
-use std::env::current_dir;
-use std::fs;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 
-use wgpu::include_wgsl;
+use include_dir::{Dir, include_dir};
+
+use super::assets::{AssetSource, EmbeddedAssets};
+use super::definitions::MaterialFeatures;
+use super::shader_preprocessor::preprocess;
+
+/// the shaders as they were when the crate was compiled
+static SHADERS_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/src/shaders");
+
+/// the default shader source, works wherever the binary is started from
+pub fn embedded_shaders() -> EmbeddedAssets {
+    EmbeddedAssets::new(&SHADERS_DIR)
+}
+
+/// preprocesses and compiles `shader_filename` read from `shaders`, with the features
+/// in `defines` turned on
+pub fn load_shader_module(
+    device: &wgpu::Device,
+    shaders: &dyn AssetSource,
+    shader_filename: &str,
+    defines: &[&str],
+) -> wgpu::ShaderModule {
+    try_load_shader_module(device, shaders, shader_filename, defines).unwrap()
+}
+
+/// like `load_shader_module`, but missing files and bad directives are errors instead of panics
+pub fn try_load_shader_module(
+    device: &wgpu::Device,
+    shaders: &dyn AssetSource,
+    shader_filename: &str,
+    defines: &[&str],
+) -> Result<wgpu::ShaderModule, String> {
+    let source_code = preprocess(shaders, shader_filename, defines)?.code;
+
+    let shader_module_descriptor = wgpu::ShaderModuleDescriptor {
+        label: Some(shader_filename),
+        source: wgpu::ShaderSource::Wgsl(source_code.into()),
+    };
+    Ok(device.create_shader_module(shader_module_descriptor))
+}
+
+/// runs `create` and returns the validation errors it raised (bad WGSL, mismatched layouts)
+/// instead of letting them reach the device's panicking error handler
+pub fn capture_errors<T>(
+    device: &wgpu::Device,
+    create: impl FnOnce() -> Result<T, String>,
+) -> Result<T, String> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let created = create();
+    match pollster::block_on(device.pop_error_scope()) {
+        Some(error) => Err(error.to_string()),
+        None => created,
+    }
+}
+
+/// the view-projection matrix pushed for every draw
+pub const PUSH_CONSTANT_RANGE: wgpu::PushConstantRange = wgpu::PushConstantRange {
+    stages: wgpu::ShaderStages::VERTEX,
+    range: 0..64,
+};
 
 pub struct Builder<'a> {
     shader_filename: String,
     vertex_entry: String,
     fragment_entry: String,
+    /// features turned on for the shader's `#ifdef`s
+    defines: Vec<String>,
     pixel_format: wgpu::TextureFormat,
+    blend: wgpu::BlendState,
+    /// written instead of a single `pixel_format` target when set
+    color_targets: Option<Vec<wgpu::ColorTargetState>>,
+    depth_compare: wgpu::CompareFunction,
+    depth_write: bool,
+    cull_mode: Option<wgpu::Face>,
+    depth_bias: wgpu::DepthBiasState,
+    /// no color target, and no fragment stage unless a fragment entry is set
+    depth_only: bool,
     vertex_buffer_layouts: Vec<wgpu::VertexBufferLayout<'static>>,
     bind_group_layouts: Vec<&'a wgpu::BindGroupLayout>,
     device: &'a wgpu::Device,
+    shaders: &'a dyn AssetSource,
 }
 
 impl<'a> Builder<'a> {
-    pub fn new(device: &'a wgpu::Device) -> Self {
+    pub fn new(device: &'a wgpu::Device, shaders: &'a dyn AssetSource) -> Self {
         Builder {
             shader_filename: "dummy".to_string(),
             vertex_entry: "dummy".to_string(),
             fragment_entry: "dummy".to_string(),
+            defines: Vec::new(),
             pixel_format: wgpu::TextureFormat::Rgba8Unorm,
+            blend: wgpu::BlendState::REPLACE,
+            color_targets: None,
+            depth_compare: wgpu::CompareFunction::Less,
+            depth_write: true,
+            cull_mode: Some(wgpu::Face::Back),
+            depth_bias: wgpu::DepthBiasState::default(),
+            depth_only: false,
             vertex_buffer_layouts: Vec::new(),
             bind_group_layouts: Vec::new(),
-            device: device,
+            device,
+            shaders,
         }
     }
 
     fn reset(&mut self) {
         self.vertex_buffer_layouts.clear();
         self.bind_group_layouts.clear();
+        self.defines.clear();
     }
 
     pub fn add_vertex_buffer_layout(&mut self, layout: wgpu::VertexBufferLayout<'static>) {
@@ -39,6 +120,8 @@ impl<'a> Builder<'a> {
         self.bind_group_layouts.push(layout);
     }
 
+    /// `shader_filename` goes through `shader_preprocessor::preprocess`, so its `#include`s
+    /// are resolved relative to it
     pub fn set_shader_module(
         &mut self,
         shader_filename: &str,
@@ -50,49 +133,93 @@ impl<'a> Builder<'a> {
         self.fragment_entry = fragment_entry.to_string();
     }
 
+    /// turns on `#ifdef define` blocks of the shader module for the next pipeline
+    pub fn add_define(&mut self, define: &str) {
+        self.defines.push(define.to_string());
+    }
+
     pub fn set_pixel_format(&mut self, pixel_format: wgpu::TextureFormat) {
         self.pixel_format = pixel_format;
     }
 
+    /// how the fragments are combined with the `pixel_format` target, `REPLACE` by default
+    pub fn set_blend_state(&mut self, blend: wgpu::BlendState) {
+        self.blend = blend;
+    }
+
+    /// the fragment stage writes `targets` instead of a single `pixel_format` target
+    pub fn set_color_targets(&mut self, targets: &[wgpu::ColorTargetState]) {
+        self.color_targets = Some(targets.to_vec());
+    }
+
+    /// `Greater` for reverse-Z projections
+    pub fn set_depth_compare(&mut self, depth_compare: wgpu::CompareFunction) {
+        self.depth_compare = depth_compare;
+    }
+
+    /// off for surfaces that must not hide what is drawn after them, e.g. blended ones
+    pub fn set_depth_write(&mut self, depth_write: bool) {
+        self.depth_write = depth_write;
+    }
+
+    /// `None` draws back faces too
+    pub fn set_cull_mode(&mut self, cull_mode: Option<wgpu::Face>) {
+        self.cull_mode = cull_mode;
+    }
+
+    /// offsets the depth written, by `slope_scale` times the depth slope of each triangle plus
+    /// `constant` of the smallest depth steps
+    pub fn set_depth_bias(&mut self, depth_bias: wgpu::DepthBiasState) {
+        self.depth_bias = depth_bias;
+    }
+
+    /// only writes depth, e.g. for shadow maps. a fragment entry, if one is set, can only
+    /// discard fragments
+    pub fn set_depth_only(&mut self, depth_only: bool) {
+        self.depth_only = depth_only;
+    }
+
     pub fn build(&mut self, label: &str) -> wgpu::RenderPipeline {
-        let mut filepath = current_dir().unwrap();
-        filepath.push("src/");
-        filepath.push(self.shader_filename.as_str());
-        let filepath = filepath.into_os_string().into_string().unwrap();
-        let source_code = fs::read_to_string(filepath).expect("Can't read source code!");
-
-        let shader_module_descriptor = wgpu::ShaderModuleDescriptor {
-            label: Some("Shader Module"),
-            source: wgpu::ShaderSource::Wgsl(source_code.into()),
-        };
-        let shader_module = self.device.create_shader_module(shader_module_descriptor);
+        self.try_build(label)
+            .unwrap_or_else(|error| panic!("{}: {}", label, error))
+    }
 
-        let push_constant_range = wgpu::PushConstantRange {
-            stages: wgpu::ShaderStages::VERTEX, // vertex shader needs it
-            range: 0..64,
-        };
+    /// like `build`, but shader errors are returned instead of panicking
+    pub fn try_build(&mut self, label: &str) -> Result<wgpu::RenderPipeline, String> {
+        let pipeline = capture_errors(self.device, || self.create(label));
+        self.reset();
+        pipeline
+    }
+
+    fn create(&self, label: &str) -> Result<wgpu::RenderPipeline, String> {
+        let defines: Vec<&str> = self.defines.iter().map(String::as_str).collect();
+        let shader_module =
+            try_load_shader_module(self.device, self.shaders, &self.shader_filename, &defines)?;
 
         let pipeline_layout_descriptor = wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
             bind_group_layouts: &self.bind_group_layouts, // textures etc
-            push_constant_ranges: &[push_constant_range],
+            push_constant_ranges: &[PUSH_CONSTANT_RANGE],
         };
         let pipeline_layout: wgpu::PipelineLayout = self
             .device
             .create_pipeline_layout(&pipeline_layout_descriptor);
 
-        let render_targets = [Some(wgpu::ColorTargetState {
-            format: self.pixel_format,
-            blend: Some(wgpu::BlendState::REPLACE),
-            write_mask: wgpu::ColorWrites::ALL,
-        })];
+        let render_targets: Vec<Option<wgpu::ColorTargetState>> = match &self.color_targets {
+            Some(targets) => targets.iter().cloned().map(Some).collect(),
+            None => vec![Some(wgpu::ColorTargetState {
+                format: self.pixel_format,
+                blend: Some(self.blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        };
 
         let depth_stencil = wgpu::DepthStencilState {
             format: wgpu::TextureFormat::Depth32Float,
-            depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::Less,
+            depth_write_enabled: self.depth_write,
+            depth_compare: self.depth_compare,
             stencil: wgpu::StencilState::default(),
-            bias: wgpu::DepthBiasState::default(),
+            bias: self.depth_bias,
         };
 
         let render_pipeline_descriptor = wgpu::RenderPipelineDescriptor {
@@ -112,17 +239,23 @@ impl<'a> Builder<'a> {
                 topology: wgpu::PrimitiveTopology::TriangleList,
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back), // backface culling
+                cull_mode: self.cull_mode,
                 polygon_mode: wgpu::PolygonMode::Fill,
                 unclipped_depth: false,
                 conservative: false,
             },
 
-            fragment: Some(wgpu::FragmentState {
-                module: &shader_module,
-                entry_point: Some(&self.fragment_entry),
-                targets: &render_targets,
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            fragment: (!self.depth_only || !self.fragment_entry.is_empty()).then(|| {
+                wgpu::FragmentState {
+                    module: &shader_module,
+                    entry_point: Some(&self.fragment_entry),
+                    targets: if self.depth_only {
+                        &[]
+                    } else {
+                        &render_targets
+                    },
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }
             }),
 
             depth_stencil: Some(depth_stencil),
@@ -134,12 +267,55 @@ impl<'a> Builder<'a> {
             multiview: None,
         };
 
-        let pipeline = self
+        Ok(self
             .device
-            .create_render_pipeline(&render_pipeline_descriptor);
+            .create_render_pipeline(&render_pipeline_descriptor))
+    }
+}
 
-        self.reset();
+/// model pipelines by the material features they were built for
+#[derive(Default)]
+pub struct PipelineCache {
+    pipelines: HashMap<MaterialFeatures, wgpu::RenderPipeline>,
+}
 
-        pipeline
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the pipeline for `features`, built with `build` by the first material that needs it.
+    /// if `build` fails its error is returned and the next material tries again
+    pub fn get_or_build(
+        &mut self,
+        features: MaterialFeatures,
+        build: impl FnOnce() -> Result<wgpu::RenderPipeline, String>,
+    ) -> Result<&wgpu::RenderPipeline, String> {
+        match self.pipelines.entry(features) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => Ok(entry.insert(build()?)),
+        }
+    }
+
+    pub fn get(&self, features: MaterialFeatures) -> Option<&wgpu::RenderPipeline> {
+        self.pipelines.get(&features)
+    }
+
+    /// replaces the pipeline of `features`, after a shader edit or a depth convention change
+    pub fn insert(&mut self, features: MaterialFeatures, pipeline: wgpu::RenderPipeline) {
+        self.pipelines.insert(features, pipeline);
+    }
+
+    /// every feature set a pipeline was built for
+    pub fn features(&self) -> Vec<MaterialFeatures> {
+        self.pipelines.keys().copied().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.is_empty()
     }
 }
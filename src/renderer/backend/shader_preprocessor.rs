@@ -0,0 +1,138 @@
+use std::collections::HashSet;
+
+use super::assets::{AssetSource, sibling};
+
+/// WGSL after `preprocess`, ready for `create_shader_module`
+pub struct PreprocessedShader {
+    pub code: String,
+    /// the shader and every file it included, relative to the shader source
+    pub files: Vec<String>,
+}
+
+/// one `#ifdef`/`#ifndef` block
+struct Condition {
+    /// the block's own condition holds
+    taken: bool,
+    /// every enclosing block is active
+    parent_active: bool,
+    /// the block's `#else` was passed
+    in_else: bool,
+}
+
+struct Preprocessor<'a> {
+    shaders: &'a dyn AssetSource,
+    defines: HashSet<String>,
+    files: Vec<String>,
+    code: String,
+}
+
+/// resolves the directives of `filename`:
+///
+/// - `#include "file.wgsl"` pastes a file, relative to the including one. every file is
+///   included once, later includes of it are skipped
+/// - `#define NAME` turns a feature on for the rest of the shader, `defines` start out on
+/// - `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif` keep or drop the lines between them
+pub fn preprocess(
+    shaders: &dyn AssetSource,
+    filename: &str,
+    defines: &[&str],
+) -> Result<PreprocessedShader, String> {
+    let mut preprocessor = Preprocessor {
+        shaders,
+        defines: defines.iter().map(|define| define.to_string()).collect(),
+        files: Vec::new(),
+        code: String::new(),
+    };
+    preprocessor.include(filename)?;
+
+    Ok(PreprocessedShader {
+        code: preprocessor.code,
+        files: preprocessor.files,
+    })
+}
+
+/// the single argument of a directive, quotes around it are dropped
+fn argument<'l>(filename: &str, number: usize, rest: &'l str) -> Result<&'l str, String> {
+    let argument = rest.trim().trim_matches('"');
+    match argument.is_empty() || argument.contains(char::is_whitespace) {
+        true => Err(format!("{}:{}: expected one argument", filename, number)),
+        false => Ok(argument),
+    }
+}
+
+impl Preprocessor<'_> {
+    fn include(&mut self, filename: &str) -> Result<(), String> {
+        if self.files.iter().any(|file| file == filename) {
+            return Ok(());
+        }
+        self.files.push(filename.to_string());
+
+        let source = self
+            .shaders
+            .read_to_string(filename)
+            .ok_or_else(|| format!("Can't read source code of {}!", filename))?;
+
+        let mut conditions: Vec<Condition> = Vec::new();
+        for (i, line) in source.lines().enumerate() {
+            let number = i + 1;
+            let active = conditions.last().is_none_or(|c| c.taken && c.parent_active);
+
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if active {
+                    self.code.push_str(line);
+                    self.code.push('\n');
+                }
+                continue;
+            };
+
+            let (name, rest) = directive.split_once(' ').unwrap_or((directive, ""));
+            let name = name.trim();
+            match name {
+                "ifdef" | "ifndef" => {
+                    let feature = argument(filename, number, rest)?;
+                    conditions.push(Condition {
+                        taken: self.defines.contains(feature) == (name == "ifdef"),
+                        parent_active: active,
+                        in_else: false,
+                    });
+                }
+                "else" => match conditions.last_mut() {
+                    Some(condition) if condition.in_else => {
+                        return Err(format!("{}:{}: second #else in a block", filename, number));
+                    }
+                    Some(condition) => {
+                        condition.taken = !condition.taken;
+                        condition.in_else = true;
+                    }
+                    None => return Err(format!("{}:{}: #else without #ifdef", filename, number)),
+                },
+                "endif" => {
+                    if conditions.pop().is_none() {
+                        return Err(format!("{}:{}: #endif without #ifdef", filename, number));
+                    }
+                }
+                "define" if active => {
+                    let feature = argument(filename, number, rest)?;
+                    self.defines.insert(feature.to_string());
+                }
+                "include" if active => {
+                    let path = sibling(filename, argument(filename, number, rest)?);
+                    self.include(&path)
+                        .map_err(|error| format!("{}:{}: {}", filename, number, error))?;
+                }
+                "define" | "include" => {}
+                other => {
+                    return Err(format!(
+                        "{}:{}: unknown directive #{}",
+                        filename, number, other
+                    ));
+                }
+            }
+        }
+
+        match conditions.is_empty() {
+            true => Ok(()),
+            false => Err(format!("{}: #ifdef without #endif", filename)),
+        }
+    }
+}
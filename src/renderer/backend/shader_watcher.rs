@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::assets::normalize;
+
+/// notices edits to the `.wgsl` files below a directory by polling their modification times,
+/// a handful of `stat` calls per frame
+pub struct ShaderWatcher {
+    dir: PathBuf,
+    modified: HashMap<String, SystemTime>,
+}
+
+impl ShaderWatcher {
+    /// starts watching `dir`, the files as they are now count as seen
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let mut watcher = ShaderWatcher {
+            dir: dir.into(),
+            modified: HashMap::new(),
+        };
+        watcher.changed();
+        watcher
+    }
+
+    /// shaders written since the last call, relative to the watched directory
+    pub fn changed(&mut self) -> Vec<String> {
+        let mut changed = Vec::new();
+        let dir = self.dir.clone();
+        self.scan(&dir, &mut changed);
+        changed.sort();
+        changed
+    }
+
+    /// checks the shaders in `dir` and its subdirectories, where includes live
+    fn scan(&mut self, dir: &Path, changed: &mut Vec<String>) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                self.scan(&path, changed);
+                continue;
+            }
+            if path.extension().is_none_or(|e| e != "wgsl") {
+                continue;
+            }
+            let Ok(modified) = entry.metadata().and_then(|m| m.modified()) else {
+                continue;
+            };
+
+            let name = normalize(path.strip_prefix(&self.dir).unwrap());
+            if self.modified.insert(name.clone(), modified) != Some(modified) {
+                changed.push(name);
+            }
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use glam::*;
+
+use super::assets::AssetSource;
+use super::definitions::{Camera, InstanceData, VertexData};
+use super::lights::{Light, LightKind};
+use super::pipeline;
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+/// the shadow pass shader, relative to the shader source
+pub const SHADER: &str = "shadow.wgsl";
+
+/// how many shadow maps the view distance is split into, each covers a slice of the
+/// camera frustum further away and at a lower resolution than the one before
+pub const CASCADE_COUNT: usize = 4;
+
+/// width and height of each cascade's shadow map until `RendererState::set_shadow_map_size`
+pub const DEFAULT_MAP_SIZE: u32 = 1024;
+
+/// how far from the camera shadows reach until `RendererState::set_shadow_distance`
+pub const DEFAULT_DISTANCE: f32 = 500.0;
+
+/// 0 spaces the cascade splits evenly, 1 logarithmically. in between keeps the near
+/// cascades sharp without squeezing the far ones to nothing
+const SPLIT_LAMBDA: f32 = 0.75;
+
+/// the light the shadow maps are drawn for: the first directional light in `lights` that
+/// casts shadows. point and spot lights don't cast any
+pub fn shadowed_light(lights: &[Light]) -> Option<(usize, Vec3)> {
+    lights
+        .iter()
+        .enumerate()
+        .find_map(|(i, light)| match light.kind {
+            LightKind::Directional { direction } if light.cast_shadows => {
+                Some((i, direction.normalize_or(Vec3::NEG_Z)))
+            }
+            _ => None,
+        })
+}
+
+/// the view depths the cascades end at, the last one at `far`
+pub fn cascade_splits(near: f32, far: f32) -> [f32; CASCADE_COUNT] {
+    std::array::from_fn(|i| {
+        let t = (i + 1) as f32 / CASCADE_COUNT as f32;
+        let logarithmic = near * (far / near).powf(t);
+        let uniform = near + (far - near) * t;
+        SPLIT_LAMBDA * logarithmic + (1.0 - SPLIT_LAMBDA) * uniform
+    })
+}
+
+/// where each cascade's shadow map is drawn from, see `fit_cascades`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cascades {
+    /// world space to the clip space of each cascade's shadow map
+    pub view_projections: [Mat4; CASCADE_COUNT],
+    /// the view depth each cascade ends at
+    pub splits: [f32; CASCADE_COUNT],
+    /// the world size of a shadow map texel in each cascade
+    pub texel_sizes: [f32; CASCADE_COUNT],
+}
+
+/// the four world space corners of the camera frustum's cross section at view depth `depth`
+fn frustum_corners(camera: &Camera, aspect: f32, depth: f32) -> [Vec3; 4] {
+    let clip = camera.projection.matrix(aspect) * Vec4::new(0.0, 0.0, -depth, 1.0);
+    let z = clip.z / clip.w;
+    let inverse = camera.view_projection(aspect).inverse();
+    [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+        .map(|(x, y)| inverse.project_point3(Vec3::new(x, y, z)))
+}
+
+/// fits one orthographic shadow map per cascade around its slice of the camera frustum,
+/// looking along `direction` (the way the light travels) and reaching `distance` towards
+/// the light for occluders outside the frustum.
+/// each map bounds a sphere around its slice and moves in whole texels, so shadow edges
+/// stay put while the camera turns and moves
+pub fn fit_cascades(
+    camera: &Camera,
+    aspect: f32,
+    direction: Vec3,
+    distance: f32,
+    map_size: u32,
+) -> Cascades {
+    let (near, far) = camera.projection.depth_range(distance);
+    let near = near.max(0.01);
+    let splits = cascade_splits(near, far.min(distance).max(near * 2.0));
+
+    // z is up, unless the light shines straight up or down
+    let up = match direction.z.abs() > 0.99 {
+        true => Vec3::Y,
+        false => Vec3::Z,
+    };
+    let light_view = Mat4::look_to_rh(Vec3::ZERO, direction, up);
+
+    let mut view_projections = [Mat4::IDENTITY; CASCADE_COUNT];
+    let mut texel_sizes = [0.0; CASCADE_COUNT];
+    let mut slice_start = frustum_corners(camera, aspect, near);
+    for (i, split) in splits.iter().enumerate() {
+        let slice_end = frustum_corners(camera, aspect, *split);
+        let corners = [slice_start, slice_end].concat();
+        slice_start = slice_end;
+
+        let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+        let radius = corners
+            .iter()
+            .map(|corner| corner.distance(center))
+            .fold(0.0, f32::max);
+        // in steps, so the texel size doesn't change with the camera's rotation
+        let radius = (radius * 16.0).ceil() / 16.0;
+        let texel_size = 2.0 * radius / map_size as f32;
+
+        let mut center = light_view.transform_point3(center);
+        center.x = (center.x / texel_size).floor() * texel_size;
+        center.y = (center.y / texel_size).floor() * texel_size;
+
+        let projection = Mat4::orthographic_rh(
+            center.x - radius,
+            center.x + radius,
+            center.y - radius,
+            center.y + radius,
+            -center.z - radius - distance,
+            -center.z + radius,
+        );
+        view_projections[i] = projection * light_view;
+        texel_sizes[i] = texel_size;
+    }
+
+    Cascades {
+        view_projections,
+        splits,
+        texel_sizes,
+    }
+}
+
+/// `Cascades` and the shadowed light as the model shader reads them, `Shadows` in
+/// include/shadows.wgsl
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniforms {
+    view_projections: [[[f32; 4]; 4]; CASCADE_COUNT],
+    splits: [f32; CASCADE_COUNT],
+    texel_sizes: [f32; CASCADE_COUNT],
+    /// index of the shadowed light in the light buffer, `u32::MAX` if no light casts shadows
+    light: u32,
+    map_size: f32,
+    padding: [u32; 2],
+}
+
+/// one depth map per cascade, drawn from the shadowed light every frame
+pub struct ShadowMaps {
+    texture: wgpu::Texture,
+    /// every cascade, sampled by the model shader
+    pub view: wgpu::TextureView,
+    /// one cascade each, rendered by the shadow pass
+    pub cascade_views: Vec<wgpu::TextureView>,
+    /// compares against the stored depth, filtering the results of neighbouring texels
+    pub sampler: wgpu::Sampler,
+    /// `ShadowUniforms`
+    pub uniforms: wgpu::Buffer,
+    map_size: u32,
+}
+
+impl ShadowMaps {
+    pub fn new(device: &wgpu::Device, map_size: u32) -> Self {
+        let map_size = map_size.max(1);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow Maps"),
+            size: wgpu::Extent3d {
+                width: map_size,
+                height: map_size,
+                depth_or_array_layers: CASCADE_COUNT as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let cascade_views = (0..CASCADE_COUNT as u32)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Shadow Cascade"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let uniforms = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Uniforms"),
+            size: std::mem::size_of::<ShadowUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        ShadowMaps {
+            texture,
+            view,
+            cascade_views,
+            sampler,
+            uniforms,
+            map_size,
+        }
+    }
+
+    pub fn map_size(&self) -> u32 {
+        self.map_size
+    }
+
+    /// points the model shader at `cascades` of the light at `light` in the light buffer,
+    /// `None` turns shadows off
+    pub fn write_uniforms(
+        &self,
+        queue: &wgpu::Queue,
+        cascades: Option<&Cascades>,
+        light: Option<usize>,
+    ) {
+        let mut uniforms = ShadowUniforms {
+            view_projections: [[[0.0; 4]; 4]; CASCADE_COUNT],
+            splits: [0.0; CASCADE_COUNT],
+            texel_sizes: [0.0; CASCADE_COUNT],
+            light: u32::MAX,
+            map_size: self.map_size as f32,
+            padding: [0; 2],
+        };
+        if let (Some(cascades), Some(light)) = (cascades, light) {
+            uniforms.view_projections = cascades.view_projections.map(|m| m.to_cols_array_2d());
+            uniforms.splits = cascades.splits;
+            uniforms.texel_sizes = cascades.texel_sizes;
+            uniforms.light = light as u32;
+        }
+        queue.write_buffer(&self.uniforms, 0, bytemuck::bytes_of(&uniforms));
+    }
+
+    pub fn destroy(&self) {
+        self.texture.destroy();
+    }
+}
+
+/// the depth-only pipeline every instance is drawn into the shadow maps with, the cascade's
+/// view-projection is pushed in place of the camera's. with the `material_layout`, the variant
+/// for `AlphaMode::Mask` materials, which cuts their shadows out like their surfaces
+pub fn build_pipeline(
+    device: &wgpu::Device,
+    shaders: &dyn AssetSource,
+    material_layout: Option<&wgpu::BindGroupLayout>,
+) -> Result<wgpu::RenderPipeline, String> {
+    let mut pb = pipeline::Builder::new(device, shaders);
+    match material_layout {
+        Some(layout) => {
+            pb.set_shader_module(SHADER, "vs_main", "fs_main");
+            pb.add_define("ALPHA_MASK");
+            pb.add_bind_group_layout(layout);
+        }
+        None => pb.set_shader_module(SHADER, "vs_main", ""),
+    }
+    pb.set_depth_only(true);
+    // open meshes and double sided materials cast shadows from both sides
+    pb.set_cull_mode(None);
+    // keeps lit surfaces from shadowing themselves where they face away from the light
+    pb.set_depth_bias(wgpu::DepthBiasState {
+        constant: 2,
+        slope_scale: 2.0,
+        clamp: 0.0,
+    });
+    pb.add_vertex_buffer_layout(VertexData::get_layout());
+    pb.add_vertex_buffer_layout(InstanceData::get_layout());
+    pb.try_build(match material_layout {
+        Some(_) => "Masked Shadow Pipeline",
+        None => "Shadow Pipeline",
+    })
+}
@@ -0,0 +1,124 @@
+/// a stable reference to a value in a `SlotMap`.
+/// stays valid until the value is removed, after which it is rejected instead of aliasing
+/// whatever reuses its slot
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SlotKey {
+    slot: u32,
+    generation: u32,
+}
+
+/// where a key's value currently lives
+struct Slot {
+    generation: u32,
+    index: u32,
+    alive: bool,
+}
+
+/// values packed without holes so they can be uploaded as they are, addressed by
+/// generational keys. removal swap-removes, so only the moved value's slot changes
+pub struct SlotMap<T> {
+    values: Vec<T>,
+    /// slot that owns each entry of `values`, used to patch keys after a swap-remove
+    owners: Vec<u32>,
+    slots: Vec<Slot>,
+    free_slots: Vec<u32>,
+}
+
+impl<T> Default for SlotMap<T> {
+    fn default() -> Self {
+        SlotMap {
+            values: Vec::new(),
+            owners: Vec::new(),
+            slots: Vec::new(),
+            free_slots: Vec::new(),
+        }
+    }
+}
+
+impl<T> SlotMap<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// appends `value`, reusing a freed slot when there is one
+    pub fn insert(&mut self, value: T) -> SlotKey {
+        let index = self.values.len() as u32;
+        let slot = match self.free_slots.pop() {
+            Some(slot) => {
+                let s = &mut self.slots[slot as usize];
+                s.index = index;
+                s.alive = true;
+                slot
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    index,
+                    alive: true,
+                });
+                self.slots.len() as u32 - 1
+            }
+        };
+
+        self.values.push(value);
+        self.owners.push(slot);
+
+        SlotKey {
+            slot,
+            generation: self.slots[slot as usize].generation,
+        }
+    }
+
+    /// where the key's value is in `values`, `None` for stale keys
+    pub fn index(&self, key: SlotKey) -> Option<usize> {
+        self.slots
+            .get(key.slot as usize)
+            .filter(|s| s.alive && s.generation == key.generation)
+            .map(|s| s.index as usize)
+    }
+
+    pub fn contains(&self, key: SlotKey) -> bool {
+        self.index(key).is_some()
+    }
+
+    pub fn get(&self, key: SlotKey) -> Option<&T> {
+        Some(&self.values[self.index(key)?])
+    }
+
+    pub fn get_mut(&mut self, key: SlotKey) -> Option<&mut T> {
+        let index = self.index(key)?;
+        Some(&mut self.values[index])
+    }
+
+    /// removes the key's value, moving the last one into the hole. returns the index the
+    /// value was at, which now holds the moved value unless the removed one was last
+    pub fn remove(&mut self, key: SlotKey) -> Option<usize> {
+        let index = self.index(key)?;
+
+        self.values.swap_remove(index);
+        self.owners.swap_remove(index);
+        if let Some(&moved) = self.owners.get(index) {
+            self.slots[moved as usize].index = index as u32;
+        }
+
+        let slot = &mut self.slots[key.slot as usize];
+        slot.alive = false;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free_slots.push(key.slot);
+
+        Some(index)
+    }
+
+    /// every value, in insertion order until something is removed
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
@@ -1,10 +1,13 @@
-use crate::renderer::backend::mesh_builder::any_as_u8_slice;
-use std::env::current_dir;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::path::Path;
 use wgpu::util::DeviceExt;
 
-use glam::*;
-
+use super::assets::{AssetSource, normalize};
+use super::bake::{CompiledTexture, TEXTURE_EXTENSION};
 use super::bind_group;
+use super::definitions::Material;
+use super::mesh_cache::decode_compressed;
 
 pub struct Texture {
     pub texture: wgpu::Texture,
@@ -29,7 +32,8 @@ pub fn new_depth_texture(
         sample_count: 1,
         dimension: wgpu::TextureDimension::D2,
         format: wgpu::TextureFormat::Depth32Float,
-        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        // sampled by the Hi-Z pyramid build
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
         view_formats: &[],
     };
     let texture = device.create_texture(&descriptor);
@@ -39,24 +43,76 @@ pub fn new_depth_texture(
     Texture { texture, view }
 }
 
-pub fn new_texture(
+/// a color texture that can be rendered to and copied out of, used for offscreen rendering
+pub fn new_color_target(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+    label: &str,
+) -> Texture {
+    let size = wgpu::Extent3d {
+        width: config.width.max(1),
+        height: config.height.max(1),
+        depth_or_array_layers: 1,
+    };
+
+    let descriptor = wgpu::TextureDescriptor {
+        label: Some(label),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[],
+    };
+    let texture = device.create_texture(&descriptor);
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    Texture { texture, view }
+}
+
+/// how the texels of a map are sampled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    /// colors as images store them, the sampler converts them to linear
+    Srgb,
+    /// data, e.g. normals or roughness, sampled as stored
+    Linear,
+}
+
+impl ColorSpace {
+    pub fn format(self) -> wgpu::TextureFormat {
+        match self {
+            ColorSpace::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+/// decodes an image from `assets` and uploads it to a sampled texture in `color_space`,
+/// textures baked by `bake` are uploaded with their mip chain
+pub fn new_image_texture(
+    assets: &dyn AssetSource,
     filename: &str,
+    color_space: ColorSpace,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     label: &str,
-    layout: &wgpu::BindGroupLayout,
-) -> wgpu::BindGroup {
-    // Get absolute filepath from relative one
-    let mut filepath = current_dir().unwrap();
-    // filepath.push("src/");
-    filepath.push(filename);
-    let filepath = filepath.into_os_string().into_string().unwrap();
-
-    #[cfg(debug_assertions)]
-    println!("new_texture attempting to read filepath: {}", filepath);
-
-    let bytes = std::fs::read(filepath).unwrap();
-    let loaded_image = image::load_from_memory(&bytes).unwrap();
+) -> Result<Texture, String> {
+    let bytes = assets
+        .read(filename)
+        .ok_or_else(|| format!("missing texture {}", filename))?;
+
+    if filename.ends_with(&format!(".{}", TEXTURE_EXTENSION)) {
+        let compiled: CompiledTexture = decode_compressed(&bytes)
+            .ok_or_else(|| format!("could not decode baked texture {}", filename))?;
+        return new_mipmapped_texture(&compiled, color_space, device, queue, label)
+            .map_err(|e| format!("{}: {}", filename, e));
+    }
+
+    let loaded_image =
+        image::load_from_memory(&bytes).map_err(|e| format!("{}: {}", filename, e))?;
     let converted = loaded_image.to_rgba8();
     use image::GenericImageView;
     let size = loaded_image.dimensions();
@@ -72,10 +128,10 @@ pub fn new_texture(
         mip_level_count: 1,
         sample_count: 1,
         dimension: wgpu::TextureDimension::D2,
-        format: wgpu::TextureFormat::Rgba8Unorm,
+        format: color_space.format(),
         usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
         label: Some(label),
-        view_formats: &[wgpu::TextureFormat::Rgba8Unorm],
+        view_formats: &[color_space.format()],
     };
     let texture = device.create_texture(&texture_descriptor);
 
@@ -99,7 +155,70 @@ pub fn new_texture(
     // Get a view of the texture
     let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
 
-    // Make a sampler
+    Ok(Texture { texture, view })
+}
+
+/// uploads every level of a baked texture in `color_space`, levels that don't hold the
+/// pixels of their size are an error
+pub fn new_mipmapped_texture(
+    compiled: &CompiledTexture,
+    color_space: ColorSpace,
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    label: &str,
+) -> Result<Texture, String> {
+    let texture_size = wgpu::Extent3d {
+        width: compiled.width,
+        height: compiled.height,
+        depth_or_array_layers: 1,
+    };
+    if compiled.width == 0 || compiled.height == 0 || compiled.levels.is_empty() {
+        return Err("texture has no pixels".to_string());
+    }
+    for (level, pixels) in compiled.levels.iter().enumerate() {
+        let size = texture_size.mip_level_size(level as u32, wgpu::TextureDimension::D2);
+        if pixels.len() != 4 * size.width as usize * size.height as usize {
+            return Err(format!("mip level {} has the wrong size", level));
+        }
+    }
+
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        size: texture_size,
+        mip_level_count: compiled.levels.len() as u32,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: color_space.format(),
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        label: Some(label),
+        view_formats: &[color_space.format()],
+    });
+
+    for (level, pixels) in compiled.levels.iter().enumerate() {
+        let size = texture_size.mip_level_size(level as u32, wgpu::TextureDimension::D2);
+        queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &texture,
+                mip_level: level as u32,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            pixels,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * size.width),
+                rows_per_image: Some(size.height),
+            },
+            size,
+        );
+    }
+
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    Ok(Texture { texture, view })
+}
+
+/// the sampler shared by every material texture
+pub fn new_material_sampler(device: &wgpu::Device) -> wgpu::Sampler {
     let sampler_descriptor = wgpu::SamplerDescriptor {
         address_mode_u: wgpu::AddressMode::Repeat,
         address_mode_v: wgpu::AddressMode::Repeat,
@@ -109,41 +228,140 @@ pub fn new_texture(
         mipmap_filter: wgpu::FilterMode::Nearest,
         ..Default::default()
     };
-    let sampler = device.create_sampler(&sampler_descriptor);
+    device.create_sampler(&sampler_descriptor)
+}
 
-    // Make a bind group for everything
-    let mut builder = bind_group::Builder::new(device);
-    builder.set_layout(layout);
-    builder.add_material(&view, &sampler);
-    let bind_group = builder.build(label);
+/// a single white texel, bound in place of the maps a material doesn't have
+pub fn new_white_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> Texture {
+    let compiled = CompiledTexture {
+        width: 1,
+        height: 1,
+        levels: vec![vec![255; 4]],
+    };
+    new_mipmapped_texture(
+        &compiled,
+        ColorSpace::Linear,
+        device,
+        queue,
+        "White Texture",
+    )
+    .unwrap()
+}
 
-    bind_group
+/// the maps of `material` in bind group order: base color, roughness, metallic, normal,
+/// specular and emissive, with the color space each is sampled in
+fn material_maps(material: &Material) -> [(Option<&String>, ColorSpace); 6] {
+    [
+        (material.filename.as_ref(), ColorSpace::Srgb),
+        (material.roughness_texture.as_ref(), ColorSpace::Linear),
+        (material.metallic_texture.as_ref(), ColorSpace::Linear),
+        (material.normal_texture.as_ref(), ColorSpace::Linear),
+        (material.specular_texture.as_ref(), ColorSpace::Linear),
+        (material.emissive_texture.as_ref(), ColorSpace::Srgb),
+    ]
 }
 
-pub fn new_color(
-    color: &Vec4,
-    device: &wgpu::Device,
-    label: &str,
-    layout: &wgpu::BindGroupLayout,
-) -> wgpu::BindGroup {
-    let bytes: &[u8] = unsafe { any_as_u8_slice(color) };
-
-    let buffer_descriptor = wgpu::util::BufferInitDescriptor {
-        label: Some("Model vertex & index buffer"),
-        contents: bytes,
-        usage: wgpu::BufferUsages::UNIFORM,
-    };
+/// every image texture loaded so far, keyed by normalized path and color space so that each
+/// file is decoded and uploaded once per way it is sampled, however it was spelled
+pub struct TextureCache {
+    textures: HashMap<(String, ColorSpace), Texture>,
+    sampler: wgpu::Sampler,
+    white: Texture,
+}
+
+impl TextureCache {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        TextureCache {
+            textures: HashMap::new(),
+            sampler: new_material_sampler(device),
+            white: new_white_texture(device, queue),
+        }
+    }
+
+    /// the texture for `filename` in `color_space`, loading it on first use. files that
+    /// can't be read or decoded are an error and aren't cached
+    pub fn get_or_load(
+        &mut self,
+        assets: &dyn AssetSource,
+        filename: &str,
+        color_space: ColorSpace,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<&Texture, String> {
+        match self
+            .textures
+            .entry((normalize(Path::new(filename)), color_space))
+        {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => Ok(entry.insert(new_image_texture(
+                assets,
+                filename,
+                color_space,
+                device,
+                queue,
+                "Texture",
+            )?)),
+        }
+    }
+
+    /// loads the maps of `material` that aren't cached yet, the first that fails is returned
+    pub fn load_maps(
+        &mut self,
+        assets: &dyn AssetSource,
+        material: &Material,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Result<(), String> {
+        for (filename, color_space) in material_maps(material) {
+            if let Some(filename) = filename {
+                self.get_or_load(assets, filename, color_space, device, queue)?;
+            }
+        }
+        Ok(())
+    }
 
-    let buffer = device.create_buffer_init(&buffer_descriptor);
+    /// the bind group of `material`: its `PbrMaterial` followed by its base color, roughness,
+    /// metallic, normal, specular and emissive maps. images are only decoded if they are new,
+    /// the base color and emissive maps are sampled as sRGB
+    pub fn new_material_bind_group(
+        &mut self,
+        assets: &dyn AssetSource,
+        material: &Material,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        label: &str,
+        layout: &wgpu::BindGroupLayout,
+    ) -> Result<wgpu::BindGroup, String> {
+        self.load_maps(assets, material, device, queue)?;
+        let [base, roughness, metallic, normal, specular, emissive] =
+            material_maps(material).map(|(filename, color_space)| match filename {
+                Some(filename) => &self.textures[&(normalize(Path::new(filename)), color_space)],
+                None => &self.white,
+            });
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Material Parameters"),
+            contents: bytemuck::bytes_of(&material.pbr),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
 
-    // build bind groups
-    let bind_group: wgpu::BindGroup;
-    {
         let mut builder = bind_group::Builder::new(device);
         builder.set_layout(layout);
         builder.add_buffer(&buffer, 0);
-        bind_group = builder.build(label);
+        builder.add_material(&base.view, &self.sampler);
+        builder.add_texture_view(&roughness.view);
+        builder.add_texture_view(&metallic.view);
+        builder.add_texture_view(&normal.view);
+        builder.add_texture_view(&specular.view);
+        builder.add_texture_view(&emissive.view);
+        Ok(builder.build(label))
+    }
+
+    pub fn len(&self) -> usize {
+        self.textures.len()
     }
 
-    bind_group
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty()
+    }
 }
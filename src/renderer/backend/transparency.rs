@@ -0,0 +1,228 @@
+use super::assets::AssetSource;
+use super::bind_group;
+use super::bind_group_layout;
+use super::pipeline;
+use super::texture::Texture;
+
+/// the pass putting the accumulated surfaces over the frame, relative to the shader source
+pub const SHADER: &str = "oit_composite.wgsl";
+
+/// the weighted sum of the premultiplied colors in rgb and of the alphas in a
+const ACCUMULATION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// the product of 1 - alpha of every surface, how much of the opaque frame shows through
+const REVEALAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+/// what blended materials write in `TransparencyMode::WeightedBlended` instead of the frame,
+/// `Accumulation` in include/oit.wgsl
+pub fn accumulation_targets() -> [wgpu::ColorTargetState; 2] {
+    let sum = wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    };
+    let product = wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::OneMinusSrc,
+        operation: wgpu::BlendOperation::Add,
+    };
+    [
+        wgpu::ColorTargetState {
+            format: ACCUMULATION_FORMAT,
+            blend: Some(wgpu::BlendState {
+                color: sum,
+                alpha: sum,
+            }),
+            write_mask: wgpu::ColorWrites::ALL,
+        },
+        wgpu::ColorTargetState {
+            format: REVEALAGE_FORMAT,
+            blend: Some(wgpu::BlendState {
+                color: product,
+                alpha: product,
+            }),
+            write_mask: wgpu::ColorWrites::ALL,
+        },
+    ]
+}
+
+fn new_target(
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    label: &str,
+) -> Texture {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+    Texture { texture, view }
+}
+
+/// the targets and composite pass of weighted blended order independent transparency,
+/// built the first time `TransparencyMode::WeightedBlended` is turned on
+pub struct WeightedBlendedOit {
+    accumulation: Texture,
+    revealage: Texture,
+    layout: wgpu::BindGroupLayout,
+    /// the two targets, read by the composite pass
+    bind_group: wgpu::BindGroup,
+    composite_pipeline: wgpu::RenderPipeline,
+}
+
+impl WeightedBlendedOit {
+    /// the accumulation and revealage targets
+    pub fn bind_group_layout() -> bind_group_layout::Builder {
+        let mut builder = bind_group_layout::Builder::new();
+        builder.add_texture_view();
+        builder.add_texture_view();
+        builder
+    }
+
+    /// targets of `width` by `height`, composited onto a frame of `pixel_format`.
+    /// shader errors are returned
+    pub fn new(
+        device: &wgpu::Device,
+        shaders: &dyn AssetSource,
+        pixel_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Result<Self, String> {
+        let layout = Self::bind_group_layout().build(device, "OIT Bind Group Layout");
+
+        let mut pb = pipeline::Builder::new(device, shaders);
+        pb.set_shader_module(SHADER, "vs_main", "fs_main");
+        pb.set_pixel_format(pixel_format);
+        pb.set_blend_state(wgpu::BlendState::ALPHA_BLENDING);
+        // a single triangle over the screen, in front of everything
+        pb.set_cull_mode(None);
+        pb.set_depth_compare(wgpu::CompareFunction::Always);
+        pb.set_depth_write(false);
+        pb.add_bind_group_layout(&layout);
+        let composite_pipeline = pb.try_build("OIT Composite Pipeline")?;
+
+        let (accumulation, revealage, bind_group) = Self::targets(device, &layout, width, height);
+        Ok(WeightedBlendedOit {
+            accumulation,
+            revealage,
+            layout,
+            bind_group,
+            composite_pipeline,
+        })
+    }
+
+    fn targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        width: u32,
+        height: u32,
+    ) -> (Texture, Texture, wgpu::BindGroup) {
+        let accumulation = new_target(
+            device,
+            ACCUMULATION_FORMAT,
+            width,
+            height,
+            "OIT Accumulation",
+        );
+        let revealage = new_target(device, REVEALAGE_FORMAT, width, height, "OIT Revealage");
+
+        let mut builder = bind_group::Builder::new(device);
+        builder.set_layout(layout);
+        builder.add_texture_view(&accumulation.view);
+        builder.add_texture_view(&revealage.view);
+        let bind_group = builder.build("OIT");
+
+        (accumulation, revealage, bind_group)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.accumulation.texture.destroy();
+        self.revealage.texture.destroy();
+        (self.accumulation, self.revealage, self.bind_group) =
+            Self::targets(device, &self.layout, width, height);
+    }
+
+    /// clears the targets and starts the pass blended materials are drawn into, tested
+    /// against `depth` but not writing it
+    pub fn begin_accumulation<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        depth: &'a wgpu::TextureView,
+    ) -> wgpu::RenderPass<'a> {
+        let target = |view, clear| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })
+        };
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("OIT Accumulation Pass"),
+            color_attachments: &[
+                target(&self.accumulation.view, wgpu::Color::TRANSPARENT),
+                // nothing covers anything yet
+                target(&self.revealage.view, wgpu::Color::WHITE),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+
+    /// records the pass blending the accumulated surfaces over `frame`, whose depth is `depth`
+    pub fn composite(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        frame: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("OIT Composite Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: frame,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.composite_pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
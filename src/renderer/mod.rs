@@ -1,2 +1,3 @@
+pub mod backend;
+#[allow(clippy::module_inception)]
 pub mod renderer;
-pub mod backend;
\ No newline at end of file
@@ -1,35 +1,93 @@
 // use crate::model::game_objects::{Camera, Object};
 use crate::renderer::backend::definitions::{Camera, InstanceData, Model};
 use crate::renderer::backend::{
-    bind_group_layout,
-    mesh_builder::ObjLoader,
-    pipeline,
-    texture::{Texture, new_color, new_depth_texture, new_texture},
+    assets::{AssetSource, DirectoryAssets, sibling},
+    bake::Manifest,
+    bind_group, bind_group_layout,
+    culling::Frustum,
+    gpu_culling::{self, CullTargets, GpuCuller},
+    hi_z,
+    instances::{DirtyRanges, InstanceHandle, InstanceStore},
+    lights::{
+        self, ClusterLights, GpuLight, Light, LightClusterer, LightHandle, LightStore,
+        new_cluster_buffer, new_light_buffer,
+    },
+    materials::MaterialRegistry,
+    mesh_builder::{MeshData, ObjLoader},
+    mesh_cache::{CompiledMesh, decode_compressed},
+    pipeline::{self, PipelineCache, capture_errors},
+    shader_preprocessor::preprocess,
+    shader_watcher::ShaderWatcher,
+    shadows::{self, Cascades, ShadowMaps},
+    texture::{Texture, TextureCache, new_color_target, new_depth_texture},
+    transparency::{self, WeightedBlendedOit},
 };
+use crate::window::SurfaceProvider;
 use glam::*;
-use glfw::Window;
-use std::collections::HashMap;
-use std::hash::Hash;
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use super::backend::definitions::*;
 
-pub struct RendererState<'a> {
+/// what the renderer draws each frame into
+enum RenderTarget {
+    /// the part of the window that we draw to, presented after every frame
+    Surface(wgpu::Surface<'static>),
+    /// an offscreen texture that can be read back with `read_frame`
+    Offscreen(Texture),
+}
+
+pub struct RendererState {
     /// a handle to our GPU
     instance: wgpu::Instance,
-    /// the part of the window that we draw to
-    surface: wgpu::Surface<'a>,
+    target: RenderTarget,
     device: wgpu::Device,
     /// executes recorded CommandBuffer objects and provides convenience methods for writing to buffers
     queue: wgpu::Queue,
     /// screen size, max latency, etc
     config: wgpu::SurfaceConfiguration,
     pub size: (i32, i32),
-    /// struct that wraps a *GLFWWindow handle
-    pub window: &'a mut Window,
     /// map of pre-defined types to wgpu::RenderPipelines
-    render_pipelines: HashMap<PipelineType, wgpu::RenderPipeline>,
+    /// built the first time a material with their features is loaded
+    render_pipelines: PipelineCache,
     bind_group_layouts: HashMap<BindScope, wgpu::BindGroupLayout>,
-    materials: Vec<Material>,
+    /// `FrameUniforms`, rewritten at the start of every frame
+    frame_buffer: wgpu::Buffer,
+    /// the frame uniforms, lights and light clusters, rebuilt when the light buffer grows
+    frame_bind_group: wgpu::BindGroup,
+    lights: LightStore,
+    /// the directional light a new renderer starts with
+    sun: LightHandle,
+    light_buffer: wgpu::Buffer,
+    /// the lights reaching each cluster, filled by `light_clusterer` every frame
+    cluster_buffer: wgpu::Buffer,
+    light_clusterer: LightClusterer,
+    /// the shadow cascades of the first directional light that casts shadows
+    shadow_maps: ShadowMaps,
+    /// draws the instances into `shadow_maps`
+    shadow_pipeline: wgpu::RenderPipeline,
+    /// draws the submeshes of `AlphaMode::Mask` materials into `shadow_maps`
+    masked_shadow_pipeline: wgpu::RenderPipeline,
+    /// how far from the camera the cascades reach
+    shadow_distance: f32,
+    /// where the light clusters end for projections without a far plane
+    cluster_distance: f32,
+    /// what `shadow_maps` are drawn with this frame, `None` while no light casts shadows
+    cascades: Option<Cascades>,
+    materials: MaterialRegistry,
+    /// decoded material textures, shared between materials that use the same file
+    textures: TextureCache,
+    /// where models, materials and textures are read from, the working directory by default
+    assets: Arc<dyn AssetSource>,
+    /// where `load_assets` caches compiled meshes, see `set_mesh_cache_dir`
+    mesh_cache_dir: Option<PathBuf>,
+    /// where shaders are read from, the ones embedded at compile time by default
+    shaders: Arc<dyn AssetSource>,
+    /// set by `enable_shader_hot_reload`, checked at the start of every frame
+    shader_watcher: Option<ShaderWatcher>,
+    /// the last compile error of every shader whose edit was rejected
+    shader_errors: HashMap<String, String>,
     depth_buffer: Texture,
 
     // models: Vec<Model>, // convert to map of string to Model?
@@ -37,22 +95,48 @@ pub struct RendererState<'a> {
     // pub instance_buffer: wgpu::Buffer,
     // pub instance_count: u32,
     models: HashMap<String, Vec<Model>>,
-    pub instances: HashMap<String, Vec<InstanceData>>,
+    /// packed per-model instances, addressed through `InstanceHandle`s
+    instances: InstanceStore,
     instance_buffers: HashMap<String, wgpu::Buffer>,
     pub instance_counts: HashMap<String, u32>,
+    culling: CullingMode,
+    /// what the instance buffers hold while culling, the visible instances compacted
+    visible_instances: HashMap<String, Vec<InstanceData>>,
+    stats: FrameStats,
+    /// built the first time GPU culling is turned on
+    gpu_culler: Option<GpuCuller>,
+    cull_targets: HashMap<String, CullTargets>,
+    occlusion_culling: bool,
+    /// the view-projection `depth_buffer` was last rendered with, `None` while it holds nothing
+    /// usable, e.g. right after a resize
+    depth_view_proj: Option<Mat4>,
+    /// whether the pipelines and depth buffer are set up for a reverse-Z projection
+    reverse_z: bool,
+    /// how blended materials are drawn, their pipelines are built for it
+    transparency: TransparencyMode,
+    /// built the first time `TransparencyMode::WeightedBlended` is turned on
+    oit: Option<WeightedBlendedOit>,
+    /// stages instance uploads into the frame's command encoder so the CPU never waits on
+    /// the GPU to finish reading the instance buffers
+    staging_belt: wgpu::util::StagingBelt,
+    /// submissions that may still be executing, oldest first
+    frames_in_flight: VecDeque<wgpu::SubmissionIndex>,
 }
 
-impl<'a> RendererState<'a> {
-    pub async fn new(window: &'a mut Window) -> Self {
-        let size = window.get_framebuffer_size();
+/// the shader every model pipeline is a variant of, relative to the shader source
+pub const MODEL_SHADER: &str = "model.wgsl";
 
-        let instance_descriptor = wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            ..Default::default()
-        };
+/// staging chunk size for instance uploads, fits 16384 instances
+const STAGING_CHUNK_SIZE: u64 = 1 << 20;
 
-        let instance = wgpu::Instance::new(&instance_descriptor);
-        let surface = instance.create_surface(window.render_context()).unwrap();
+impl RendererState {
+    /// creates a renderer that presents to `window`.
+    /// the window is only borrowed here, the surface keeps its own handle to it
+    pub async fn new(window: &mut impl SurfaceProvider) -> Self {
+        let size = window.framebuffer_size();
+
+        let instance = Self::new_instance();
+        let surface = instance.create_surface(window.surface_target()).unwrap();
 
         let adapter_descriptor = wgpu::RequestAdapterOptionsBase {
             power_preference: wgpu::PowerPreference::default(),
@@ -61,32 +145,19 @@ impl<'a> RendererState<'a> {
         };
         let adapter = instance.request_adapter(&adapter_descriptor).await.unwrap();
 
-        let device_descriptor = wgpu::DeviceDescriptor {
-            required_features: wgpu::Features::PUSH_CONSTANTS,
-            required_limits: wgpu::Limits {
-                max_push_constant_size: 64,
-                ..wgpu::Limits::default()
-            },
-            memory_hints: wgpu::MemoryHints::Performance,
-            label: Some("Device"),
-            trace: wgpu::Trace::Off,
-            experimental_features: wgpu::ExperimentalFeatures::default(),
-        };
-
-        let (device, queue) = adapter.request_device(&device_descriptor).await.unwrap();
+        let (device, queue) = Self::request_device(&adapter).await;
         let surface_capabilities = surface.get_capabilities(&adapter);
         let surface_format = surface_capabilities
             .formats
             .iter()
             .copied()
-            .filter(|f| f.is_srgb())
-            .next()
+            .find(|f| f.is_srgb())
             .unwrap_or(surface_capabilities.formats[0]);
         let config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
-            width: size.0 as u32,
-            height: size.1 as u32,
+            width: size.0.max(1),
+            height: size.1.max(1),
             present_mode: surface_capabilities.present_modes[0],
             alpha_mode: surface_capabilities.alpha_modes[0],
             view_formats: vec![],
@@ -94,8 +165,92 @@ impl<'a> RendererState<'a> {
         };
         surface.configure(&device, &config);
 
+        Self::from_parts(
+            instance,
+            RenderTarget::Surface(surface),
+            device,
+            queue,
+            config,
+        )
+    }
+
+    /// creates a renderer that draws into an offscreen texture of the given size instead of a window.
+    /// falls back to a software adapter if no hardware one is available, so this works without a display
+    pub async fn new_headless(width: u32, height: u32) -> Self {
+        let instance = Self::new_instance();
+
+        let mut adapter_descriptor = wgpu::RequestAdapterOptionsBase {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: None,
+            force_fallback_adapter: false,
+        };
+        let adapter = match instance.request_adapter(&adapter_descriptor).await {
+            Ok(adapter) => adapter,
+            Err(_) => {
+                adapter_descriptor.force_fallback_adapter = true;
+                instance.request_adapter(&adapter_descriptor).await.unwrap()
+            }
+        };
+
+        let (device, queue) = Self::request_device(&adapter).await;
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        };
+        let target = new_color_target(&device, &config, "Offscreen Target");
+
+        Self::from_parts(
+            instance,
+            RenderTarget::Offscreen(target),
+            device,
+            queue,
+            config,
+        )
+    }
+
+    fn new_instance() -> wgpu::Instance {
+        let instance_descriptor = wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        };
+
+        wgpu::Instance::new(&instance_descriptor)
+    }
+
+    async fn request_device(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
+        let device_descriptor = wgpu::DeviceDescriptor {
+            required_features: wgpu::Features::PUSH_CONSTANTS,
+            required_limits: wgpu::Limits {
+                max_push_constant_size: 64,
+                ..wgpu::Limits::default()
+            },
+            memory_hints: wgpu::MemoryHints::Performance,
+            label: Some("Device"),
+            trace: wgpu::Trace::Off,
+            experimental_features: wgpu::ExperimentalFeatures::default(),
+        };
+
+        adapter.request_device(&device_descriptor).await.unwrap()
+    }
+
+    /// shared tail of `new` and `new_headless` once the render target exists
+    fn from_parts(
+        instance: wgpu::Instance,
+        target: RenderTarget,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let size = (config.width as i32, config.height as i32);
+
+        let shaders: Arc<dyn AssetSource> = Arc::new(pipeline::embedded_shaders());
         let bind_group_layouts = Self::build_bind_group_layouts(&device);
-        let render_pipelines = Self::build_pipelines(&device, &config, &bind_group_layouts);
         let depth_buffer = new_depth_texture(&device, &config, "Depth Buffer");
 
         // let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
@@ -105,105 +260,228 @@ impl<'a> RendererState<'a> {
         //     mapped_at_creation: false,
         // });
 
+        let textures = TextureCache::new(&device, &queue);
+        let frame_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Frame Uniforms"),
+            size: std::mem::size_of::<FrameUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // a new renderer is lit by a sun shining up along (-1, -1, 1), see `sun`
+        let mut lights = LightStore::new();
+        let mut sun =
+            Light::directional(Vec3::new(-1.0, -1.0, 1.0), Vec3::ONE, std::f32::consts::PI);
+        sun.cast_shadows = true;
+        let sun = lights.add(sun);
+        let light_buffer = new_light_buffer(&device, lights.len());
+        let cluster_buffer = new_cluster_buffer(&device);
+        let shadow_maps = ShadowMaps::new(&device, shadows::DEFAULT_MAP_SIZE);
+        let frame_bind_group = Self::new_frame_bind_group(
+            &device,
+            &bind_group_layouts[&BindScope::Frame],
+            &frame_buffer,
+            &light_buffer,
+            &cluster_buffer,
+            &shadow_maps,
+        );
+        let light_clusterer = LightClusterer::new(&device, shaders.as_ref());
+        let shadow_pipeline = shadows::build_pipeline(&device, shaders.as_ref(), None)
+            .unwrap_or_else(|error| panic!("{}", error));
+        let masked_shadow_pipeline = shadows::build_pipeline(
+            &device,
+            shaders.as_ref(),
+            Some(&bind_group_layouts[&BindScope::Material]),
+        )
+        .unwrap_or_else(|error| panic!("{}", error));
+
         Self {
             instance,
-            window,
-            surface,
+            target,
             device,
             queue,
             config,
             size,
-            render_pipelines,
-            bind_group_layouts: bind_group_layouts,
-            materials: Vec::new(),
+            render_pipelines: PipelineCache::new(),
+            bind_group_layouts,
+            frame_buffer,
+            frame_bind_group,
+            lights,
+            sun,
+            light_buffer,
+            cluster_buffer,
+            light_clusterer,
+            shadow_maps,
+            shadow_pipeline,
+            masked_shadow_pipeline,
+            shadow_distance: shadows::DEFAULT_DISTANCE,
+            cluster_distance: lights::DEFAULT_CLUSTER_DISTANCE,
+            cascades: None,
+            materials: MaterialRegistry::new(),
+            textures,
+            assets: Arc::new(DirectoryAssets::new(".")),
+            mesh_cache_dir: None,
+            shaders,
+            shader_watcher: None,
+            shader_errors: HashMap::new(),
             depth_buffer,
 
             models: HashMap::new(),
-            instances: HashMap::new(),
+            instances: InstanceStore::new(),
             instance_buffers: HashMap::new(),
             instance_counts: HashMap::new(), // initialize with 0?
+            culling: CullingMode::Cpu,
+            visible_instances: HashMap::new(),
+            stats: FrameStats::default(),
+            gpu_culler: None,
+            cull_targets: HashMap::new(),
+            occlusion_culling: false,
+            depth_view_proj: None,
+            reverse_z: false,
+            transparency: TransparencyMode::Sorted,
+            oit: None,
+            staging_belt: wgpu::util::StagingBelt::new(STAGING_CHUNK_SIZE),
+            frames_in_flight: VecDeque::new(),
         }
     }
 
+    /// the entries of the bind group of each scope, every model pipeline binds them all
+    pub fn bind_group_layout(scope: BindScope) -> bind_group_layout::Builder {
+        let mut builder = bind_group_layout::Builder::new();
+        match scope {
+            // the `PbrMaterial`, then the base color map with the sampler shared by every map,
+            // the roughness, metallic, normal, specular and emissive maps
+            BindScope::Material => {
+                builder.add_uniform();
+                builder.add_texture();
+                builder.add_texture_view();
+                builder.add_texture_view();
+                builder.add_texture_view();
+                builder.add_texture_view();
+                builder.add_texture_view();
+            }
+            // the `FrameUniforms`, the lights, the lights of every cluster, then the shadow
+            // cascades with their maps and comparison sampler
+            BindScope::Frame => {
+                builder.add_uniform();
+                builder.add_fragment_storage_buffer();
+                builder.add_fragment_storage_buffer();
+                builder.add_uniform();
+                builder.add_shadow_map();
+            }
+        }
+        builder
+    }
+
     fn build_bind_group_layouts(
         device: &wgpu::Device,
     ) -> HashMap<BindScope, wgpu::BindGroupLayout> {
         let mut layouts: HashMap<BindScope, wgpu::BindGroupLayout> = HashMap::new();
-        let mut layout: wgpu::BindGroupLayout;
-        let mut scope = BindScope::Texture;
-        let mut builder = bind_group_layout::Builder::new(device);
-        builder.add_texture();
-        layout = builder.build("Texture Bind Group Layout");
-        layouts.insert(scope, layout);
+        layouts.insert(
+            BindScope::Material,
+            Self::bind_group_layout(BindScope::Material)
+                .build(device, "Material Bind Group Layout"),
+        );
+        layouts.insert(
+            BindScope::Frame,
+            Self::bind_group_layout(BindScope::Frame).build(device, "Frame Bind Group Layout"),
+        );
 
-        builder.add_vec4();
-        scope = BindScope::Color;
-        layout = builder.build("Color Group Layout");
-        layouts.insert(scope, layout);
+        layouts
+    }
 
-        builder.add_mat4();
+    fn new_frame_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        frame: &wgpu::Buffer,
+        lights: &wgpu::Buffer,
+        clusters: &wgpu::Buffer,
+        shadows: &ShadowMaps,
+    ) -> wgpu::BindGroup {
+        let mut builder = bind_group::Builder::new(device);
+        builder.set_layout(layout);
+        builder.add_buffer(frame, 0);
+        builder.add_buffer(lights, 0);
+        builder.add_buffer(clusters, 0);
+        builder.add_buffer(&shadows.uniforms, 0);
+        builder.add_texture_view(&shadows.view);
+        builder.add_sampler(&shadows.sampler);
+        builder.build("Frame")
+    }
 
-        layouts
+    /// rebuilds every cached pipeline, after the shaders or the depth convention changed
+    fn rebuild_pipelines(&mut self) {
+        for features in self.render_pipelines.features() {
+            let pipeline = Self::build_pipeline(
+                &self.device,
+                self.shaders.as_ref(),
+                &self.config,
+                &self.bind_group_layouts,
+                self.reverse_z,
+                self.transparency,
+                features,
+            )
+            .unwrap_or_else(|error| panic!("{}", error));
+            self.render_pipelines.insert(features, pipeline);
+        }
     }
 
-    fn build_pipelines(
+    /// the `#define`s of the `MODEL_SHADER` variant for materials with `features`
+    pub fn model_defines(
+        features: MaterialFeatures,
+        transparency: TransparencyMode,
+    ) -> Vec<&'static str> {
+        let mut defines = features.defines();
+        if features.alpha_mode == AlphaMode::Blend
+            && transparency == TransparencyMode::WeightedBlended
+        {
+            defines.push("WEIGHTED_BLENDED");
+        }
+        defines
+    }
+
+    /// compiles the `MODEL_SHADER` variant for materials with `features`, shader errors are
+    /// returned
+    fn build_pipeline(
         device: &wgpu::Device,
+        shaders: &dyn AssetSource,
         config: &wgpu::SurfaceConfiguration,
         bind_group_layouts: &HashMap<BindScope, wgpu::BindGroupLayout>,
-    ) -> HashMap<PipelineType, wgpu::RenderPipeline> {
-        let mut pipelines: HashMap<PipelineType, wgpu::RenderPipeline> = HashMap::new();
-        let mut pb = pipeline::Builder::new(device);
-
-        let instance_layout = wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<InstanceData>() as u64,
-            step_mode: wgpu::VertexStepMode::Instance,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    format: wgpu::VertexFormat::Float32x4,
-                    offset: 0,
-                    shader_location: 3,
-                },
-                wgpu::VertexAttribute {
-                    format: wgpu::VertexFormat::Float32x4,
-                    offset: 16,
-                    shader_location: 4,
-                },
-                wgpu::VertexAttribute {
-                    format: wgpu::VertexFormat::Float32x4,
-                    offset: 32,
-                    shader_location: 5,
-                },
-                wgpu::VertexAttribute {
-                    format: wgpu::VertexFormat::Float32x4,
-                    offset: 48,
-                    shader_location: 6,
-                },
-            ],
-        };
+        reverse_z: bool,
+        transparency: TransparencyMode,
+        features: MaterialFeatures,
+    ) -> Result<wgpu::RenderPipeline, String> {
+        let mut pb = pipeline::Builder::new(device, shaders);
+        pb.set_depth_compare(match reverse_z {
+            true => wgpu::CompareFunction::Greater,
+            false => wgpu::CompareFunction::Less,
+        });
 
-        // Colored pipeline
-        pb.set_shader_module("shaders/instanced_colored.wgsl", "vs_main", "fs_main");
-        pb.set_pixel_format(config.format);
-        pb.add_vertex_buffer_layout(VertexData::get_layout());
-        pb.add_vertex_buffer_layout(instance_layout.clone());
-        pb.add_bind_group_layout(&bind_group_layouts[&BindScope::Color]);
-        pipelines.insert(
-            PipelineType::ColoredModel,
-            pb.build("Colored Model Pipeline"),
-        );
+        if features.double_sided {
+            pb.set_cull_mode(None);
+        }
+        // blended surfaces don't hide each other, what is behind them is drawn first or
+        // weighted in
+        if features.alpha_mode == AlphaMode::Blend {
+            pb.set_depth_write(false);
+            match transparency {
+                TransparencyMode::Sorted => pb.set_blend_state(wgpu::BlendState::ALPHA_BLENDING),
+                TransparencyMode::WeightedBlended => {
+                    pb.set_color_targets(&transparency::accumulation_targets())
+                }
+            }
+        }
 
-        // Textured pipeline
-        pb.set_shader_module("shaders/instanced_textured.wgsl", "vs_main", "fs_main");
+        pb.set_shader_module(MODEL_SHADER, "vs_main", "fs_main");
+        for define in Self::model_defines(features, transparency) {
+            pb.add_define(define);
+        }
         pb.set_pixel_format(config.format);
         pb.add_vertex_buffer_layout(VertexData::get_layout());
-        pb.add_vertex_buffer_layout(instance_layout);
-        pb.add_bind_group_layout(&bind_group_layouts[&BindScope::Texture]);
-        pipelines.insert(
-            PipelineType::TexturedModel,
-            pb.build("Textured Model Pipeline"),
-        );
-
-        return pipelines;
+        pb.add_vertex_buffer_layout(InstanceData::get_layout());
+        pb.add_bind_group_layout(&bind_group_layouts[&BindScope::Material]);
+        pb.add_bind_group_layout(&bind_group_layouts[&BindScope::Frame]);
+        pb.try_build(&format!("Model Pipeline {:?}", features))
     }
 
     // pub fn update_instance_buffer(&mut self, instances: &Vec<InstanceData>) {
@@ -224,78 +502,514 @@ impl<'a> RendererState<'a> {
     //         .write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(instances));
     // }
 
-    pub fn update_instance_buffer(&mut self) {
-        for (key, instances) in &self.instances {
-            let instance_count = instances.len() as u32;
-            self.instance_counts.insert(key.clone(), instance_count);
+    /// records copies of the instance ranges that changed since the last call into `encoder`.
+    /// buffers grow to the next power of two so spawning doesn't reallocate every frame.
+    /// with culling on, only instances inside the frustum of `view_proj` are uploaded, packed at the front,
+    /// followed by the ones that only cast shadows into it
+    fn update_instance_buffer(&mut self, encoder: &mut wgpu::CommandEncoder, view_proj: &Mat4) {
+        let frustum = Frustum::from_view_projection(view_proj);
+        let shadow_frustums: Vec<Frustum> = self
+            .cascades
+            .iter()
+            .flat_map(|cascades| cascades.view_projections.iter())
+            .map(Frustum::from_view_projection)
+            .collect();
+        let mut stats = FrameStats::default();
+
+        for batch in self.instances.iter_batches_mut() {
+            let key = batch.id();
 
-            // Compute required buffer size
-            let size = (instances.len() * std::mem::size_of::<InstanceData>()) as u64;
+            let visible: Option<(Vec<InstanceData>, u32)> =
+                match (self.culling, self.models.get(key)) {
+                    (CullingMode::Cpu, Some(model_list)) => {
+                        let sphere = model_list_bounds(model_list).sphere;
+                        let (mut visible, hidden): (Vec<InstanceData>, Vec<InstanceData>) =
+                            batch.instances().iter().partition(|instance| {
+                                frustum
+                                    .intersects_sphere(&sphere.transformed(&instance.transform()))
+                            });
+                        let camera_visible = visible.len() as u32;
+                        visible.extend(hidden.into_iter().filter(|instance| {
+                            let sphere = sphere.transformed(&instance.transform());
+                            shadow_frustums
+                                .iter()
+                                .any(|frustum| frustum.intersects_sphere(&sphere))
+                        }));
+                        Some((visible, camera_visible))
+                    }
+                    _ => None,
+                };
 
-            // Check if a buffer exists AND if it is large enough
-            let need_new_buffer = match self.instance_buffers.get(key) {
-                Some(buf) => buf.size() < size,
-                None => true,
-            };
+            let total = batch.instances().len() as u32;
+            let instance_count = visible.as_ref().map_or(total, |(_, count)| *count);
+            self.instance_counts.insert(key.to_string(), instance_count);
+            stats.visible_instances += instance_count;
+            stats.frustum_culled_instances += total - instance_count;
 
-            // Reallocate if needed
-            if need_new_buffer {
-                let new_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
-                    label: Some(&format!("Instance Buffer: {}", key)),
-                    size,
-                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-                    mapped_at_creation: false,
-                });
+            let reallocated = reserve_instance_buffer(
+                &self.device,
+                &mut self.instance_buffers,
+                key,
+                visible.as_ref().map_or(total, |(v, _)| v.len() as u32),
+            );
+            let buffer = &self.instance_buffers[key];
 
-                self.instance_buffers.insert(key.clone(), new_buffer);
+            // Stage only the changed instances, the copies run in order with the draws
+            // so frames still in flight keep reading the old data
+            match visible {
+                None => {
+                    // the new buffer is empty so everything gets uploaded
+                    if reallocated {
+                        batch.mark_all_dirty();
+                    }
+                    for range in batch.dirty_ranges() {
+                        stage_instances(
+                            &mut self.staging_belt,
+                            encoder,
+                            &self.device,
+                            buffer,
+                            range.start,
+                            &batch.instances()[range.start as usize..range.end as usize],
+                        );
+                    }
+                }
+                Some((visible, _)) => {
+                    let uploaded = self.visible_instances.entry(key.to_string()).or_default();
+                    if reallocated {
+                        uploaded.clear();
+                    }
+                    for range in changed_ranges(uploaded, &visible).iter() {
+                        stage_instances(
+                            &mut self.staging_belt,
+                            encoder,
+                            &self.device,
+                            buffer,
+                            range.start,
+                            &visible[range.start as usize..range.end as usize],
+                        );
+                    }
+                    *uploaded = visible;
+                }
             }
+            batch.clear_dirty();
+        }
+
+        self.stats = stats;
+    }
+
+    /// records the compute pass that culls every batch into its `CullTargets`,
+    /// after the instance uploads in `encoder`
+    fn cull_on_gpu(&mut self, encoder: &mut wgpu::CommandEncoder, view_proj: &Mat4) {
+        let Some(culler) = &self.gpu_culler else {
+            return;
+        };
+        let frustum = Frustum::from_view_projection(view_proj);
+
+        // the previous frame's depth is still in the depth buffer, the render pass clears it later
+        let occlusion_view_proj = self
+            .depth_view_proj
+            .as_ref()
+            .filter(|_| self.occlusion_culling);
+        culler.begin_frame(
+            &self.device,
+            encoder,
+            &mut self.staging_belt,
+            occlusion_view_proj.map(|_| &self.depth_buffer),
+            self.reverse_z,
+        );
 
-            // Now safe to unwrap—buffer definitely exists
-            let buffer = self.instance_buffers.get(key).unwrap();
+        for (key, model_list) in &self.models {
+            let instance_count = self.instance_counts.get(key).copied().unwrap_or(0);
+            if instance_count == 0 {
+                continue;
+            }
 
-            // Write the instance data into the GPU buffer
-            if !instances.is_empty() {
-                self.queue
-                    .write_buffer(buffer, 0, bytemuck::cast_slice(instances));
+            let instances = &self.instance_buffers[key];
+            let capacity = instances.size() / std::mem::size_of::<InstanceData>() as u64;
+            let draw_count = model_list.iter().map(|m| m.submeshes.len()).sum::<usize>() as u64;
+            if self
+                .cull_targets
+                .get(key)
+                .is_none_or(|targets| targets.too_small(capacity, draw_count))
+            {
+                self.cull_targets.insert(
+                    key.to_string(),
+                    CullTargets::new(&self.device, key, capacity, draw_count),
+                );
             }
+
+            culler.cull(
+                &self.device,
+                encoder,
+                &mut self.staging_belt,
+                &frustum,
+                &model_list_bounds(model_list).sphere,
+                model_list,
+                instances,
+                instance_count,
+                self.cull_targets.get_mut(key).unwrap(),
+                occlusion_view_proj,
+                self.reverse_z,
+            );
         }
     }
 
-    pub fn load_assets(&mut self, id: &str, filepath: &str) {
-        let mut loader = ObjLoader::new();
+    /// switches how instances get culled, the next frame re-uploads every instance
+    pub fn set_culling_mode(&mut self, mode: CullingMode) {
+        if mode == CullingMode::Gpu && self.gpu_culler.is_none() {
+            self.gpu_culler = Some(GpuCuller::new(
+                &self.device,
+                self.shaders.as_ref(),
+                self.config.width,
+                self.config.height,
+            ));
+        }
+        self.culling = mode;
+        self.visible_instances.clear();
+        for batch in self.instances.iter_batches_mut() {
+            batch.mark_all_dirty();
+        }
+    }
+
+    pub fn culling_mode(&self) -> CullingMode {
+        self.culling
+    }
+
+    /// visible and culled instance counts of the last rendered frame
+    pub fn frame_stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    /// rejects instances hidden behind the previous frame's depth (Hi-Z occlusion culling).
+    /// only applies to `CullingMode::Gpu`, other modes keep the flag for when it is turned on
+    pub fn set_occlusion_culling(&mut self, enabled: bool) {
+        if enabled && self.culling != CullingMode::Gpu {
+            eprintln!(
+                "occlusion culling only applies to CullingMode::Gpu, the {:?} mode ignores it",
+                self.culling
+            );
+        }
+        self.occlusion_culling = enabled;
+    }
 
-        let model: Model = loader.load(
-            filepath,
-            &mut self.materials,
+    pub fn occlusion_culling(&self) -> bool {
+        self.occlusion_culling
+    }
+
+    /// switches how blended materials are drawn, rebuilding their pipelines
+    pub fn set_transparency_mode(&mut self, mode: TransparencyMode) {
+        if mode == self.transparency {
+            return;
+        }
+        if mode == TransparencyMode::WeightedBlended && self.oit.is_none() {
+            self.oit = Some(self.new_oit().unwrap_or_else(|error| panic!("{}", error)));
+        }
+        self.transparency = mode;
+        self.rebuild_pipelines();
+    }
+
+    pub fn transparency_mode(&self) -> TransparencyMode {
+        self.transparency
+    }
+
+    fn new_oit(&self) -> Result<WeightedBlendedOit, String> {
+        WeightedBlendedOit::new(
             &self.device,
-            &glam::Mat4::IDENTITY,
-        );
+            self.shaders.as_ref(),
+            self.config.format,
+            self.config.width,
+            self.config.height,
+        )
+    }
 
-        self.models.insert(id.to_string(), vec![model]);
+    /// waits for the GPU and reads the counts of the last frame culled with `CullingMode::Gpu`,
+    /// `None` if GPU culling was never turned on
+    pub fn read_gpu_cull_stats(&self) -> Option<FrameStats> {
+        self.gpu_culler
+            .as_ref()
+            .map(|culler| culler.read_stats(&self.device, &self.queue))
+    }
 
-        // build bindgroups for all materials
-        for material in &mut self.materials {
-            material.bind_group = match material.pipeline_type {
-                PipelineType::ColoredModel => Some(new_color(
-                    material.color.as_ref().unwrap(),
-                    &self.device,
-                    "Color",
-                    &self.bind_group_layouts[&BindScope::Color],
-                )),
+    /// read models, materials, textures and manifests from `assets` from now on
+    pub fn set_asset_source(&mut self, assets: impl AssetSource + 'static) {
+        self.assets = Arc::new(assets);
+    }
+
+    /// cache the meshes `load_assets` compiles in `dir`, `None` parses them on every load.
+    /// off by default, so nothing is written unless asked for
+    pub fn set_mesh_cache_dir(&mut self, dir: Option<PathBuf>) {
+        self.mesh_cache_dir = dir;
+    }
+
+    /// read shaders from `shaders`, rebuilding everything compiled from the old ones
+    pub fn set_shader_source(&mut self, shaders: impl AssetSource + 'static) {
+        self.shaders = Arc::new(shaders);
+        self.rebuild_pipelines();
+        if self.gpu_culler.is_some() {
+            self.gpu_culler = Some(GpuCuller::new(
+                &self.device,
+                self.shaders.as_ref(),
+                self.config.width,
+                self.config.height,
+            ));
+            self.depth_view_proj = None;
+        }
+        self.light_clusterer = LightClusterer::new(&self.device, self.shaders.as_ref());
+        self.shadow_pipeline = shadows::build_pipeline(&self.device, self.shaders.as_ref(), None)
+            .unwrap_or_else(|error| panic!("{}", error));
+        self.masked_shadow_pipeline = shadows::build_pipeline(
+            &self.device,
+            self.shaders.as_ref(),
+            Some(&self.bind_group_layouts[&BindScope::Material]),
+        )
+        .unwrap_or_else(|error| panic!("{}", error));
+        if self.oit.is_some() {
+            self.oit = Some(self.new_oit().unwrap_or_else(|error| panic!("{}", error)));
+        }
+    }
+
+    /// dev mode: read shaders from `dir` and rebuild the pipelines using a shader whenever
+    /// it is saved. edits that don't compile keep the old pipeline, see `shader_error`
+    pub fn enable_shader_hot_reload(&mut self, dir: impl Into<PathBuf>) {
+        let dir = dir.into();
+        self.set_shader_source(DirectoryAssets::new(&dir));
+        self.shader_watcher = Some(ShaderWatcher::new(dir));
+    }
 
-                PipelineType::TexturedModel => Some(new_texture(
-                    material.filename.as_ref().unwrap().as_str(),
+    /// why the last edit of `shader_filename` was rejected, `None` once it compiles again
+    pub fn shader_error(&self, shader_filename: &str) -> Option<&str> {
+        self.shader_errors.get(shader_filename).map(String::as_str)
+    }
+
+    /// true if `shader_filename` is `file` or includes it. shaders whose directives don't
+    /// resolve count as users too, rebuilding them reports the error
+    fn shader_uses(&self, shader_filename: &str, defines: &[&str], file: &str) -> bool {
+        match preprocess(self.shaders.as_ref(), shader_filename, defines) {
+            Ok(preprocessed) => preprocessed.files.iter().any(|f| f == file),
+            Err(_) => true,
+        }
+    }
+
+    /// rebuilds what depends on shaders the watcher saw change
+    fn reload_changed_shaders(&mut self) {
+        let Some(watcher) = &mut self.shader_watcher else {
+            return;
+        };
+
+        for shader in watcher.changed() {
+            let culler_uses_shader = self.gpu_culler.is_some()
+                && [gpu_culling::SHADER, hi_z::SHADER]
+                    .into_iter()
+                    .any(|file| self.shader_uses(file, &[], &shader));
+            let clusterer_uses_shader = self.shader_uses(lights::SHADER, &[], &shader);
+            let shadows_use_shader = self.shader_uses(shadows::SHADER, &[], &shader)
+                || self.shader_uses(shadows::SHADER, &["ALPHA_MASK"], &shader);
+            let oit_uses_shader =
+                self.oit.is_some() && self.shader_uses(transparency::SHADER, &[], &shader);
+            let variants: Vec<MaterialFeatures> = self
+                .render_pipelines
+                .features()
+                .into_iter()
+                .filter(|features| {
+                    let defines = Self::model_defines(*features, self.transparency);
+                    self.shader_uses(MODEL_SHADER, &defines, &shader)
+                })
+                .collect();
+
+            let mut rebuilt = Ok(());
+            if culler_uses_shader {
+                rebuilt = capture_errors(&self.device, || {
+                    Ok(GpuCuller::new(
+                        &self.device,
+                        self.shaders.as_ref(),
+                        self.config.width,
+                        self.config.height,
+                    ))
+                })
+                .map(|culler| {
+                    self.gpu_culler = Some(culler);
+                    self.depth_view_proj = None;
+                });
+            }
+            if clusterer_uses_shader {
+                match capture_errors(&self.device, || {
+                    Ok(LightClusterer::new(&self.device, self.shaders.as_ref()))
+                }) {
+                    Ok(clusterer) => self.light_clusterer = clusterer,
+                    Err(error) => rebuilt = Err(error),
+                }
+            }
+            if shadows_use_shader {
+                let material_layout = &self.bind_group_layouts[&BindScope::Material];
+                match shadows::build_pipeline(&self.device, self.shaders.as_ref(), None).and_then(
+                    |pipeline| {
+                        shadows::build_pipeline(
+                            &self.device,
+                            self.shaders.as_ref(),
+                            Some(material_layout),
+                        )
+                        .map(|masked| (pipeline, masked))
+                    },
+                ) {
+                    Ok((pipeline, masked)) => {
+                        self.shadow_pipeline = pipeline;
+                        self.masked_shadow_pipeline = masked;
+                    }
+                    Err(error) => rebuilt = Err(error),
+                }
+            }
+            if oit_uses_shader {
+                match self.new_oit() {
+                    Ok(oit) => self.oit = Some(oit),
+                    Err(error) => rebuilt = Err(error),
+                }
+            }
+            for features in variants {
+                let pipeline = Self::build_pipeline(
                     &self.device,
-                    &self.queue,
-                    "Texture",
-                    &self.bind_group_layouts[&BindScope::Texture],
-                )),
+                    self.shaders.as_ref(),
+                    &self.config,
+                    &self.bind_group_layouts,
+                    self.reverse_z,
+                    self.transparency,
+                    features,
+                );
+                match pipeline {
+                    Ok(pipeline) => {
+                        self.render_pipelines.insert(features, pipeline);
+                    }
+                    Err(error) => rebuilt = Err(error),
+                }
+            }
 
-                _ => None,
-            };
+            match rebuilt {
+                Ok(()) => {
+                    self.shader_errors.remove(&shader);
+                }
+                Err(error) => {
+                    eprintln!(
+                        "{} failed to compile, keeping the old version:\n{}",
+                        shader, error
+                    );
+                    self.shader_errors.insert(shader, error);
+                }
+            }
         }
+    }
+
+    /// how many distinct image files have been decoded and uploaded
+    pub fn loaded_textures(&self) -> usize {
+        self.textures.len()
+    }
+
+    /// how many pipeline variants the loaded materials needed
+    pub fn built_pipelines(&self) -> usize {
+        self.render_pipelines.len()
+    }
+
+    /// loads an OBJ and its materials as `id`. an OBJ, MTL or map that can't be read is
+    /// returned before anything is registered, and so are shader variants its materials
+    /// need that fail to compile, the model is not added then
+    pub fn load_assets(&mut self, id: &str, filepath: &str) -> Result<(), String> {
+        let mut loader = ObjLoader::new();
+        loader.set_optimize(true);
+        loader.set_cache_dir(self.mesh_cache_dir.clone());
+        loader.set_asset_source(self.assets.clone());
+
+        let compiled = loader.compile_cached(filepath, &glam::Mat4::IDENTITY)?;
+        self.load_maps(&compiled)?;
+        let model = MeshData::from_compiled(compiled, &mut self.materials).upload(&self.device);
+
+        self.add_model(id, model)
+    }
 
-        self.instances.entry(id.to_string()).or_insert(Vec::new());
+    /// decodes and uploads the maps of `compiled`'s materials ahead of registering them
+    fn load_maps(&mut self, compiled: &CompiledMesh) -> Result<(), String> {
+        for material in &compiled.materials {
+            self.textures
+                .load_maps(self.assets.as_ref(), material, &self.device, &self.queue)?;
+        }
+        Ok(())
+    }
+
+    /// loads every model listed in a manifest written by the `bake` binary,
+    /// under the id the manifest gives it. nothing is registered if the manifest or any of
+    /// its meshes or textures can't be read, a shader variant that fails to compile stops it
+    /// after the models before
+    pub fn load_manifest(&mut self, manifest_path: &str) -> Result<(), String> {
+        let text = self
+            .assets
+            .read_to_string(manifest_path)
+            .ok_or_else(|| format!("missing manifest {}", manifest_path))?;
+        let manifest =
+            Manifest::from_toml(&text).map_err(|e| format!("{}: {}", manifest_path, e))?;
+
+        // paths in the manifest and in its meshes are relative to the manifest
+        let mut meshes = Vec::new();
+        for (id, entry) in &manifest.meshes {
+            let mesh = sibling(manifest_path, &entry.mesh);
+            let mut compiled: CompiledMesh = self
+                .assets
+                .read(&mesh)
+                .and_then(|bytes| decode_compressed(&bytes))
+                .ok_or_else(|| format!("could not read baked mesh {}", mesh))?;
+            for material in &mut compiled.materials {
+                for texture in material.textures_mut() {
+                    *texture = sibling(manifest_path, texture);
+                }
+            }
+            meshes.push((id, compiled));
+        }
+        for (_, compiled) in &meshes {
+            self.load_maps(compiled)?;
+        }
+
+        for (id, compiled) in meshes {
+            let model = MeshData::from_compiled(compiled, &mut self.materials).upload(&self.device);
+            self.add_model(id, model)?;
+        }
+        Ok(())
+    }
+
+    /// makes a loaded model drawable under `id`, unless the pipeline of one of its materials
+    /// fails to compile
+    fn add_model(&mut self, id: &str, model: Model) -> Result<(), String> {
+        // build bindgroups for the materials this model added, maps come from the cache,
+        // and the pipelines for feature sets no earlier material had. every new material
+        // gets its bind group even if a pipeline fails, other models may share it
+        let mut built = Ok(());
+        for material in self.materials.take_new() {
+            match self.textures.new_material_bind_group(
+                self.assets.as_ref(),
+                material,
+                &self.device,
+                &self.queue,
+                "Material",
+                &self.bind_group_layouts[&BindScope::Material],
+            ) {
+                Ok(bind_group) => material.bind_group = Some(bind_group),
+                Err(error) => built = Err(error),
+            }
+
+            let pipeline = self.render_pipelines.get_or_build(material.features, || {
+                Self::build_pipeline(
+                    &self.device,
+                    self.shaders.as_ref(),
+                    &self.config,
+                    &self.bind_group_layouts,
+                    self.reverse_z,
+                    self.transparency,
+                    material.features,
+                )
+            });
+            if let Err(error) = pipeline {
+                built = Err(error);
+            }
+        }
+        built?;
+
+        self.models.insert(id.to_string(), vec![model]);
+        self.instances.add_batch(id);
         self.instance_counts.entry(id.to_string()).or_insert(0);
 
         let placeholder_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
@@ -307,6 +1021,60 @@ impl<'a> RendererState<'a> {
 
         self.instance_buffers
             .insert(id.to_string(), placeholder_buffer);
+        Ok(())
+    }
+
+    /// adds an instance of the model loaded as `model_id`, an error if no model was loaded
+    /// under that id. the handle stays valid until `despawn`, no matter what else is spawned
+    /// or despawned
+    pub fn spawn_instance(
+        &mut self,
+        model_id: &str,
+        transform: Mat4,
+    ) -> Result<InstanceHandle, String> {
+        self.instances
+            .spawn(model_id, InstanceData::from(transform))
+            .ok_or_else(|| format!("no model loaded as {}", model_id))
+    }
+
+    /// moves an instance, returns false if the handle was already despawned
+    pub fn set_transform(&mut self, handle: InstanceHandle, transform: Mat4) -> bool {
+        self.instances.set(handle, InstanceData::from(transform))
+    }
+
+    pub fn transform(&self, handle: InstanceHandle) -> Option<Mat4> {
+        self.instances.get(handle).map(InstanceData::transform)
+    }
+
+    /// removes an instance, returns false if the handle was already despawned
+    pub fn despawn(&mut self, handle: InstanceHandle) -> bool {
+        self.instances.remove(handle)
+    }
+
+    /// adds a light, the handle stays valid until `remove_light`.
+    /// every light is shaded in one pass, only by the fragments it can reach
+    pub fn add_light(&mut self, light: Light) -> LightHandle {
+        self.lights.add(light)
+    }
+
+    /// changes a light, returns false if the handle was already removed
+    pub fn set_light(&mut self, handle: LightHandle, light: Light) -> bool {
+        self.lights.set(handle, light)
+    }
+
+    pub fn light(&self, handle: LightHandle) -> Option<Light> {
+        self.lights.get(handle).copied()
+    }
+
+    /// removes a light, returns false if the handle was already removed
+    pub fn remove_light(&mut self, handle: LightHandle) -> bool {
+        self.lights.remove(handle)
+    }
+
+    /// the directional light a new renderer starts with, casting shadows. change or remove it
+    /// like any other
+    pub fn sun(&self) -> LightHandle {
+        self.sun
     }
 
     pub fn resize(&mut self, new_size: (i32, i32)) {
@@ -314,54 +1082,442 @@ impl<'a> RendererState<'a> {
             self.size = new_size;
             self.config.width = new_size.0 as u32;
             self.config.height = new_size.1 as u32;
-            self.surface.configure(&self.device, &self.config);
+            match &mut self.target {
+                RenderTarget::Surface(surface) => surface.configure(&self.device, &self.config),
+                RenderTarget::Offscreen(target) => {
+                    target.texture.destroy();
+                    *target = new_color_target(&self.device, &self.config, "Offscreen Target");
+                }
+            }
 
             self.depth_buffer.texture.destroy();
             self.depth_buffer = new_depth_texture(&self.device, &self.config, "Depth Buffer");
+            self.depth_view_proj = None;
+            if let Some(culler) = &mut self.gpu_culler {
+                culler.resize(&self.device, self.config.width, self.config.height);
+            }
+            if let Some(oit) = &mut self.oit {
+                oit.resize(&self.device, self.config.width, self.config.height);
+            }
         }
     }
 
-    pub fn update_surface(&mut self) {
-        self.surface = self
-            .instance
-            .create_surface(self.window.render_context())
-            .unwrap();
+    /// recreates the surface for `window`, does nothing for headless renderers
+    pub fn update_surface(&mut self, window: &mut impl SurfaceProvider) {
+        if let RenderTarget::Surface(_) = self.target {
+            let surface = self
+                .instance
+                .create_surface(window.surface_target())
+                .unwrap();
+            self.target = RenderTarget::Surface(surface);
+        }
     }
 
-    fn update_projection(&self, camera: &Camera) -> Mat4 {
-        // Vectors for view matrix columns
-        let c0 = Vec4::new(camera.right.x, camera.up.x, -camera.forwards.x, 0.0);
-        let c1 = Vec4::new(camera.right.y, camera.up.y, -camera.forwards.y, 0.0);
-        let c2 = Vec4::new(camera.right.z, camera.up.z, -camera.forwards.z, 0.0);
-        let a: f32 = -camera.right.dot(camera.position);
-        let b: f32 = -camera.up.dot(camera.position);
-        let c: f32 = camera.forwards.dot(camera.position);
-        let c3 = Vec4::new(a, b, c, 1.0);
+    /// copies the last rendered frame back to the CPU as an RGBA image.
+    /// returns None when rendering to a window surface
+    pub fn read_frame(&self) -> Option<image::RgbaImage> {
+        let RenderTarget::Offscreen(target) = &self.target else {
+            return None;
+        };
 
-        let view = Mat4::from_cols(c0, c1, c2, c3);
+        let width = self.config.width;
+        let height = self.config.height;
 
-        let fov_y: f32 = 80.0_f32.to_radians();
-        let aspect = 4.0 / 3.0;
-        let z_near = 0.5;
-        let z_far = 10000.0;
-        let projection = Mat4::perspective_rh(fov_y, aspect, z_near, z_far);
+        // texture to buffer copies need rows aligned to 256 bytes
+        let unpadded_bytes_per_row = 4 * width;
+        let padded_bytes_per_row = unpadded_bytes_per_row
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
 
-        projection * view
-    }
+        let readback_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (padded_bytes_per_row * height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
 
-    /// draws all objects in an instanced way.
-    /// runs an instanced draw on each submesh/mat in each model
-    pub fn render(&mut self, camera: &Camera) -> Result<(), wgpu::SurfaceError> {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Readback Encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &target.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &readback_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            target.texture.size(),
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = readback_buffer.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
         let _ = self.device.poll(wgpu::PollType::Wait {
             submission_index: None,
             timeout: None,
         });
-        self.update_instance_buffer();
 
-        let drawable = self.surface.get_current_texture()?;
-        let view = drawable
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        // strip the row padding
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+        }
+        readback_buffer.unmap();
+
+        image::RgbaImage::from_raw(width, height, pixels)
+    }
+
+    /// rebuilds the pipelines if the camera switched between standard and reverse-Z depth
+    fn update_depth_direction(&mut self, camera: &Camera) {
+        let reverse_z = camera.projection.is_reverse_z();
+        if reverse_z == self.reverse_z {
+            return;
+        }
+
+        self.reverse_z = reverse_z;
+        self.rebuild_pipelines();
+        // the depth buffer holds the other convention now
+        self.depth_view_proj = None;
+    }
+
+    fn update_projection(&self, camera: &Camera) -> Mat4 {
+        let aspect = self.config.width as f32 / self.config.height as f32;
+        camera.view_projection(aspect)
+    }
+
+    fn update_frame_uniforms(&self, camera: &Camera) {
+        let aspect = self.config.width as f32 / self.config.height as f32;
+        // the slices are spaced by the ratio of far to near, which has to stay finite
+        let (near, far) = camera.projection.depth_range(self.cluster_distance);
+        let near = near.max(0.01);
+        let uniforms = FrameUniforms {
+            view: camera.view(),
+            inverse_projection: camera.projection.matrix(aspect).inverse(),
+            camera_position: camera.position.extend(1.0),
+            cluster_count: lights::CLUSTER_COUNT.extend(self.lights.len() as u32),
+            cluster_extent: Vec4::new(
+                self.config.width as f32 / lights::CLUSTER_COUNT.x as f32,
+                self.config.height as f32 / lights::CLUSTER_COUNT.y as f32,
+                near,
+                far.max(near * 2.0),
+            ),
+        };
+        self.queue
+            .write_buffer(&self.frame_buffer, 0, bytemuck::bytes_of(&uniforms));
+    }
+
+    /// uploads the lights if they changed, growing their buffer if they no longer fit
+    fn update_lights(&mut self) {
+        if !self.lights.is_dirty() {
+            return;
+        }
+
+        let gpu_lights: Vec<GpuLight> = self.lights.lights().iter().map(GpuLight::from).collect();
+        let size = std::mem::size_of_val(gpu_lights.as_slice()) as u64;
+        if self.light_buffer.size() < size {
+            self.light_buffer =
+                new_light_buffer(&self.device, gpu_lights.len().next_power_of_two());
+            self.frame_bind_group = Self::new_frame_bind_group(
+                &self.device,
+                &self.bind_group_layouts[&BindScope::Frame],
+                &self.frame_buffer,
+                &self.light_buffer,
+                &self.cluster_buffer,
+                &self.shadow_maps,
+            );
+        }
+        if size > 0 {
+            self.queue
+                .write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&gpu_lights));
+        }
+        self.lights.clear_dirty();
+    }
+
+    /// fits the cascades of the shadowed light around the camera's view for this frame
+    fn update_shadows(&mut self, camera: &Camera) {
+        let shadowed = shadows::shadowed_light(self.lights.lights());
+        let aspect = self.config.width as f32 / self.config.height as f32;
+        self.cascades = shadowed.map(|(_, direction)| {
+            shadows::fit_cascades(
+                camera,
+                aspect,
+                direction,
+                self.shadow_distance,
+                self.shadow_maps.map_size(),
+            )
+        });
+        self.shadow_maps.write_uniforms(
+            &self.queue,
+            self.cascades.as_ref(),
+            shadowed.map(|(index, _)| index),
+        );
+    }
+
+    /// draws every instance into the shadow map of each cascade, including the ones culled
+    /// from the camera's view. masked submeshes are cut out by their base color's alpha, and
+    /// blended ones cast no shadow at all, the maps can't hold partial opacity
+    fn draw_shadows(&self, encoder: &mut wgpu::CommandEncoder) {
+        let Some(cascades) = &self.cascades else {
+            return;
+        };
+
+        for (view_proj, cascade_view) in cascades
+            .view_projections
+            .iter()
+            .zip(&self.shadow_maps.cascade_views)
+        {
+            let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: cascade_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            for (alpha_mode, pipeline) in [
+                (AlphaMode::Opaque, &self.shadow_pipeline),
+                (AlphaMode::Mask, &self.masked_shadow_pipeline),
+            ] {
+                shadow_pass.set_pipeline(pipeline);
+                shadow_pass.set_push_constants(
+                    wgpu::ShaderStages::VERTEX,
+                    0,
+                    mat4_as_bytes(view_proj),
+                );
+
+                for (id, model_list) in &self.models {
+                    // CPU culling keeps the shadow casters behind the visible instances
+                    let instance_count = match self.culling {
+                        CullingMode::Cpu => {
+                            self.visible_instances.get(id).map_or(0, Vec::len) as u32
+                        }
+                        _ => self.instance_counts.get(id).copied().unwrap_or(0),
+                    };
+                    if instance_count == 0 {
+                        continue;
+                    }
+
+                    shadow_pass.set_vertex_buffer(1, self.instance_buffers[id].slice(..));
+                    for model in model_list {
+                        shadow_pass.set_vertex_buffer(0, model.buffer.slice(0..model.ebo_offset));
+                        shadow_pass.set_index_buffer(
+                            model.buffer.slice(model.ebo_offset..),
+                            model.index_format,
+                        );
+                        for submesh in &model.submeshes {
+                            let material = self.materials.get(submesh.material_id);
+                            if material.features.alpha_mode != alpha_mode {
+                                continue;
+                            }
+                            if alpha_mode == AlphaMode::Mask {
+                                shadow_pass.set_bind_group(
+                                    0,
+                                    material.bind_group.as_ref().unwrap(),
+                                    &[],
+                                );
+                            }
+                            shadow_pass.draw_indexed(
+                                submesh.first_index..submesh.first_index + submesh.index_count,
+                                0,
+                                0..instance_count,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// the width and height of each shadow cascade's map, in texels
+    pub fn set_shadow_map_size(&mut self, size: u32) {
+        self.shadow_maps.destroy();
+        self.shadow_maps = ShadowMaps::new(&self.device, size);
+        self.frame_bind_group = Self::new_frame_bind_group(
+            &self.device,
+            &self.bind_group_layouts[&BindScope::Frame],
+            &self.frame_buffer,
+            &self.light_buffer,
+            &self.cluster_buffer,
+            &self.shadow_maps,
+        );
+    }
+
+    pub fn shadow_map_size(&self) -> u32 {
+        self.shadow_maps.map_size()
+    }
+
+    /// how far from the camera shadows are drawn, the cascades split this distance
+    /// between them. surfaces further away are lit as if nothing was in the way
+    pub fn set_shadow_distance(&mut self, distance: f32) {
+        self.shadow_distance = distance;
+    }
+
+    pub fn shadow_distance(&self) -> f32 {
+        self.shadow_distance
+    }
+
+    /// where the light clusters end for projections without a far plane. their depth slices
+    /// split the view up to this distance, anything further shares the last slice and misses
+    /// the lights that don't reach it
+    pub fn set_cluster_distance(&mut self, distance: f32) {
+        self.cluster_distance = distance;
+    }
+
+    pub fn cluster_distance(&self) -> f32 {
+        self.cluster_distance
+    }
+
+    /// waits for the GPU and reads which lights the clusters of the last frame shade,
+    /// see `lights::read_clusters`
+    pub fn read_light_clusters(&self) -> Vec<ClusterLights> {
+        lights::read_clusters(&self.device, &self.queue, &self.cluster_buffer)
+    }
+
+    /// how many frames the CPU may queue ahead of the GPU.
+    /// 1 waits for each frame to finish before starting the next
+    pub fn set_frame_latency(&mut self, frames: u32) {
+        self.config.desired_maximum_frame_latency = frames.max(1);
+        if let RenderTarget::Surface(surface) = &self.target {
+            surface.configure(&self.device, &self.config);
+        }
+    }
+
+    /// only blocks once `desired_maximum_frame_latency` frames are queued on the GPU
+    fn throttle_frames(&mut self) {
+        // never blocks, but hands finished staging chunks back to the belt
+        let _ = self.device.poll(wgpu::PollType::Poll);
+
+        while self.frames_in_flight.len() >= self.config.desired_maximum_frame_latency as usize {
+            let oldest = self.frames_in_flight.pop_front().unwrap();
+            let _ = self.device.poll(wgpu::PollType::Wait {
+                submission_index: Some(oldest),
+                timeout: None,
+            });
+        }
+    }
+
+    /// every instance of a blended submesh the camera can see, back to front for
+    /// `TransparencyMode::Sorted`
+    fn blended_draws(&self, camera: &Camera, view_proj: &Mat4) -> Vec<BlendedDraw<'_>> {
+        let frustum = Frustum::from_view_projection(view_proj);
+        let mut draws = Vec::new();
+
+        for (id, model_list) in &self.models {
+            let count = self.instance_counts.get(id).copied().unwrap_or(0) as usize;
+            // what the instance buffer holds, with CPU culling the visible instances first
+            let instances = match self.culling {
+                CullingMode::Cpu => self
+                    .visible_instances
+                    .get(id)
+                    .map_or(&[][..], |visible| &visible[..count]),
+                _ => &self.instances.instances(id)[..count],
+            };
+
+            for (m, model) in model_list.iter().enumerate() {
+                for (s, submesh) in model.submeshes.iter().enumerate() {
+                    let material = self.materials.get(submesh.material_id);
+                    if material.features.alpha_mode != AlphaMode::Blend {
+                        continue;
+                    }
+                    for (i, instance) in instances.iter().enumerate() {
+                        let sphere = submesh.bounds.sphere.transformed(&instance.transform());
+                        if !frustum.intersects_sphere(&sphere) {
+                            continue;
+                        }
+                        draws.push(BlendedDraw {
+                            model_id: id,
+                            model: m,
+                            submesh: s,
+                            instance: i as u32,
+                            distance: sphere.center.distance_squared(camera.position),
+                        });
+                    }
+                }
+            }
+        }
+
+        // the farthest first, the nearer ones are blended over it
+        if self.transparency == TransparencyMode::Sorted {
+            draws.sort_by(|a, b| b.distance.total_cmp(&a.distance));
+        }
+        draws
+    }
+
+    /// records `draws` in their order, one instance each
+    fn draw_blended(
+        &self,
+        renderpass: &mut wgpu::RenderPass,
+        draws: &[BlendedDraw],
+        view_proj: &Mat4,
+    ) {
+        let instance_size = std::mem::size_of::<InstanceData>() as u64;
+        for draw in draws {
+            let model = &self.models[draw.model_id][draw.model];
+            let submesh = &model.submeshes[draw.submesh];
+            let material = self.materials.get(submesh.material_id);
+
+            renderpass.set_pipeline(self.render_pipelines.get(material.features).unwrap());
+            renderpass.set_push_constants(wgpu::ShaderStages::VERTEX, 0, mat4_as_bytes(view_proj));
+            renderpass.set_bind_group(0, material.bind_group.as_ref().unwrap(), &[]);
+            renderpass.set_bind_group(1, &self.frame_bind_group, &[]);
+            renderpass.set_vertex_buffer(0, model.buffer.slice(0..model.ebo_offset));
+            // the instance is bound as the first one, not every backend has base instances
+            renderpass.set_vertex_buffer(
+                1,
+                self.instance_buffers[draw.model_id].slice(draw.instance as u64 * instance_size..),
+            );
+            renderpass.set_index_buffer(model.buffer.slice(model.ebo_offset..), model.index_format);
+            renderpass.draw_indexed(
+                submesh.first_index..submesh.first_index + submesh.index_count,
+                0,
+                0..1,
+            );
+        }
+    }
+
+    /// draws all objects in an instanced way.
+    /// runs an instanced draw on each submesh/mat in each model.
+    /// works the same for window surfaces and offscreen targets
+    pub fn render(&mut self, camera: &Camera) -> Result<(), wgpu::SurfaceError> {
+        self.throttle_frames();
+        self.reload_changed_shaders();
+
+        // offscreen targets have nothing to present
+        let (drawable, view) = match &self.target {
+            RenderTarget::Surface(surface) => {
+                let drawable = surface.get_current_texture()?;
+                let view = drawable
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                (Some(drawable), view)
+            }
+            RenderTarget::Offscreen(target) => (
+                None,
+                target
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default()),
+            ),
+        };
 
         let mut encoder = self
             .device
@@ -369,6 +1525,27 @@ impl<'a> RendererState<'a> {
                 label: Some("Render Encoder"),
             });
 
+        self.update_depth_direction(camera);
+        let view_proj = self.update_projection(camera);
+        self.update_frame_uniforms(camera);
+        self.update_lights();
+        self.update_shadows(camera);
+        self.light_clusterer.assign(
+            &self.device,
+            &mut encoder,
+            &self.frame_buffer,
+            &self.light_buffer,
+            &self.cluster_buffer,
+        );
+
+        self.update_instance_buffer(&mut encoder, &view_proj);
+        self.stats.overflowing_light_clusters = self.light_clusterer.overflowing_clusters();
+        if self.culling == CullingMode::Gpu {
+            self.cull_on_gpu(&mut encoder, &view_proj);
+        }
+        self.staging_belt.finish();
+        self.draw_shadows(&mut encoder);
+
         let mut renderpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
@@ -388,7 +1565,8 @@ impl<'a> RendererState<'a> {
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                 view: &self.depth_buffer.view,
                 depth_ops: Some(wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(1.0),
+                    // the far plane
+                    load: wgpu::LoadOp::Clear(if self.reverse_z { 0.0 } else { 1.0 }),
                     store: wgpu::StoreOp::Store,
                 }),
                 stencil_ops: None,
@@ -397,8 +1575,6 @@ impl<'a> RendererState<'a> {
             timestamp_writes: None,
         });
 
-        let view_proj = self.update_projection(camera);
-
         // draw loop
         for (id, model_list) in &self.models {
             let instance_count = match self.instance_counts.get(id) {
@@ -409,44 +1585,193 @@ impl<'a> RendererState<'a> {
                 Some(b) => b,
                 None => continue,
             };
+            // with GPU culling the instance counts come from the indirect draws
+            let cull_targets = match self.culling {
+                CullingMode::Gpu => self.cull_targets.get(id),
+                _ => None,
+            };
+            let mut draw_index = 0;
 
             for model in model_list {
                 renderpass.set_vertex_buffer(0, model.buffer.slice(0..model.ebo_offset));
-                renderpass.set_index_buffer(
-                    model.buffer.slice(model.ebo_offset..),
-                    wgpu::IndexFormat::Uint32,
-                );
-                renderpass.set_vertex_buffer(1, instance_buffer.slice(..));
+                // the GL backend ignores index buffer offsets in indirect draws, so those
+                // bind the whole buffer and skip the vertices through `first_index` instead
+                let index_start = match cull_targets {
+                    Some(_) => 0,
+                    None => model.ebo_offset,
+                };
+                renderpass.set_index_buffer(model.buffer.slice(index_start..), model.index_format);
+                match cull_targets {
+                    Some(targets) => renderpass.set_vertex_buffer(1, targets.visible.slice(..)),
+                    None => renderpass.set_vertex_buffer(1, instance_buffer.slice(..)),
+                }
                 // draw each submesh with its own material
                 for submesh in &model.submeshes {
-                    let material = &self.materials[submesh.material_id];
+                    let material = self.materials.get(submesh.material_id);
+                    // drawn after every opaque submesh, see `blended_draws`
+                    if material.features.alpha_mode == AlphaMode::Blend {
+                        draw_index += 1;
+                        continue;
+                    }
 
-                    renderpass.set_pipeline(&self.render_pipelines[&material.pipeline_type]);
+                    renderpass.set_pipeline(self.render_pipelines.get(material.features).unwrap());
                     renderpass.set_push_constants(
                         wgpu::ShaderStages::VERTEX,
                         0,
                         mat4_as_bytes(&view_proj),
                     );
                     renderpass.set_bind_group(0, material.bind_group.as_ref().unwrap(), &[]);
+                    renderpass.set_bind_group(1, &self.frame_bind_group, &[]);
 
-                    renderpass.draw_indexed(
-                        0..submesh.index_count,
-                        submesh.first_index,
-                        0..instance_count,
-                    );
+                    match cull_targets {
+                        Some(targets) => renderpass.draw_indexed_indirect(
+                            &targets.draws,
+                            draw_index
+                                * std::mem::size_of::<wgpu::util::DrawIndexedIndirectArgs>() as u64,
+                        ),
+                        None => renderpass.draw_indexed(
+                            submesh.first_index..submesh.first_index + submesh.index_count,
+                            0,
+                            0..instance_count,
+                        ),
+                    }
+                    draw_index += 1;
                 }
             }
         }
 
-        drop(renderpass);
+        let blended = self.blended_draws(camera, &view_proj);
+        match (&self.oit, self.transparency) {
+            (Some(oit), TransparencyMode::WeightedBlended) => {
+                drop(renderpass);
+                let mut accumulation =
+                    oit.begin_accumulation(&mut encoder, &self.depth_buffer.view);
+                self.draw_blended(&mut accumulation, &blended, &view_proj);
+                drop(accumulation);
+                oit.composite(&mut encoder, &view, &self.depth_buffer.view);
+            }
+            _ => {
+                self.draw_blended(&mut renderpass, &blended, &view_proj);
+                drop(renderpass);
+            }
+        }
 
-        self.queue.submit(Some(encoder.finish()));
-        drawable.present();
+        let submission = self.queue.submit(Some(encoder.finish()));
+        self.light_clusterer.map_overflow();
+        self.depth_view_proj = Some(view_proj);
+        self.frames_in_flight.push_back(submission);
+        self.staging_belt.recall();
+
+        if let Some(drawable) = drawable {
+            drawable.present();
+        }
 
         Ok(())
     }
 }
 
+/// one instance of a blended submesh, drawn on its own so blended surfaces can be sorted
+struct BlendedDraw<'a> {
+    model_id: &'a str,
+    /// index into the models loaded under `model_id`
+    model: usize,
+    submesh: usize,
+    /// index into the instance buffer of `model_id`
+    instance: u32,
+    /// squared distance of the submesh's bounds from the camera
+    distance: f32,
+}
+
+/// bounds of every model loaded under one id
+fn model_list_bounds(model_list: &[Model]) -> Bounds {
+    model_list
+        .iter()
+        .map(|model| model.bounds)
+        .reduce(|a, b| a.union(&b))
+        .unwrap()
+}
+
+/// makes sure `key` has an instance buffer that fits `instance_count` instances,
+/// returns true if a new (empty) buffer had to be created
+fn reserve_instance_buffer(
+    device: &wgpu::Device,
+    instance_buffers: &mut HashMap<String, wgpu::Buffer>,
+    key: &str,
+    instance_count: u32,
+) -> bool {
+    let instance_size = std::mem::size_of::<InstanceData>() as u64;
+    let size = instance_count as u64 * instance_size;
+
+    if let Some(buffer) = instance_buffers.get(key)
+        && buffer.size() >= size
+    {
+        return false;
+    }
+
+    let capacity = (instance_count as u64).next_power_of_two();
+    let new_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(&format!("Instance Buffer: {}", key)),
+        size: capacity * instance_size,
+        // STORAGE so GPU culling can read it
+        usage: wgpu::BufferUsages::VERTEX
+            | wgpu::BufferUsages::STORAGE
+            | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    instance_buffers.insert(key.to_string(), new_buffer);
+    true
+}
+
+/// copies `instances` to `buffer` starting at instance `first`
+fn stage_instances(
+    staging_belt: &mut wgpu::util::StagingBelt,
+    encoder: &mut wgpu::CommandEncoder,
+    device: &wgpu::Device,
+    buffer: &wgpu::Buffer,
+    first: u32,
+    instances: &[InstanceData],
+) {
+    let data: &[u8] = bytemuck::cast_slice(instances);
+    let instance_size = std::mem::size_of::<InstanceData>() as u64;
+    staging_belt
+        .write_buffer(
+            encoder,
+            buffer,
+            first as u64 * instance_size,
+            wgpu::BufferSize::new(data.len() as u64).unwrap(),
+            device,
+        )
+        .copy_from_slice(data);
+}
+
+/// index ranges where `new` differs from what was uploaded before.
+/// a mostly still camera only moves a few instances in or out of view, so most of the
+/// compacted list stays in place
+fn changed_ranges(old: &[InstanceData], new: &[InstanceData]) -> DirtyRanges {
+    let mut ranges = DirtyRanges::default();
+    let mut run_start = None;
+
+    for (i, instance) in new.iter().enumerate() {
+        let same = old
+            .get(i)
+            .is_some_and(|o| bytemuck::bytes_of(o) == bytemuck::bytes_of(instance));
+
+        match (same, run_start) {
+            (false, None) => run_start = Some(i as u32),
+            (true, Some(start)) => {
+                ranges.insert(start..i as u32);
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = run_start {
+        ranges.insert(start..new.len() as u32);
+    }
+
+    ranges
+}
+
 pub fn mat4_as_bytes(m: &glam::Mat4) -> &[u8] {
     unsafe {
         std::slice::from_raw_parts((m as *const Mat4) as *const u8, std::mem::size_of::<Mat4>())
@@ -0,0 +1,76 @@
+// lists the lights that can reach each cluster of the view frustum. clusters split the
+// screen into tiles and the view depth into slices, one invocation fills one cluster
+#include "include/frame.wgsl"
+#include "include/lights.wgsl"
+
+@group(0) @binding(0) var<uniform> frame: Frame;
+@group(0) @binding(1) var<storage, read> lights: array<Light>;
+// per cluster: the number of lights reaching it, then the indices of the first
+// `CLUSTER_STRIDE - 1` of them
+@group(0) @binding(2) var<storage, read_write> clusters: array<u32>;
+// clusters that more lights reach than they hold
+@group(0) @binding(3) var<storage, read_write> overflow: atomic<u32>;
+
+// the view space point at normalized device coordinates `ndc` and depth buffer value `z`
+fn unproject(ndc: vec2<f32>, z: f32) -> vec3<f32> {
+    let view = frame.inverse_projection * vec4<f32>(ndc, z, 1.0);
+    return view.xyz / view.w;
+}
+
+@compute @workgroup_size(64)
+fn assign(@builtin(global_invocation_id) id: vec3<u32>) {
+    let count = frame.cluster_count;
+    let cluster = id.x;
+    if cluster >= count.x * count.y * count.z {
+        return;
+    }
+    let tile = vec2<u32>(cluster % count.x, (cluster / count.x) % count.y);
+    let slice = cluster / (count.x * count.y);
+    let near = slice_depth(frame, slice);
+    let far = slice_depth(frame, slice + 1u);
+
+    // the view space box around the tile's corners at both ends of the slice. two finite
+    // points on each corner's ray work for every projection, including infinite ones
+    var box_min = vec3<f32>(3.4e38);
+    var box_max = vec3<f32>(-3.4e38);
+    for (var corner = 0u; corner < 4u; corner++) {
+        let tile_corner = vec2<f32>(tile + vec2<u32>(corner & 1u, corner >> 1u));
+        // tiles count down from the top of the screen
+        let ndc = vec2<f32>(
+            tile_corner.x / f32(count.x) * 2.0 - 1.0,
+            1.0 - tile_corner.y / f32(count.y) * 2.0,
+        );
+        let a = unproject(ndc, 0.25);
+        let b = unproject(ndc, 0.75);
+        for (var end = 0u; end < 2u; end++) {
+            let depth = select(near, far, end == 1u);
+            // view space looks down -z
+            let point = mix(a, b, (-depth - a.z) / (b.z - a.z));
+            box_min = min(box_min, point);
+            box_max = max(box_max, point);
+        }
+    }
+
+    let start = cluster * CLUSTER_STRIDE;
+    var found = 0u;
+    for (var i = 0u; i < count.w; i++) {
+        let light = lights[i];
+        var reaches = true;
+        // directional lights reach everything, the others as far as their range sphere
+        if light.kind != LIGHT_DIRECTIONAL {
+            let center = (frame.view * vec4<f32>(light.position, 1.0)).xyz;
+            let offset = clamp(center, box_min, box_max) - center;
+            reaches = dot(offset, offset) <= light.range * light.range;
+        }
+        if reaches {
+            if found < CLUSTER_STRIDE - 1u {
+                clusters[start + 1u + found] = i;
+            }
+            found++;
+        }
+    }
+    clusters[start] = found;
+    if found > CLUSTER_STRIDE - 1u {
+        atomicAdd(&overflow, 1u);
+    }
+}
@@ -0,0 +1,124 @@
+// compacts the instances whose bounding sphere touches the view frustum, and isn't hidden
+// behind the previous frame's depth, and counts them into the indirect draws of every
+// submesh of the model
+
+struct CullParams {
+    // the view-projection the depth pyramid was rendered with
+    occlusion_view_proj: mat4x4<f32>,
+    // (nx, ny, nz, d), inside is dot(n, p) + d >= 0
+    planes: array<vec4<f32>, 6>,
+    // model space bounding sphere, radius in w
+    sphere: vec4<f32>,
+    instance_count: u32,
+    draw_count: u32,
+    // 1 to test against the depth pyramid
+    occlusion: u32,
+    // 1 if the projections use reverse-Z, the pyramid itself is always stored near = 0
+    reverse_z: u32,
+};
+
+// DrawIndexedIndirectArgs
+struct DrawArgs {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+@group(0) @binding(0) var<uniform> params: CullParams;
+@group(0) @binding(1) var<storage, read> instances: array<mat4x4<f32>>;
+@group(0) @binding(2) var<storage, read_write> visible: array<mat4x4<f32>>;
+@group(0) @binding(3) var<storage, read_write> draws: array<DrawArgs>;
+// farthest depth per texel, each level covering 2x2 texels of the one below
+@group(0) @binding(4) var hi_z: texture_2d<f32>;
+// visible, frustum culled, occluded
+@group(0) @binding(5) var<storage, read_write> stats: array<atomic<u32>, 3>;
+
+// true if the sphere is entirely behind the depth stored in the pyramid
+fn occluded(center: vec3<f32>, radius: f32) -> bool {
+    // screen rect and nearest depth of the sphere's bounding cube
+    var uv_min = vec2<f32>(1.0);
+    var uv_max = vec2<f32>(0.0);
+    var nearest = 1.0;
+    for (var i = 0u; i < 8u; i++) {
+        let offset = vec3<f32>(
+            select(-radius, radius, (i & 1u) != 0u),
+            select(-radius, radius, (i & 2u) != 0u),
+            select(-radius, radius, (i & 4u) != 0u),
+        );
+        let clip = params.occlusion_view_proj * vec4<f32>(center + offset, 1.0);
+        // crosses the camera plane, the projection is meaningless
+        if clip.w <= 0.0 {
+            return false;
+        }
+
+        let ndc = clip.xyz / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        uv_min = min(uv_min, uv);
+        uv_max = max(uv_max, uv);
+        nearest = min(nearest, select(ndc.z, 1.0 - ndc.z, params.reverse_z == 1u));
+    }
+    uv_min = clamp(uv_min, vec2<f32>(0.0), vec2<f32>(1.0));
+    uv_max = clamp(uv_max, vec2<f32>(0.0), vec2<f32>(1.0));
+
+    // the level where the rect spans at most 2x2 texels
+    let extent = (uv_max - uv_min) * vec2<f32>(textureDimensions(hi_z, 0));
+    // signed mip levels, naga's GLSL output only accepts those
+    let level = min(
+        i32(ceil(log2(max(max(extent.x, extent.y), 1.0)))),
+        i32(textureNumLevels(hi_z)) - 1
+    );
+    let size = textureDimensions(hi_z, level);
+    let lo = min(vec2<u32>(uv_min * vec2<f32>(size)), size - 1u);
+    let hi = min(vec2<u32>(uv_max * vec2<f32>(size)), size - 1u);
+
+    let farthest = max(
+        max(textureLoad(hi_z, lo, level).r, textureLoad(hi_z, vec2<u32>(hi.x, lo.y), level).r),
+        max(textureLoad(hi_z, vec2<u32>(lo.x, hi.y), level).r, textureLoad(hi_z, hi, level).r)
+    );
+    return nearest > farthest;
+}
+
+@compute @workgroup_size(64)
+fn cull(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= params.instance_count {
+        return;
+    }
+
+    let model = instances[id.x];
+    let center = (model * vec4<f32>(params.sphere.xyz, 1.0)).xyz;
+    let max_scale = sqrt(max(
+        dot(model[0].xyz, model[0].xyz),
+        max(dot(model[1].xyz, model[1].xyz), dot(model[2].xyz, model[2].xyz))
+    ));
+    let radius = params.sphere.w * max_scale;
+
+    for (var i = 0u; i < 6u; i++) {
+        let plane = params.planes[i];
+        if dot(plane.xyz, center) + plane.w < -radius {
+            atomicAdd(&stats[1], 1u);
+            return;
+        }
+    }
+
+    if params.occlusion == 1u && occluded(center, radius) {
+        atomicAdd(&stats[2], 1u);
+        return;
+    }
+    atomicAdd(&stats[0], 1u);
+
+    // the first draw doubles as the counter, write_draws copies it to the rest
+    let slot = atomicAdd(&draws[0].instance_count, 1u);
+    visible[slot] = model;
+}
+
+@compute @workgroup_size(64)
+fn write_draws(@builtin(global_invocation_id) id: vec3<u32>) {
+    let draw = id.x + 1u;
+    if draw >= params.draw_count {
+        return;
+    }
+
+    atomicStore(&draws[draw].instance_count, atomicLoad(&draws[0].instance_count));
+}
@@ -0,0 +1,51 @@
+// builds the Hi-Z pyramid, every texel holds the farthest depth of the texels it covers
+// one level down
+
+@group(0) @binding(0) var dst: texture_storage_2d<r32float, write>;
+// the depth buffer for copy_depth, the previous pyramid level for downsample.
+// depth is bound as an unfilterable float texture, GLSL can't textureLoad depth textures
+@group(0) @binding(1) var src: texture_2d<f32>;
+
+@compute @workgroup_size(8, 8)
+fn copy_depth(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id.xy >= textureDimensions(dst)) {
+        return;
+    }
+
+    textureStore(dst, id.xy, vec4<f32>(textureLoad(src, id.xy, 0).r, 0.0, 0.0, 0.0));
+}
+
+// reverse-Z depth buffers, flipped so the pyramid is always near = 0
+@compute @workgroup_size(8, 8)
+fn copy_depth_reversed(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id.xy >= textureDimensions(dst)) {
+        return;
+    }
+
+    textureStore(dst, id.xy, vec4<f32>(1.0 - textureLoad(src, id.xy, 0).r, 0.0, 0.0, 0.0));
+}
+
+@compute @workgroup_size(8, 8)
+fn downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(dst);
+    if any(id.xy >= size) {
+        return;
+    }
+
+    // odd sized sources fold their last row/column into the last texel
+    let src_size = textureDimensions(src);
+    let extra = vec2<u32>(
+        select(0u, 1u, id.x == size.x - 1u && (src_size.x & 1u) == 1u),
+        select(0u, 1u, id.y == size.y - 1u && (src_size.y & 1u) == 1u),
+    );
+
+    var farthest = 0.0;
+    for (var y = 0u; y <= 1u + extra.y; y++) {
+        for (var x = 0u; x <= 1u + extra.x; x++) {
+            let p = min(id.xy * 2u + vec2<u32>(x, y), src_size - 1u);
+            farthest = max(farthest, textureLoad(src, p, 0).r);
+        }
+    }
+
+    textureStore(dst, id.xy, vec4<f32>(farthest, 0.0, 0.0, 0.0));
+}
@@ -0,0 +1,27 @@
+// what every draw of a frame shares, `FrameUniforms` on the Rust side
+struct Frame {
+    view: mat4x4<f32>,
+    // takes clip space back to view space
+    inverse_projection: mat4x4<f32>,
+    // w unused
+    camera_position: vec4<f32>,
+    // clusters across, down and deep, then the number of lights
+    cluster_count: vec4<u32>,
+    // the pixel size of a cluster's screen tile, then the view depths the slices span
+    cluster_extent: vec4<f32>,
+};
+
+// the depth slice a view depth falls in, slices grow exponentially with the distance
+fn depth_slice(frame: Frame, depth: f32) -> u32 {
+    let near = frame.cluster_extent.z;
+    let far = frame.cluster_extent.w;
+    let slice = log(max(depth, near) / near) / log(far / near) * f32(frame.cluster_count.z);
+    return min(u32(slice), frame.cluster_count.z - 1u);
+}
+
+// the view depth where `slice` starts
+fn slice_depth(frame: Frame, slice: u32) -> f32 {
+    let near = frame.cluster_extent.z;
+    let far = frame.cluster_extent.w;
+    return near * pow(far / near, f32(slice) / f32(frame.cluster_count.z));
+}
@@ -0,0 +1,57 @@
+// the lights added through `RendererState::add_light`, `GpuLight` on the Rust side
+
+const LIGHT_DIRECTIONAL: u32 = 0u;
+const LIGHT_POINT: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
+// `u32`s per cluster: the number of lights reaching it, then up to
+// `lights::MAX_LIGHTS_PER_CLUSTER` light indices. tests/shader_layouts.rs checks it against
+// `lights::CLUSTER_STRIDE`
+const CLUSTER_STRIDE: u32 = 64u;
+
+struct Light {
+    position: vec3<f32>,
+    kind: u32,
+    // normalized, the way the light travels
+    direction: vec3<f32>,
+    range: f32,
+    // color times intensity
+    radiance: vec3<f32>,
+    cos_outer: f32,
+    cos_inner: f32,
+};
+
+// light arriving at a point
+struct IncomingLight {
+    // normalized, towards the light
+    direction: vec3<f32>,
+    radiance: vec3<f32>,
+};
+
+// inverse square falloff, windowed to reach 0 at `range`
+fn distance_falloff(distance: f32, range: f32) -> f32 {
+    let ratio = distance / range;
+    let window = saturate(1.0 - ratio * ratio * ratio * ratio);
+    return window * window / max(distance * distance, 1e-4);
+}
+
+fn incoming_light(light: Light, position: vec3<f32>) -> IncomingLight {
+    var incoming: IncomingLight;
+    if light.kind == LIGHT_DIRECTIONAL {
+        incoming.direction = -light.direction;
+        incoming.radiance = light.radiance;
+        return incoming;
+    }
+
+    let to_light = light.position - position;
+    let distance = length(to_light);
+    incoming.direction = to_light / max(distance, 1e-4);
+    var strength = distance_falloff(distance, light.range);
+    if light.kind == LIGHT_SPOT {
+        let cos_angle = dot(-incoming.direction, light.direction);
+        let cone = saturate((cos_angle - light.cos_outer) / (light.cos_inner - light.cos_outer));
+        strength *= cone * cone;
+    }
+    incoming.radiance = light.radiance * strength;
+    return incoming;
+}
@@ -0,0 +1,19 @@
+// what blended surfaces write in weighted blended order independent transparency,
+// `transparency::accumulation_targets`. oit_composite.wgsl puts them over the frame
+struct Accumulation {
+    // summed: premultiplied rgb and alpha, weighted
+    @location(0) sum: vec4<f32>,
+    // multiplied: 1 - alpha
+    @location(1) revealage: vec4<f32>,
+};
+
+// `color` at view depth `depth`, nearer surfaces outweigh the ones behind them
+// (McGuire and Bavoil 2013, equation 10)
+fn accumulate(color: vec4<f32>, depth: f32) -> Accumulation {
+    let weight = color.a * clamp(0.03 / (1e-5 + pow(depth / 200.0, 4.0)), 1e-2, 3e3);
+
+    var out: Accumulation;
+    out.sum = vec4<f32>(color.rgb * color.a, color.a) * weight;
+    out.revealage = vec4<f32>(color.a);
+    return out;
+}
@@ -0,0 +1,66 @@
+// metallic/roughness shading: a Lambert diffuse lobe and a GGX specular lobe with Smith
+// visibility and Schlick's Fresnel, as in "Real Shading in Unreal Engine 4"
+
+const PI: f32 = 3.14159265;
+
+// `PbrMaterial` on the Rust side
+struct PbrMaterial {
+    // linear rgb, alpha is the opacity
+    base_color: vec4<f32>,
+    emissive: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    reflectance: f32,
+    alpha_cutoff: f32,
+};
+
+// the material at one point of a surface, after its maps were applied
+struct Surface {
+    base_color: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+    reflectance: f32,
+};
+
+fn distribution_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// Smith's height-correlated masking-shadowing, divided by 4 n.l n.v
+fn visibility_smith(n_dot_v: f32, n_dot_l: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let v = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - a2) + a2);
+    let l = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - a2) + a2);
+    return 0.5 / max(v + l, 1e-5);
+}
+
+fn fresnel_schlick(f0: vec3<f32>, v_dot_h: f32) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(1.0 - v_dot_h, 5.0);
+}
+
+// the light leaving the surface towards `v` of light arriving from `l` with `radiance`.
+// `n`, `v` and `l` are normalized and point away from the surface
+fn shade(surface: Surface, n: vec3<f32>, v: vec3<f32>, l: vec3<f32>, radiance: vec3<f32>) -> vec3<f32> {
+    let n_dot_l = max(dot(n, l), 0.0);
+    if n_dot_l <= 0.0 {
+        return vec3<f32>(0.0);
+    }
+    let h = normalize(v + l);
+    let n_dot_v = max(dot(n, v), 1e-4);
+    let n_dot_h = max(dot(n, h), 0.0);
+    let v_dot_h = max(dot(v, h), 0.0);
+
+    // perceptual roughness squared, kept off 0 where the highlight would vanish
+    let roughness = clamp(surface.roughness, 0.045, 1.0);
+    let alpha = roughness * roughness;
+
+    // metals reflect their base color and diffuse nothing
+    let f0 = mix(vec3<f32>(surface.reflectance), surface.base_color, surface.metallic);
+    let fresnel = fresnel_schlick(f0, v_dot_h);
+    let specular = distribution_ggx(n_dot_h, alpha) * visibility_smith(n_dot_v, n_dot_l, alpha) * fresnel;
+    let diffuse = (1.0 - fresnel) * (1.0 - surface.metallic) * surface.base_color / PI;
+
+    return (diffuse + specular) * radiance * n_dot_l;
+}
@@ -0,0 +1,11 @@
+// pushed by the renderer before every draw, see `pipeline::PUSH_CONSTANT_RANGE`.
+//
+// the GL backend compiles each stage from a copy of the module without what that stage
+// doesn't use, but looks the push constant types up by handle in the full module. so every
+// type declared ahead of them has to be used by the vertex stage: include this before any
+// resources, and note that naga declares `f32` first as soon as a function signature uses it,
+// which `to_world` in vertex_in.wgsl takes care of
+struct PushConsts {
+    view_projection: mat4x4<f32>,
+};
+var<push_constant> pc: PushConsts;
@@ -0,0 +1,51 @@
+// cascaded shadow maps of one directional light, `ShadowUniforms` on the Rust side
+
+// `shadows::CASCADE_COUNT`
+const SHADOW_CASCADES: u32 = 4u;
+
+struct Shadows {
+    // world space to the clip space of each cascade's shadow map
+    view_projections: array<mat4x4<f32>, 4>,
+    // the view depth each cascade ends at
+    splits: vec4<f32>,
+    // the world size of a shadow map texel in each cascade
+    texel_sizes: vec4<f32>,
+    // index of the shadowed light, 0xffffffff if no light casts shadows
+    light: u32,
+    map_size: f32,
+};
+
+// how much of the shadowed light reaches `position` at view depth `depth`, from 0 in full
+// shadow to 1. averages 3x3 filtered comparisons (PCF) so the shadow edges are soft
+fn shadow_visibility(
+    shadows: Shadows,
+    maps: texture_depth_2d_array,
+    comparison: sampler_comparison,
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    depth: f32,
+) -> f32 {
+    var cascade = 0u;
+    while cascade < SHADOW_CASCADES && depth >= shadows.splits[cascade] {
+        cascade++;
+    }
+    // past the shadow distance
+    if cascade == SHADOW_CASCADES {
+        return 1.0;
+    }
+
+    // a texel's worth off the surface, so it doesn't shadow itself
+    let offset = position + normal * shadows.texel_sizes[cascade] * 1.5;
+    let clip = shadows.view_projections[cascade] * vec4<f32>(offset, 1.0);
+    let uv = clip.xy * vec2<f32>(0.5, -0.5) + 0.5;
+    let texel = 1.0 / shadows.map_size;
+
+    var visibility = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let tap = uv + vec2<f32>(f32(x), f32(y)) * texel;
+            visibility += textureSampleCompareLevel(maps, comparison, tap, cascade, clip.z);
+        }
+    }
+    return visibility / 9.0;
+}
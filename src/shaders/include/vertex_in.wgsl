@@ -0,0 +1,23 @@
+// `VertexData` at locations 0-3, the `InstanceData` model matrix at 4-7
+struct VertexIn {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coord: vec2<f32>,
+    @location(2) normal: vec3<f32>,
+    // w is the sign of the bitangent
+    @location(3) tangent: vec4<f32>,
+
+    // instance transform matrix
+    @location(4) i_m0: vec4<f32>,
+    @location(5) i_m1: vec4<f32>,
+    @location(6) i_m2: vec4<f32>,
+    @location(7) i_m3: vec4<f32>,
+};
+
+fn instance_model(v: VertexIn) -> mat4x4<f32> {
+    return mat4x4<f32>(v.i_m0, v.i_m1, v.i_m2, v.i_m3);
+}
+
+// a model space point (`w` = 1) or direction (`w` = 0) in world space
+fn to_world(model: mat4x4<f32>, v: vec3<f32>, w: f32) -> vec4<f32> {
+    return model * vec4<f32>(v, w);
+}
@@ -0,0 +1,146 @@
+// every model pipeline is a variant of this shader, `MaterialFeatures::defines` picks the
+// features:
+//
+// BASE_TEXTURE       the base color is scaled by a texture
+// ROUGHNESS_TEXTURE  the roughness is scaled by the red channel of a texture
+// METALLIC_TEXTURE   the metallic factor is scaled by the red channel of a texture
+// NORMAL_TEXTURE     the normal is bent by a tangent space normal map
+// SPECULAR_TEXTURE   the reflectance is scaled by the average of a texture's rgb
+// EMISSIVE_TEXTURE   the emissive color is scaled by a texture
+// DOUBLE_SIDED       back faces are lit from their own side
+// ALPHA_MASK         fragments with an alpha below the material's cutoff are discarded
+// ALPHA_BLEND        the alpha is blended by the pipeline
+// WEIGHTED_BLENDED   with ALPHA_BLEND, the surface is summed into the targets of
+//                    include/oit.wgsl instead, in `TransparencyMode::WeightedBlended`
+//
+// every variant binds the same material layout, maps a material doesn't have are white
+#include "include/push_constants.wgsl"
+#include "include/vertex_in.wgsl"
+#include "include/frame.wgsl"
+#include "include/lights.wgsl"
+#include "include/shadows.wgsl"
+#include "include/pbr.wgsl"
+#ifdef WEIGHTED_BLENDED
+#include "include/oit.wgsl"
+#endif
+
+@group(0) @binding(0) var<uniform> material: PbrMaterial;
+@group(0) @binding(1) var base_texture: texture_2d<f32>;
+@group(0) @binding(2) var material_sampler: sampler;
+@group(0) @binding(3) var roughness_texture: texture_2d<f32>;
+@group(0) @binding(4) var metallic_texture: texture_2d<f32>;
+@group(0) @binding(5) var normal_texture: texture_2d<f32>;
+@group(0) @binding(6) var specular_texture: texture_2d<f32>;
+@group(0) @binding(7) var emissive_texture: texture_2d<f32>;
+
+@group(1) @binding(0) var<uniform> frame: Frame;
+@group(1) @binding(1) var<storage, read> lights: array<Light>;
+// filled by cluster_lights.wgsl
+@group(1) @binding(2) var<storage, read> clusters: array<u32>;
+@group(1) @binding(3) var<uniform> shadows: Shadows;
+@group(1) @binding(4) var shadow_maps: texture_depth_2d_array;
+@group(1) @binding(5) var shadow_sampler: sampler_comparison;
+
+struct VertexPayload {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coord: vec2<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) world_position: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
+};
+
+@vertex
+fn vs_main(v: VertexIn) -> VertexPayload {
+    let model = instance_model(v);
+    let world_position = to_world(model, v.position, 1.0);
+
+    var out: VertexPayload;
+    out.position = pc.view_projection * world_position;
+    out.tex_coord = v.tex_coord;
+    out.normal = to_world(model, v.normal, 0.0).xyz;
+    out.tangent = vec4<f32>(to_world(model, v.tangent.xyz, 0.0).xyz, v.tangent.w);
+    out.world_position = world_position.xyz;
+
+    return out;
+}
+
+@fragment
+#ifdef WEIGHTED_BLENDED
+fn fs_main(in: VertexPayload, @builtin(front_facing) front_facing: bool) -> Accumulation {
+#else
+fn fs_main(in: VertexPayload, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+#endif
+    var normal = in.normal;
+#ifdef NORMAL_TEXTURE
+    // MikkTSpace's frame: the bitangent is rebuilt from the interpolated normal and tangent,
+    // neither of them normalized first
+    let bent = textureSample(normal_texture, material_sampler, in.tex_coord).xyz * 2.0 - 1.0;
+    let bitangent = in.tangent.w * cross(in.normal, in.tangent.xyz);
+    normal = bent.x * in.tangent.xyz + bent.y * bitangent + bent.z * in.normal;
+#endif
+#ifdef DOUBLE_SIDED
+    normal = select(-normal, normal, front_facing);
+#endif
+    normal = normalize(normal);
+
+    var base = material.base_color;
+#ifdef BASE_TEXTURE
+    base *= textureSample(base_texture, material_sampler, in.tex_coord);
+#endif
+
+    var surface: Surface;
+    surface.base_color = base.rgb;
+    surface.metallic = material.metallic;
+    surface.roughness = material.roughness;
+    surface.reflectance = material.reflectance;
+#ifdef ROUGHNESS_TEXTURE
+    surface.roughness *= textureSample(roughness_texture, material_sampler, in.tex_coord).r;
+#endif
+#ifdef METALLIC_TEXTURE
+    surface.metallic *= textureSample(metallic_texture, material_sampler, in.tex_coord).r;
+#endif
+#ifdef SPECULAR_TEXTURE
+    let specular = textureSample(specular_texture, material_sampler, in.tex_coord).rgb;
+    surface.reflectance *= dot(specular, vec3<f32>(1.0 / 3.0));
+#endif
+
+    var emissive = material.emissive;
+#ifdef EMISSIVE_TEXTURE
+    emissive *= textureSample(emissive_texture, material_sampler, in.tex_coord).rgb;
+#endif
+
+#ifdef ALPHA_MASK
+    // after every sample, they need the neighbouring fragments
+    if base.a < material.alpha_cutoff {
+        discard;
+    }
+#endif
+
+    // only the lights of the fragment's cluster can reach it
+    let depth = -(frame.view * vec4<f32>(in.world_position, 1.0)).z;
+    let tile = min(
+        vec2<u32>(in.position.xy / frame.cluster_extent.xy),
+        frame.cluster_count.xy - 1u,
+    );
+    let cluster = (depth_slice(frame, depth) * frame.cluster_count.y + tile.y)
+        * frame.cluster_count.x + tile.x;
+    let start = cluster * CLUSTER_STRIDE;
+
+    let view = normalize(frame.camera_position.xyz - in.world_position);
+    var color = emissive;
+    for (var i = 0u; i < min(clusters[start], CLUSTER_STRIDE - 1u); i++) {
+        let index = clusters[start + 1u + i];
+        var incoming = incoming_light(lights[index], in.world_position);
+        if index == shadows.light {
+            incoming.radiance *= shadow_visibility(
+                shadows, shadow_maps, shadow_sampler, in.world_position, normal, depth,
+            );
+        }
+        color += shade(surface, normal, view, incoming.direction, incoming.radiance);
+    }
+#ifdef WEIGHTED_BLENDED
+    return accumulate(vec4<f32>(color, base.a), depth);
+#else
+    return vec4<f32>(color, base.a);
+#endif
+}
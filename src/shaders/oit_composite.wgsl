@@ -0,0 +1,21 @@
+// puts the blended surfaces summed by include/oit.wgsl over the opaque frame, which the
+// pipeline blends with the source alpha
+@group(0) @binding(0) var accumulation: texture_2d<f32>;
+@group(0) @binding(1) var revealage: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    // one triangle covering the screen
+    let corner = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(corner * 2.0 - 1.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let texel = vec2<i32>(position.xy);
+    let sum = textureLoad(accumulation, texel, 0);
+    let revealed = textureLoad(revealage, texel, 0).r;
+
+    // the weighted average color covers all but what is revealed
+    return vec4<f32>(sum.rgb / max(sum.a, 1e-5), 1.0 - revealed);
+}
@@ -0,0 +1,42 @@
+// draws every instance into one cascade of the shadow maps, depth only.
+// the cascade's view-projection is pushed in place of the camera's
+//
+// ALPHA_MASK  the base color's alpha is sampled and fragments below the material's cutoff
+//             are discarded, for `AlphaMode::Mask` materials. binds the material layout
+#include "include/push_constants.wgsl"
+#include "include/vertex_in.wgsl"
+#ifdef ALPHA_MASK
+#include "include/pbr.wgsl"
+
+// the first bindings of the material layout, see model.wgsl
+@group(0) @binding(0) var<uniform> material: PbrMaterial;
+@group(0) @binding(1) var base_texture: texture_2d<f32>;
+@group(0) @binding(2) var material_sampler: sampler;
+#endif
+
+struct VertexPayload {
+    @builtin(position) position: vec4<f32>,
+#ifdef ALPHA_MASK
+    @location(0) tex_coord: vec2<f32>,
+#endif
+};
+
+@vertex
+fn vs_main(v: VertexIn) -> VertexPayload {
+    var out: VertexPayload;
+    out.position = pc.view_projection * to_world(instance_model(v), v.position, 1.0);
+#ifdef ALPHA_MASK
+    out.tex_coord = v.tex_coord;
+#endif
+    return out;
+}
+
+#ifdef ALPHA_MASK
+@fragment
+fn fs_main(in: VertexPayload) {
+    let alpha = material.base_color.a * textureSample(base_texture, material_sampler, in.tex_coord).a;
+    if alpha < material.alpha_cutoff {
+        discard;
+    }
+}
+#endif
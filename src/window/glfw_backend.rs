@@ -0,0 +1,54 @@
+use glfw::{Action, PWindow};
+
+use super::{InputSource, Key, SurfaceProvider};
+
+impl SurfaceProvider for PWindow {
+    fn surface_target(&mut self) -> wgpu::SurfaceTarget<'static> {
+        // the render context is an owned, thread safe copy of the window handle
+        wgpu::SurfaceTarget::from(self.render_context())
+    }
+
+    fn framebuffer_size(&self) -> (u32, u32) {
+        let (width, height) = self.get_framebuffer_size();
+        (width as u32, height as u32)
+    }
+}
+
+impl InputSource for PWindow {
+    fn is_focused(&self) -> bool {
+        glfw::Window::is_focused(self)
+    }
+
+    fn is_key_down(&self, key: Key) -> bool {
+        let key = match key {
+            Key::W => glfw::Key::W,
+            Key::A => glfw::Key::A,
+            Key::S => glfw::Key::S,
+            Key::D => glfw::Key::D,
+            Key::Space => glfw::Key::Space,
+            Key::LeftShift => glfw::Key::LeftShift,
+        };
+
+        self.get_key(key) == Action::Press
+    }
+
+    /// measures the cursor against the window center, then warps it back there. the cursor
+    /// is in screen coordinates, the delta is scaled to framebuffer pixels for HiDPI displays
+    fn take_mouse_delta(&mut self) -> (f64, f64) {
+        let (width, height) = self.get_size();
+        let center = (width as f64 / 2.0, height as f64 / 2.0);
+
+        let mouse_pos = self.get_cursor_pos();
+        self.set_cursor_pos(center.0, center.1);
+
+        let (fb_width, fb_height) = self.get_framebuffer_size();
+        let scale = (
+            fb_width as f64 / (width as f64).max(1.0),
+            fb_height as f64 / (height as f64).max(1.0),
+        );
+        (
+            (mouse_pos.0 - center.0) * scale.0,
+            (mouse_pos.1 - center.1) * scale.1,
+        )
+    }
+}
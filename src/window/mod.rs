@@ -0,0 +1,38 @@
+//! windowing abstraction so the renderer doesn't depend on a specific windowing library.
+//! backends are picked with the `glfw` and `winit` cargo features.
+
+#[cfg(feature = "glfw")]
+pub mod glfw_backend;
+#[cfg(feature = "winit")]
+pub mod winit_backend;
+
+/// anything the renderer can create a surface for and present to
+pub trait SurfaceProvider {
+    /// an owned handle to the native window, used to create a wgpu surface
+    fn surface_target(&mut self) -> wgpu::SurfaceTarget<'static>;
+
+    /// size of the drawable area in pixels
+    fn framebuffer_size(&self) -> (u32, u32);
+}
+
+/// keys the camera controls are bound to
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Key {
+    W,
+    A,
+    S,
+    D,
+    Space,
+    LeftShift,
+}
+
+/// keyboard and mouse state the camera reads every frame
+pub trait InputSource {
+    fn is_focused(&self) -> bool;
+
+    fn is_key_down(&self, key: Key) -> bool;
+
+    /// cursor movement in framebuffer pixels since the last call, the units `Camera::update`
+    /// measures the surface in
+    fn take_mouse_delta(&mut self) -> (f64, f64);
+}
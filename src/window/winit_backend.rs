@@ -0,0 +1,87 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use winit::event::{DeviceEvent, ElementState, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+use winit::window::Window;
+
+use super::{InputSource, Key, SurfaceProvider};
+
+impl SurfaceProvider for Arc<Window> {
+    fn surface_target(&mut self) -> wgpu::SurfaceTarget<'static> {
+        wgpu::SurfaceTarget::from(self.clone())
+    }
+
+    fn framebuffer_size(&self) -> (u32, u32) {
+        let size = self.inner_size();
+        (size.width, size.height)
+    }
+}
+
+/// winit only reports input through events, so this accumulates them for the camera
+#[derive(Default)]
+pub struct WinitInput {
+    focused: bool,
+    keys_down: HashSet<Key>,
+    mouse_delta: (f64, f64),
+}
+
+impl WinitInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::Focused(focused) => {
+                self.focused = *focused;
+                if !focused {
+                    self.keys_down.clear();
+                }
+            }
+
+            WindowEvent::KeyboardInput { event, .. } => {
+                let PhysicalKey::Code(code) = event.physical_key else {
+                    return;
+                };
+                let key = match code {
+                    KeyCode::KeyW => Key::W,
+                    KeyCode::KeyA => Key::A,
+                    KeyCode::KeyS => Key::S,
+                    KeyCode::KeyD => Key::D,
+                    KeyCode::Space => Key::Space,
+                    KeyCode::ShiftLeft => Key::LeftShift,
+                    _ => return,
+                };
+
+                match event.state {
+                    ElementState::Pressed => self.keys_down.insert(key),
+                    ElementState::Released => self.keys_down.remove(&key),
+                };
+            }
+            _ => {}
+        }
+    }
+
+    /// raw mouse motion, reported even while the cursor is grabbed
+    pub fn handle_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta } = event {
+            self.mouse_delta.0 += delta.0;
+            self.mouse_delta.1 += delta.1;
+        }
+    }
+}
+
+impl InputSource for WinitInput {
+    fn is_focused(&self) -> bool {
+        self.focused
+    }
+
+    fn is_key_down(&self, key: Key) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    fn take_mouse_delta(&mut self) -> (f64, f64) {
+        std::mem::take(&mut self.mouse_delta)
+    }
+}
@@ -0,0 +1,125 @@
+//! Models and textures load the same from every kind of asset source.
+
+pub mod common;
+
+use std::path::Path;
+
+use common::{MemoryAssets, frame};
+use glam::{Mat4, Vec3};
+use image::RgbaImage;
+use include_dir::{Dir, include_dir};
+use project::renderer::backend::assets::{
+    ArchiveAssets, AssetSource, DirectoryAssets, EmbeddedAssets, normalize,
+};
+use project::renderer::renderer::RendererState;
+
+static ASSETS_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/assets");
+
+/// the companion cube and a spaceship from the source `set_source` picks, `prefix` goes in
+/// front of their paths
+fn render(set_source: impl FnOnce(&mut RendererState), prefix: &str) -> RgbaImage {
+    let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+    set_source(&mut state);
+    state
+        .load_assets(
+            "companion_cube",
+            &format!("{prefix}companion_cube/companion_cube.obj"),
+        )
+        .unwrap();
+    state
+        .load_assets("spaceship", &format!("{prefix}spaceship/spaceship.obj"))
+        .unwrap();
+    state
+        .spawn_instance(
+            "companion_cube",
+            Mat4::from_translation(Vec3::new(80.0, 44.0, 0.0)),
+        )
+        .unwrap();
+    state
+        .spawn_instance(
+            "spaceship",
+            Mat4::from_translation(Vec3::new(10.0, 0.0, 0.0)),
+        )
+        .unwrap();
+
+    frame(&mut state)
+}
+
+#[test]
+fn normalizes_paths() {
+    assert_eq!(normalize(Path::new("a/./b/../c.png")), "a/c.png");
+    assert_eq!(normalize(Path::new("../a/b")), "../a/b");
+    assert_eq!(normalize(Path::new("/tmp/x/../y")), "/tmp/y");
+}
+
+#[test]
+fn embedded_and_archived_assets_render_like_the_directory() {
+    let from_disk = render(|_| {}, "assets/");
+
+    let embedded = render(
+        |state| state.set_asset_source(EmbeddedAssets::new(&ASSETS_DIR)),
+        "",
+    );
+    assert!(embedded == from_disk);
+
+    let archive = common::temp_dir("assets_pack").join("assets.pack");
+    ArchiveAssets::pack(Path::new("assets"), &archive).unwrap();
+    let archived = render(
+        |state| state.set_asset_source(ArchiveAssets::open(&archive).unwrap()),
+        "",
+    );
+    assert!(archived == from_disk);
+    std::fs::remove_dir_all(archive.parent().unwrap()).ok();
+}
+
+#[test]
+fn missing_files_read_as_none() {
+    let sources: [Box<dyn AssetSource>; 2] = [
+        Box::new(EmbeddedAssets::new(&ASSETS_DIR)),
+        Box::new(DirectoryAssets::new("assets")),
+    ];
+    for source in &sources {
+        assert!(source.read("spaceship/spaceship.mtl").is_some());
+        assert!(source.read("spaceship/missing.mtl").is_none());
+    }
+}
+
+#[test]
+fn missing_and_broken_files_are_load_errors() {
+    let files: MemoryAssets = [
+        (
+            "no_mtl.obj",
+            "mtllib missing.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n".as_bytes(),
+        ),
+        (
+            "no_map.obj",
+            b"mtllib no_map.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl m\nf 1 2 3\n",
+        ),
+        ("no_map.mtl", b"newmtl m\nmap_Kd missing.png\n"),
+        (
+            "bad_map.obj",
+            b"mtllib bad_map.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl m\nf 1 2 3\n",
+        ),
+        ("bad_map.mtl", b"newmtl m\nmap_Kd bad.png\n"),
+        ("bad.png", b"not a png"),
+    ]
+    .into_iter()
+    .collect();
+    let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+    state.set_asset_source(files);
+
+    for (obj, error) in [
+        ("missing.obj", "missing.obj"),
+        ("no_mtl.obj", "material library"),
+        ("no_map.obj", "missing texture missing.png"),
+        ("bad_map.obj", "bad.png"),
+    ] {
+        let result = state.load_assets("broken", obj);
+        assert!(
+            result.as_ref().is_err_and(|e| e.contains(error)),
+            "{obj}: {result:?}"
+        );
+        assert!(state.spawn_instance("broken", Mat4::IDENTITY).is_err());
+    }
+    assert_eq!(state.loaded_textures(), 0);
+}
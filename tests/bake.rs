@@ -0,0 +1,171 @@
+//! Baking assets into binary meshes and textures, and loading them back through the manifest.
+
+pub mod common;
+
+use std::path::PathBuf;
+
+use common::{SPACESHIP, frame, temp_dir};
+use glam::{Mat4, Vec3};
+use project::renderer::backend::assets::DirectoryAssets;
+use project::renderer::backend::bake::{CompiledTexture, MANIFEST_NAME, Manifest, bake};
+use project::renderer::backend::mesh_cache::read_compressed;
+use project::renderer::renderer::RendererState;
+
+#[test]
+fn bakes_the_bundled_assets() {
+    let out_dir = temp_dir("bake_bundled");
+    let report = bake(&PathBuf::from("assets"), &out_dir);
+
+    assert_eq!(report.problems, Vec::<String>::new());
+    let ids: Vec<&String> = report.manifest.meshes.keys().collect();
+    assert_eq!(ids, ["companion_cube", "spaceship"]);
+    assert!(out_dir.join(MANIFEST_NAME).is_file());
+
+    let cube = &report.manifest.meshes["companion_cube"];
+    assert_eq!(cube.textures.len(), 1);
+    assert_eq!(cube.source, "companion_cube/companion_cube.obj");
+    let texture: CompiledTexture = read_compressed(&out_dir.join(&cube.textures[0])).unwrap();
+    let largest = texture.width.max(texture.height);
+    assert_eq!(texture.levels.len() as u32, largest.ilog2() + 1);
+    assert_eq!(texture.levels.last().unwrap().len(), 4);
+
+    std::fs::remove_dir_all(&out_dir).ok();
+}
+
+#[test]
+fn manifest_renders_like_the_obj() {
+    let out_dir = temp_dir("bake_render");
+    bake(&PathBuf::from("assets"), &out_dir);
+
+    let render = |baked: bool| {
+        let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+        if baked {
+            state.set_asset_source(DirectoryAssets::new(&out_dir));
+            state.load_manifest(MANIFEST_NAME).unwrap();
+        } else {
+            state.load_assets("spaceship", SPACESHIP).unwrap();
+        }
+        state
+            .spawn_instance(
+                "spaceship",
+                Mat4::from_translation(Vec3::new(10.0, 0.0, 0.0)),
+            )
+            .unwrap();
+        frame(&mut state)
+    };
+
+    assert!(render(true) == render(false));
+    std::fs::remove_dir_all(&out_dir).ok();
+}
+
+#[test]
+fn reports_broken_references() {
+    let asset_dir = temp_dir("bake_broken_assets");
+    let triangle = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+    std::fs::write(
+        asset_dir.join("missing_texture.obj"),
+        format!("mtllib missing_texture.mtl\nusemtl Broken\n{triangle}"),
+    )
+    .unwrap();
+    std::fs::write(
+        asset_dir.join("missing_texture.mtl"),
+        "newmtl Broken\nKd 1 1 1\nmap_Kd nowhere.png\n",
+    )
+    .unwrap();
+    std::fs::write(
+        asset_dir.join("missing_mtl.obj"),
+        format!("mtllib absent.mtl\n{triangle}"),
+    )
+    .unwrap();
+
+    let out_dir = temp_dir("bake_broken_out");
+    let report = bake(&asset_dir, &out_dir);
+
+    assert!(report.manifest.meshes.is_empty());
+    assert_eq!(report.problems.len(), 2, "{:?}", report.problems);
+    assert!(report.problems.iter().any(|p| p.contains("nowhere.png")));
+    assert!(
+        report
+            .problems
+            .iter()
+            .any(|p| p.contains("could not load materials"))
+    );
+
+    std::fs::remove_dir_all(&asset_dir).ok();
+    std::fs::remove_dir_all(&out_dir).ok();
+}
+
+#[test]
+fn reports_textures_that_fail_to_bake() {
+    let asset_dir = temp_dir("bake_undecodable_assets");
+    std::fs::write(
+        asset_dir.join("garbled.obj"),
+        "mtllib garbled.mtl\nusemtl Garbled\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n",
+    )
+    .unwrap();
+    std::fs::write(
+        asset_dir.join("garbled.mtl"),
+        "newmtl Garbled\nKd 1 1 1\nmap_Kd garbled.png\n",
+    )
+    .unwrap();
+    std::fs::write(asset_dir.join("garbled.png"), "not a png").unwrap();
+
+    let out_dir = temp_dir("bake_undecodable_out");
+    let report = bake(&asset_dir, &out_dir);
+
+    assert!(report.manifest.meshes.is_empty());
+    assert!(report.manifest.textures.is_empty());
+    assert_eq!(report.problems.len(), 1, "{:?}", report.problems);
+    assert!(report.problems[0].contains("garbled.png"));
+    assert!(out_dir.join(MANIFEST_NAME).is_file());
+
+    std::fs::remove_dir_all(&asset_dir).ok();
+    std::fs::remove_dir_all(&out_dir).ok();
+}
+
+#[test]
+fn broken_manifests_are_errors() {
+    let out_dir = temp_dir("bake_broken_manifest");
+    bake(&PathBuf::from("assets"), &out_dir);
+
+    let load = |manifest: &str| {
+        let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+        state.set_asset_source(DirectoryAssets::new(&out_dir));
+        state.load_manifest(manifest)
+    };
+
+    assert!(load("missing.toml").unwrap_err().contains("missing.toml"));
+
+    std::fs::write(out_dir.join("garbled.toml"), "meshes = 3").unwrap();
+    assert!(load("garbled.toml").unwrap_err().contains("garbled.toml"));
+
+    let spaceship =
+        Manifest::from_toml(&std::fs::read_to_string(out_dir.join(MANIFEST_NAME)).unwrap())
+            .unwrap()
+            .meshes
+            .remove("spaceship")
+            .unwrap();
+    std::fs::write(out_dir.join(&spaceship.mesh), "not a mesh").unwrap();
+    assert!(load(MANIFEST_NAME).unwrap_err().contains(&spaceship.mesh));
+
+    // textures are checked before any model is added, the cube is renamed to load last
+    let mut manifest = bake(&PathBuf::from("assets"), &out_dir).manifest;
+    let cube = manifest.meshes.remove("companion_cube").unwrap();
+    let texture = cube.textures[0].clone();
+    manifest.meshes.insert("z_cube".to_string(), cube);
+    manifest.write(&out_dir.join(MANIFEST_NAME)).unwrap();
+    std::fs::write(out_dir.join(&texture), "not a texture").unwrap();
+    let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+    state.set_asset_source(DirectoryAssets::new(&out_dir));
+    let error = state.load_manifest(MANIFEST_NAME).unwrap_err();
+    assert!(error.contains(&texture), "{error}");
+    for id in manifest.meshes.keys() {
+        assert!(state.spawn_instance(id, Mat4::IDENTITY).is_err());
+    }
+
+    std::fs::remove_file(out_dir.join(&texture)).unwrap();
+    let error = load(MANIFEST_NAME).unwrap_err();
+    assert!(error.contains("missing texture") && error.contains(&texture));
+
+    std::fs::remove_dir_all(&out_dir).ok();
+}
@@ -0,0 +1,91 @@
+//! Fixtures the integration tests share, each test file pulls them in with `pub mod common;`
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use glam::Mat4;
+use image::{Rgba, RgbaImage};
+use project::renderer::backend::assets::AssetSource;
+use project::renderer::backend::definitions::Camera;
+use project::renderer::renderer::RendererState;
+
+pub const COMPANION_CUBE: &str = "assets/companion_cube/companion_cube.obj";
+pub const SPACESHIP: &str = "assets/spaceship/spaceship.obj";
+
+/// files held in memory, for models, materials and shaders generated by a test
+#[derive(Default)]
+pub struct MemoryAssets(HashMap<String, Vec<u8>>);
+
+impl MemoryAssets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_file(&mut self, path: &str, contents: impl Into<Vec<u8>>) {
+        self.0.insert(path.to_string(), contents.into());
+    }
+}
+
+impl<P: Into<String>, C: Into<Vec<u8>>> FromIterator<(P, C)> for MemoryAssets {
+    fn from_iter<I: IntoIterator<Item = (P, C)>>(files: I) -> Self {
+        MemoryAssets(
+            files
+                .into_iter()
+                .map(|(path, contents)| (path.into(), contents.into()))
+                .collect(),
+        )
+    }
+}
+
+impl AssetSource for MemoryAssets {
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.0.get(path).cloned()
+    }
+}
+
+/// a 4x4 PNG of a single color
+pub fn png(color: [u8; 4]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    RgbaImage::from_pixel(4, 4, Rgba(color))
+        .write_to(
+            &mut std::io::Cursor::new(&mut bytes),
+            image::ImageFormat::Png,
+        )
+        .unwrap();
+    bytes
+}
+
+/// a 64x48 headless renderer with the OBJ at `path` loaded as `id` and one instance of it
+/// at `transform`
+pub fn scene(id: &str, path: &str, transform: Mat4) -> RendererState {
+    let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+    state.load_assets(id, path).unwrap();
+    state.spawn_instance(id, transform).unwrap();
+    state
+}
+
+/// renders from the default camera and reads the frame back
+pub fn frame(state: &mut RendererState) -> RgbaImage {
+    state.render(&Camera::new()).unwrap();
+    state.read_frame().unwrap()
+}
+
+/// true if no more than 1% of the pixels differ by more than rounding. for frames whose draw
+/// order isn't fixed, like those culled on the GPU: its compaction packs the visible instances
+/// in whatever order the threads finish, coplanar surfaces then win the depth test differently
+pub fn frames_match(a: &RgbaImage, b: &RgbaImage) -> bool {
+    let differing = a
+        .pixels()
+        .zip(b.pixels())
+        .filter(|(a, b)| a.0.iter().zip(b.0).any(|(a, b)| a.abs_diff(b) > 2))
+        .count();
+    a.dimensions() == b.dimensions() && differing * 100 <= (a.width() * a.height()) as usize
+}
+
+/// a fresh, empty directory under the system temp dir. `name` keeps parallel tests apart
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,122 @@
+//! Frustum culling counts for instances in front of and behind the default camera.
+
+pub mod common;
+
+use common::{SPACESHIP, frame, frames_match};
+use glam::{Mat4, Vec3};
+use project::renderer::backend::definitions::{Camera, CullingMode, Projection};
+use project::renderer::renderer::RendererState;
+
+fn scene() -> RendererState {
+    let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+    state.load_assets("spaceship", SPACESHIP).unwrap();
+
+    // the camera sits at (-5, 0, 2) looking down +x
+    for x in [25.0, 50.0, -60.0] {
+        state
+            .spawn_instance("spaceship", Mat4::from_translation(Vec3::new(x, 0.0, 0.0)))
+            .unwrap();
+    }
+    // far off to the side
+    state
+        .spawn_instance(
+            "spaceship",
+            Mat4::from_translation(Vec3::new(25.0, 500.0, 0.0)),
+        )
+        .unwrap();
+    state
+}
+
+#[test]
+fn culls_instances_outside_the_frustum() {
+    let mut state = scene();
+    state.render(&Camera::new()).unwrap();
+
+    let stats = state.frame_stats();
+    assert_eq!(stats.visible_instances, 2);
+    assert_eq!(stats.frustum_culled_instances, 2);
+    assert_eq!(state.instance_counts["spaceship"], 2);
+}
+
+#[test]
+fn culling_off_draws_everything() {
+    let mut state = scene();
+    state.set_culling_mode(CullingMode::Off);
+    state.render(&Camera::new()).unwrap();
+
+    let stats = state.frame_stats();
+    assert_eq!(stats.visible_instances, 4);
+    assert_eq!(stats.frustum_culled_instances, 0);
+}
+
+#[test]
+fn gpu_culling_matches_cpu_culling() {
+    let render = |mode| {
+        let mut state = scene();
+        state.set_culling_mode(mode);
+        frame(&mut state)
+    };
+
+    let cpu = render(CullingMode::Cpu);
+    let gpu = render(CullingMode::Gpu);
+    assert!(
+        cpu.pixels().any(|p| p.0 != [0, 0, 25, 255]),
+        "nothing was drawn"
+    );
+    assert!(
+        frames_match(&cpu, &gpu),
+        "GPU culling drew a different frame"
+    );
+}
+
+fn assert_occludes_hidden_instances(camera: &Camera) {
+    let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+    state
+        .load_assets("companion_cube", "assets/companion_cube/companion_cube.obj")
+        .unwrap();
+    state.load_assets("spaceship", SPACESHIP).unwrap();
+    state.set_culling_mode(CullingMode::Gpu);
+    state.set_occlusion_culling(true);
+
+    // the cube model sits around (0, -44, 0) and is ~40 units wide, this centers it in view
+    state
+        .spawn_instance(
+            "companion_cube",
+            Mat4::from_translation(Vec3::new(40.0, 44.0, 2.0)),
+        )
+        .unwrap();
+    // right behind the cube
+    state
+        .spawn_instance(
+            "spaceship",
+            Mat4::from_translation(Vec3::new(120.0, 0.0, 2.0)),
+        )
+        .unwrap();
+
+    // the first frame has no previous depth to test against
+    state.render(camera).unwrap();
+    let stats = state.read_gpu_cull_stats().unwrap();
+    assert_eq!(stats.visible_instances, 2);
+    assert_eq!(stats.occlusion_culled_instances, 0);
+
+    state.render(camera).unwrap();
+    let stats = state.read_gpu_cull_stats().unwrap();
+    assert_eq!(stats.visible_instances, 1);
+    assert_eq!(stats.frustum_culled_instances, 0);
+    assert_eq!(stats.occlusion_culled_instances, 1);
+}
+
+#[test]
+fn occlusion_culls_instances_hidden_behind_the_previous_frame() {
+    assert_occludes_hidden_instances(&Camera::new());
+}
+
+#[test]
+fn occlusion_culling_with_reverse_z() {
+    let mut camera = Camera::new();
+    camera.projection = Projection::ReverseZInfinite {
+        fov_y: 80.0,
+        z_near: 0.5,
+    };
+    assert_occludes_hidden_instances(&camera);
+}
@@ -0,0 +1,224 @@
+//! Golden-image regression tests for the instanced pipelines.
+//!
+//! Each test renders a small scene offscreen and compares it against a reference PNG in
+//! `tests/golden/`. On mismatch the actual frame and a diff image (failing pixels in red)
+//! are written to `target/golden/`.
+//!
+//! * `GOLDEN_BLESS=1` overwrites the references with the current output
+//! * `GOLDEN_TOLERANCE=<n>` overrides the allowed per-channel difference
+
+use std::path::{Path, PathBuf};
+
+use glam::{Mat4, Quat, Vec3};
+use image::{Rgba, RgbaImage};
+use project::renderer::backend::definitions::{Camera, Projection};
+use project::renderer::renderer::RendererState;
+
+const WIDTH: u32 = 256;
+const HEIGHT: u32 = 192;
+
+/// how far a rendered frame may drift from its reference
+#[derive(Clone, Copy)]
+struct Tolerance {
+    /// max absolute difference allowed in any one channel of a pixel
+    per_channel: u8,
+    /// fraction of pixels allowed to exceed `per_channel` (rasterization differences between adapters)
+    max_failing_fraction: f32,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        let per_channel = std::env::var("GOLDEN_TOLERANCE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2);
+
+        Tolerance {
+            per_channel,
+            max_failing_fraction: 0.001,
+        }
+    }
+}
+
+/// a model file and the transforms of its instances
+struct SceneModel {
+    id: &'static str,
+    path: &'static str,
+    instances: Vec<Mat4>,
+}
+
+fn render_scene(models: Vec<SceneModel>) -> RgbaImage {
+    render_scene_with(models, &Camera::new(), WIDTH, HEIGHT)
+}
+
+fn render_scene_with(
+    models: Vec<SceneModel>,
+    camera: &Camera,
+    width: u32,
+    height: u32,
+) -> RgbaImage {
+    let mut state = pollster::block_on(RendererState::new_headless(width, height));
+
+    for model in models {
+        state.load_assets(model.id, model.path).unwrap();
+        for transform in model.instances {
+            state.spawn_instance(model.id, transform).unwrap();
+        }
+    }
+
+    state.render(camera).unwrap();
+    state.read_frame().unwrap()
+}
+
+fn output_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target/golden")
+}
+
+/// compares `actual` against `tests/golden/<name>.png`, writing the actual and diff images on failure
+fn assert_golden(name: &str, actual: &RgbaImage, tolerance: Tolerance) {
+    let reference_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"));
+
+    if std::env::var_os("GOLDEN_BLESS").is_some() {
+        std::fs::create_dir_all(reference_path.parent().unwrap()).unwrap();
+        actual.save(&reference_path).unwrap();
+        return;
+    }
+
+    let expected = image::open(&reference_path)
+        .unwrap_or_else(|e| {
+            panic!(
+                "can't open reference {} ({e}), run with GOLDEN_BLESS=1 to create it",
+                reference_path.display()
+            )
+        })
+        .to_rgba8();
+    assert_eq!(
+        expected.dimensions(),
+        actual.dimensions(),
+        "{name}: reference and rendered frame differ in size"
+    );
+
+    let mut diff = RgbaImage::new(actual.width(), actual.height());
+    let mut failing = 0;
+    for (x, y, a) in actual.enumerate_pixels() {
+        let e = expected.get_pixel(x, y);
+        let worst =
+            a.0.iter()
+                .zip(e.0.iter())
+                .map(|(a, e)| a.abs_diff(*e))
+                .max()
+                .unwrap();
+
+        if worst > tolerance.per_channel {
+            failing += 1;
+            diff.put_pixel(x, y, Rgba([255, 0, 0, 255]));
+        } else {
+            // dimmed reference so the failing pixels stand out
+            let [r, g, b, _] = e.0;
+            diff.put_pixel(x, y, Rgba([r / 3, g / 3, b / 3, 255]));
+        }
+    }
+
+    let allowed =
+        (tolerance.max_failing_fraction * (actual.width() * actual.height()) as f32) as u32;
+    if failing > allowed {
+        let dir = output_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let actual_path = dir.join(format!("{name}.actual.png"));
+        let diff_path = dir.join(format!("{name}.diff.png"));
+        actual.save(&actual_path).unwrap();
+        diff.save(&diff_path).unwrap();
+
+        panic!(
+            "{name}: {failing} pixels differ by more than {} (allowed {allowed}), see {} and {}",
+            tolerance.per_channel,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+/// a row of instances in front of the default camera, each turned a bit further.
+/// the camera sits at (-5, 0, 2) looking down +x with z up.
+/// instances keep unit scale since the shaders don't renormalize normals
+fn instance_row(count: usize, distance: f32, spacing: f32) -> Vec<Mat4> {
+    (0..count)
+        .map(|i| {
+            let offset = (i as f32 - (count - 1) as f32 / 2.0) * spacing;
+            // above the camera so the sun-lit undersides face it
+            let pos = Vec3::new(distance, offset, distance * 0.3);
+            let rot = Quat::from_euler(glam::EulerRot::ZYX, 0.4 * i as f32 - 0.4, 0.2, 0.1);
+            Mat4::from_rotation_translation(rot, pos)
+        })
+        .collect()
+}
+
+#[test]
+fn colored_model_pipeline() {
+    // spaceship.mtl has no texture, so it gets the flat color variant of the model shader
+    let frame = render_scene(vec![SceneModel {
+        id: "spaceship",
+        path: "assets/spaceship/spaceship.obj",
+        instances: instance_row(3, 25.0, 10.0),
+    }]);
+
+    assert_golden("colored_model", &frame, Tolerance::default());
+}
+
+#[test]
+fn textured_model_pipeline() {
+    // companion_cube.mtl has a map_Kd, so it gets the BASE_TEXTURE variant
+    let frame = render_scene(vec![SceneModel {
+        id: "companion_cube",
+        path: "assets/companion_cube/companion_cube.obj",
+        // the cube is ~40 units across in the OBJ
+        instances: instance_row(3, 150.0, 60.0),
+    }]);
+
+    assert_golden("textured_model", &frame, Tolerance::default());
+}
+
+fn spaceship_row() -> Vec<SceneModel> {
+    vec![SceneModel {
+        id: "spaceship",
+        path: "assets/spaceship/spaceship.obj",
+        instances: instance_row(3, 25.0, 10.0),
+    }]
+}
+
+#[test]
+fn reverse_z_projection() {
+    // only depth precision changes, so it has to match the standard projection's reference
+    let mut camera = Camera::new();
+    camera.projection = Projection::ReverseZInfinite {
+        fov_y: 80.0,
+        z_near: 0.5,
+    };
+    let frame = render_scene_with(spaceship_row(), &camera, WIDTH, HEIGHT);
+
+    assert_golden("colored_model", &frame, Tolerance::default());
+}
+
+#[test]
+fn wide_aspect_is_not_stretched() {
+    // same vertical field of view, so the middle of a wider frame is the 4:3 reference
+    let frame = render_scene_with(spaceship_row(), &Camera::new(), WIDTH + 128, HEIGHT);
+    let middle = image::imageops::crop_imm(&frame, 64, 0, WIDTH, HEIGHT).to_image();
+
+    assert_golden("colored_model", &middle, Tolerance::default());
+}
+
+#[test]
+fn orthographic_projection() {
+    let mut camera = Camera::new();
+    camera.projection = Projection::Orthographic {
+        height: 40.0,
+        z_near: 0.5,
+        z_far: 1000.0,
+    };
+    let frame = render_scene_with(spaceship_row(), &camera, WIDTH, HEIGHT);
+
+    assert_golden("orthographic", &frame, Tolerance::default());
+}
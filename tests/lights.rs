@@ -0,0 +1,248 @@
+//! Lights added through `RendererState` reach what they should and nothing else, however
+//! many there are.
+
+pub mod common;
+
+use common::{COMPANION_CUBE, frame};
+use glam::{Mat4, Vec3};
+use image::RgbaImage;
+use project::renderer::backend::definitions::{Camera, Projection};
+use project::renderer::backend::lights::{
+    CLUSTER_COUNT, DEFAULT_CLUSTER_DISTANCE, Light, LightStore, MAX_LIGHTS_PER_CLUSTER,
+};
+use project::renderer::renderer::RendererState;
+
+/// a cube ~40 units across whose -x face looks at the camera, and no lights.
+/// the OBJ's cube sits at y = -44, the instance moves it in front of the camera
+fn scene() -> RendererState {
+    let mut state = common::scene(
+        "cube",
+        COMPANION_CUBE,
+        Mat4::from_translation(Vec3::new(80.0, 44.0, 0.0)),
+    );
+    assert!(state.remove_light(state.sun()));
+    state
+}
+
+/// unlit surfaces are black, the clear color is too but for a hint of blue
+fn lit_pixels(image: &RgbaImage) -> usize {
+    image.pixels().filter(|p| p.0[0] > 16).count()
+}
+
+/// shines at the cube's front face from between it and the camera
+fn front_light() -> Light {
+    Light::point(Vec3::new(40.0, 0.0, 2.0), 60.0, Vec3::ONE, 2000.0)
+}
+
+#[test]
+fn handles_survive_removing_other_lights() {
+    let mut lights = LightStore::new();
+    let a = lights.add(front_light());
+    let b = lights.add(Light::directional(Vec3::X, Vec3::ONE, 1.0));
+    let c = lights.add(Light::directional(Vec3::Y, Vec3::ONE, 1.0));
+
+    assert!(lights.remove(a));
+    assert!(!lights.remove(a));
+    assert_eq!(lights.get(a), None);
+    assert_eq!(lights.len(), 2);
+
+    // c moved into a's place, its handle still finds it
+    assert_eq!(
+        lights.get(c),
+        Some(&Light::directional(Vec3::Y, Vec3::ONE, 1.0))
+    );
+    assert!(lights.set(b, front_light()));
+    assert_eq!(lights.get(b), Some(&front_light()));
+
+    // a's slot is reused, the old handle doesn't alias the new light
+    let d = lights.add(front_light());
+    assert_ne!(a, d);
+    assert_eq!(lights.get(a), None);
+    assert!(!lights.set(a, front_light()));
+}
+
+#[test]
+fn without_lights_only_emission_is_visible() {
+    let mut state = scene();
+    assert_eq!(lit_pixels(&frame(&mut state)), 0);
+
+    let light = state.add_light(front_light());
+    let lit = frame(&mut state);
+    assert!(lit_pixels(&lit) > 0);
+
+    // the cube is out of range
+    let short = Light::point(Vec3::new(40.0, 0.0, 2.0), 10.0, Vec3::ONE, 2000.0);
+    assert!(state.set_light(light, short));
+    assert_eq!(lit_pixels(&frame(&mut state)), 0);
+
+    assert!(state.remove_light(light));
+    assert_eq!(state.light(light), None);
+    assert_eq!(lit_pixels(&frame(&mut state)), 0);
+}
+
+#[test]
+fn spot_lights_only_light_their_cone() {
+    let mut state = scene();
+    let towards_cube = Light::spot(
+        Vec3::new(40.0, 0.0, 2.0),
+        Vec3::X,
+        60.0,
+        0.3,
+        0.5,
+        Vec3::ONE,
+        2000.0,
+    );
+    let spot = state.add_light(towards_cube);
+    assert!(lit_pixels(&frame(&mut state)) > 0);
+
+    let away = Light::spot(
+        Vec3::new(40.0, 0.0, 2.0),
+        Vec3::NEG_X,
+        60.0,
+        0.3,
+        0.5,
+        Vec3::ONE,
+        2000.0,
+    );
+    assert!(state.set_light(spot, away));
+    assert_eq!(lit_pixels(&frame(&mut state)), 0);
+}
+
+/// index into `read_light_clusters` of the cluster at screen tile `x`, `y` (from the top)
+/// and depth slice `slice`
+fn cluster(x: u32, y: u32, slice: u32) -> usize {
+    ((slice * CLUSTER_COUNT.y + y) * CLUSTER_COUNT.x + x) as usize
+}
+
+/// the depth slice view depth `depth` falls in, with the default camera's near plane and the
+/// far plane of `far`
+fn slice(depth: f32, far: f32) -> u32 {
+    let near = 0.5;
+    ((depth / near).ln() / (far / near).ln() * CLUSTER_COUNT.z as f32) as u32
+}
+
+#[test]
+fn lights_out_of_reach_cost_nothing_visible() {
+    let mut state = scene();
+    state.add_light(front_light());
+    let one = frame(&mut state);
+
+    // hundreds of small lights behind the camera, each cluster still only shades its own
+    for i in 0..500 {
+        let position = Vec3::new(-50.0 - (i % 20) as f32 * 5.0, (i / 20) as f32 * 5.0, 0.0);
+        state.add_light(Light::point(position, 2.0, Vec3::new(1.0, 0.0, 0.0), 100.0));
+    }
+    let many = frame(&mut state);
+
+    assert!(lit_pixels(&one) > 0);
+    assert!(one == many);
+
+    // the front light is light 0, 45 units ahead in the middle of the screen. it reaches the
+    // clusters around it and none past its range, the lights behind the camera reach none
+    let clusters = state.read_light_clusters();
+    assert_eq!(clusters.len(), CLUSTER_COUNT.element_product() as usize);
+    for lights in &clusters {
+        assert!(
+            lights.lights.is_empty() || lights.lights == [0],
+            "{:?}",
+            lights
+        );
+        assert_eq!(lights.reaching as usize, lights.lights.len());
+    }
+    let depth = slice(45.0, 10000.0);
+    for x in [7, 8] {
+        assert_eq!(clusters[cluster(x, 4, depth)].lights, [0]);
+    }
+    for far_slice in slice(45.0 + 60.0, 10000.0) + 1..CLUSTER_COUNT.z {
+        assert!(clusters[cluster(8, 4, far_slice)].lights.is_empty());
+    }
+    // the corner of the screen is 100 units off to the side that deep
+    assert!(
+        clusters[cluster(0, 0, slice(100.0, 10000.0))]
+            .lights
+            .is_empty()
+    );
+    assert_eq!(state.frame_stats().overflowing_light_clusters, 0);
+}
+
+#[test]
+fn lights_added_after_the_light_buffer_grows_are_clustered() {
+    let mut state = scene();
+    state.add_light(Light::point(
+        Vec3::new(-50.0, 0.0, 0.0),
+        2.0,
+        Vec3::ONE,
+        100.0,
+    ));
+    frame(&mut state);
+
+    // the buffer is reallocated, the front light is the last one in it
+    for i in 0..100 {
+        let position = Vec3::new(-60.0 - i as f32, 0.0, 0.0);
+        state.add_light(Light::point(position, 2.0, Vec3::ONE, 100.0));
+    }
+    state.add_light(front_light());
+    assert!(lit_pixels(&frame(&mut state)) > 0);
+    let depth = slice(45.0, 10000.0);
+    assert_eq!(
+        state.read_light_clusters()[cluster(8, 4, depth)].lights,
+        [101]
+    );
+}
+
+#[test]
+fn full_clusters_are_counted() {
+    let mut state = scene();
+    for _ in 0..MAX_LIGHTS_PER_CLUSTER + 7 {
+        state.add_light(Light::directional(Vec3::X, Vec3::ONE, 0.01));
+    }
+
+    // the count takes a frame or two to come back from the GPU
+    let mut overflowing = 0;
+    for _ in 0..10 {
+        frame(&mut state);
+        overflowing = state.frame_stats().overflowing_light_clusters;
+        if overflowing > 0 {
+            break;
+        }
+    }
+    assert_eq!(overflowing, CLUSTER_COUNT.element_product());
+
+    // directional lights reach everything, the first ones that fit are kept
+    let kept: Vec<u32> = (0..MAX_LIGHTS_PER_CLUSTER).collect();
+    for lights in state.read_light_clusters() {
+        assert_eq!(lights.reaching, MAX_LIGHTS_PER_CLUSTER + 7);
+        assert_eq!(lights.lights, kept);
+    }
+}
+
+#[test]
+fn infinite_projections_cluster_up_to_the_cluster_distance() {
+    let mut state = scene();
+    state.add_light(Light::point(
+        Vec3::new(3000.0, 0.0, 2.0),
+        10.0,
+        Vec3::ONE,
+        1.0,
+    ));
+    let mut camera = Camera::new();
+    camera.projection = Projection::ReverseZInfinite {
+        fov_y: 80.0,
+        z_near: 0.5,
+    };
+    let reached = |state: &mut RendererState| {
+        state.render(&camera).unwrap();
+        state
+            .read_light_clusters()
+            .iter()
+            .filter(|lights| !lights.lights.is_empty())
+            .count()
+    };
+
+    assert_eq!(state.cluster_distance(), DEFAULT_CLUSTER_DISTANCE);
+    assert!(reached(&mut state) > 0);
+
+    // the slices end before the light, it is in none of them
+    state.set_cluster_distance(1000.0);
+    assert_eq!(reached(&mut state), 0);
+}
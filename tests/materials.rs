@@ -0,0 +1,355 @@
+//! Materials of several OBJs share one registry without their indices colliding, and share
+//! pipelines by their features. Their maps reach the shader.
+
+pub mod common;
+
+use std::sync::Arc;
+
+use common::{COMPANION_CUBE, MemoryAssets, SPACESHIP, frame, png};
+use glam::{Mat4, Vec3, Vec4};
+use image::RgbaImage;
+use project::renderer::backend::definitions::{AlphaMode, Material, MaterialFeatures, PbrMaterial};
+use project::renderer::backend::lights::Light;
+use project::renderer::backend::materials::MaterialRegistry;
+use project::renderer::backend::mesh_builder::ObjLoader;
+use project::renderer::renderer::RendererState;
+
+fn mtl(source: &str) -> tobj::Material {
+    let (mut materials, _) = tobj::load_mtl_buf(&mut source.as_bytes()).unwrap();
+    materials.remove(0)
+}
+
+#[test]
+fn each_model_keeps_its_own_materials() {
+    let mut materials = MaterialRegistry::new();
+    let cube = ObjLoader::new()
+        .parse(COMPANION_CUBE, &mut materials, &Mat4::IDENTITY)
+        .unwrap();
+    let spaceship = ObjLoader::new()
+        .parse(SPACESHIP, &mut materials, &Mat4::IDENTITY)
+        .unwrap();
+
+    for submesh in &cube.submeshes {
+        let material = materials.get(submesh.material_id);
+        assert!(material.features.base_texture);
+        assert!(
+            material
+                .filename
+                .as_ref()
+                .unwrap()
+                .ends_with("companion_cube.png")
+        );
+    }
+
+    for submesh in &spaceship.submeshes {
+        let material = materials.get(submesh.material_id);
+        assert!(!material.features.base_texture);
+        assert_eq!(material.pbr.base_color, Vec4::new(0.8, 0.8, 0.8, 1.0));
+    }
+
+    // both OBJs number their first material 0, the registry must not
+    assert_ne!(
+        cube.submeshes[0].material_id,
+        spaceship.submeshes[0].material_id
+    );
+}
+
+#[test]
+fn identical_materials_are_registered_once() {
+    let mut materials = MaterialRegistry::new();
+    let first = ObjLoader::new()
+        .parse(COMPANION_CUBE, &mut materials, &Mat4::IDENTITY)
+        .unwrap();
+    let count = materials.len();
+
+    let second = ObjLoader::new()
+        .parse(COMPANION_CUBE, &mut materials, &Mat4::IDENTITY)
+        .unwrap();
+    assert_eq!(materials.len(), count);
+    assert_eq!(
+        first.submeshes[0].material_id,
+        second.submeshes[0].material_id
+    );
+}
+
+#[test]
+fn textures_are_decoded_once_per_file() {
+    let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+    state.load_assets("cube", COMPANION_CUBE).unwrap();
+    state.load_assets("spaceship", SPACESHIP).unwrap();
+    // the same file, spelled differently
+    state
+        .load_assets(
+            "cube_again",
+            "assets/companion_cube/../companion_cube/companion_cube.obj",
+        )
+        .unwrap();
+
+    assert_eq!(state.loaded_textures(), 1);
+}
+
+#[test]
+fn pipelines_are_built_once_per_feature_set() {
+    let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+    assert_eq!(state.built_pipelines(), 0);
+
+    state.load_assets("spaceship", SPACESHIP).unwrap();
+    assert_eq!(state.built_pipelines(), 1);
+    state.load_assets("cube", COMPANION_CUBE).unwrap();
+    assert_eq!(state.built_pipelines(), 2);
+
+    // a colored and a textured material again, nothing new to build
+    state.load_assets("spaceship_again", SPACESHIP).unwrap();
+    state.load_assets("cube_again", COMPANION_CUBE).unwrap();
+    assert_eq!(state.built_pipelines(), 2);
+}
+
+#[test]
+fn features_keep_materials_apart() {
+    let mut materials = MaterialRegistry::new();
+    let single = materials.register(Material::new());
+    let mut double_sided = Material::new();
+    double_sided.features = MaterialFeatures {
+        double_sided: true,
+        ..Default::default()
+    };
+    let double = materials.register(double_sided);
+
+    assert_ne!(single, double);
+    assert_eq!(materials.len(), 2);
+}
+
+#[test]
+fn phong_statements_map_to_metallic_roughness() {
+    let spaceship = mtl(include_str!("../assets/spaceship/spaceship.mtl"));
+    let pbr = PbrMaterial::new(Vec4::ONE).from_mtl(&spaceship);
+    assert_eq!(pbr.base_color, Vec4::new(0.8, 0.8, 0.8, 1.0));
+    // Blender writes Ns as 1000 (1 - roughness)², specular level 0.5 as Ks
+    assert!((pbr.roughness - 0.5).abs() < 1e-6);
+    assert!((pbr.reflectance - 0.04).abs() < 1e-6);
+    assert_eq!(pbr.metallic, 0.0);
+    assert_eq!(pbr.emissive, Vec3::ZERO);
+
+    // without Ks the index of refraction gives the reflectance, Tr the opacity
+    let glass = mtl("newmtl glass\nKd 0.1 0.2 0.3\nNi 1.5\nTr 0.75\n");
+    let pbr = PbrMaterial::new(Vec4::ONE).from_mtl(&glass);
+    assert!((pbr.reflectance - 0.04).abs() < 1e-6);
+    assert_eq!(pbr.base_color, Vec4::new(0.1, 0.2, 0.3, 0.25));
+
+    // no highlights, no reflections. statements that are missing keep the defaults
+    let chalk = mtl("newmtl chalk\nKs 1 1 1\nillum 1\n");
+    let pbr = PbrMaterial::new(Vec4::ONE).from_mtl(&chalk);
+    assert_eq!(pbr.reflectance, 0.0);
+    assert_eq!(pbr.base_color, Vec4::ONE);
+    assert_eq!(pbr.roughness, PbrMaterial::new(Vec4::ONE).roughness);
+}
+
+#[test]
+fn pbr_extensions_win_over_phong_statements() {
+    let obj = "mtllib brass.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvn 0 0 1\nusemtl brass\nf 1/1/1 2/1/1 3/1/1\n";
+    let mtl = "newmtl brass\nKd 0.9 0.6 0.2\nNs 900\nPr 0.3\nPm 1\nKe 0.1 0.2 0.3\nd 0.5\nmap_Pr brass_roughness.png\nmap_Pm brass_metallic.png\n";
+    let mut loader = ObjLoader::new();
+    loader.set_asset_source(Arc::new(MemoryAssets::from_iter([
+        ("models/brass.obj", obj),
+        ("models/brass.mtl", mtl),
+    ])));
+
+    let mut materials = MaterialRegistry::new();
+    let model = loader
+        .parse("models/brass.obj", &mut materials, &Mat4::IDENTITY)
+        .unwrap();
+    let material = materials.get(model.submeshes[0].material_id);
+
+    assert_eq!(material.pbr.base_color, Vec4::new(0.9, 0.6, 0.2, 0.5));
+    assert_eq!(material.pbr.roughness, 0.3);
+    assert_eq!(material.pbr.metallic, 1.0);
+    assert_eq!(material.pbr.emissive, Vec3::new(0.1, 0.2, 0.3));
+
+    assert_eq!(
+        material.features,
+        MaterialFeatures {
+            roughness_texture: true,
+            metallic_texture: true,
+            alpha_mode: AlphaMode::Blend,
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        material.roughness_texture.as_deref(),
+        Some("models/brass_roughness.png")
+    );
+    assert_eq!(
+        material.metallic_texture.as_deref(),
+        Some("models/brass_metallic.png")
+    );
+}
+
+#[test]
+fn map_paths_keep_their_spaces() {
+    let obj = "mtllib wall.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl wall\nf 1 2 3\n";
+    let mtl = "newmtl wall\nmap_Kd -o 0.5 0.5 -clamp on My Texture.png\nmap_Bump -bm 2 -mm 0 1 -s 2 bumpy 1.png\nmap_Ks -t 0.1 -not an option.png\n";
+    let mut loader = ObjLoader::new();
+    loader.set_asset_source(Arc::new(MemoryAssets::from_iter([
+        ("models/wall.obj", obj),
+        ("models/wall.mtl", mtl),
+    ])));
+
+    let mut materials = MaterialRegistry::new();
+    let model = loader
+        .parse("models/wall.obj", &mut materials, &Mat4::IDENTITY)
+        .unwrap();
+
+    let wall = materials.get(model.submeshes[0].material_id);
+    assert_eq!(wall.filename.as_deref(), Some("models/My Texture.png"));
+    assert_eq!(wall.normal_texture.as_deref(), Some("models/bumpy 1.png"));
+    assert_eq!(
+        wall.specular_texture.as_deref(),
+        Some("models/-not an option.png")
+    );
+}
+
+#[test]
+fn normal_specular_and_emissive_maps_are_loaded() {
+    let obj = "mtllib wall.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvn 0 0 1\nusemtl wall\nf 1/1/1 2/1/1 3/1/1\nusemtl panel\nf 1/1/1 3/1/1 2/1/1\n";
+    let mtl = "newmtl wall\nmap_Bump -bm 0.5 wall_normal.png\nmap_Ks wall_specular.png\nmap_Ke wall_emissive.png\nnewmtl panel\nKe 0.1 0.2 0.3\nnorm panel_normal.png\n";
+    let mut loader = ObjLoader::new();
+    loader.set_asset_source(Arc::new(MemoryAssets::from_iter([
+        ("models/wall.obj", obj),
+        ("models/wall.mtl", mtl),
+    ])));
+
+    let mut materials = MaterialRegistry::new();
+    let model = loader
+        .parse("models/wall.obj", &mut materials, &Mat4::IDENTITY)
+        .unwrap();
+
+    let wall = materials.get(model.submeshes[0].material_id);
+    assert_eq!(
+        wall.features,
+        MaterialFeatures {
+            normal_texture: true,
+            specular_texture: true,
+            emissive_texture: true,
+            ..Default::default()
+        }
+    );
+    // the options before the file are skipped
+    assert_eq!(
+        wall.normal_texture.as_deref(),
+        Some("models/wall_normal.png")
+    );
+    assert_eq!(
+        wall.specular_texture.as_deref(),
+        Some("models/wall_specular.png")
+    );
+    assert_eq!(
+        wall.emissive_texture.as_deref(),
+        Some("models/wall_emissive.png")
+    );
+    // without Ke the emissive map glows as it is
+    assert_eq!(wall.pbr.emissive, Vec3::ONE);
+
+    let panel = materials.get(model.submeshes[1].material_id);
+    assert_eq!(
+        panel.normal_texture.as_deref(),
+        Some("models/panel_normal.png")
+    );
+    assert_eq!(panel.pbr.emissive, Vec3::new(0.1, 0.2, 0.3));
+}
+
+/// a white, unreflective wall filling the middle of the view, with `map` naming `image` in
+/// its material. u runs to the right of the screen, v up
+fn wall(map: &str, image: Vec<u8>, light: Option<Light>) -> RgbaImage {
+    let obj = "mtllib wall.mtl\nv 30 15 -13\nv 30 -15 -13\nv 30 -15 17\nv 30 15 17\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvn -1 0 0\nusemtl wall\nf 1/1/1 2/2/1 3/3/1 4/4/1\n";
+    let mtl = format!("newmtl wall\nKd 1 1 1\nKs 0 0 0\n{} map.png\n", map);
+    let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+    state.set_asset_source(MemoryAssets::from_iter([
+        ("wall.obj", obj.as_bytes().to_vec()),
+        ("wall.mtl", mtl.into_bytes()),
+        ("map.png", image),
+    ]));
+    state.load_assets("wall", "wall.obj").unwrap();
+    state.spawn_instance("wall", Mat4::IDENTITY).unwrap();
+
+    assert!(state.remove_light(state.sun()));
+    if let Some(light) = light {
+        state.add_light(light);
+    }
+    frame(&mut state)
+}
+
+fn mean_red(image: &RgbaImage) -> f32 {
+    image.pixels().map(|p| p.0[0] as f32).sum::<f32>() / image.pixels().len() as f32
+}
+
+#[test]
+fn normal_maps_bend_the_light() {
+    // from behind the camera, the top right of the screen
+    let light = Light::directional(Vec3::new(1.0, 1.0, -1.0), Vec3::ONE, 2.0);
+    let flat = mean_red(&wall("map_Bump", png([128, 128, 255, 255]), Some(light)));
+    let towards = mean_red(&wall("map_Bump", png([191, 191, 217, 255]), Some(light)));
+    let away = mean_red(&wall("map_Bump", png([64, 64, 217, 255]), Some(light)));
+
+    // tilted right and up the surface faces the light, left and down away from it
+    assert!(towards > flat + 4.0, "{} {}", towards, flat);
+    assert!(away + 4.0 < flat, "{} {}", away, flat);
+}
+
+#[test]
+fn emissive_maps_glow_without_light() {
+    let glowing = wall("map_Ke", png([255, 255, 255, 255]), None);
+    let dark = wall("map_Ke", png([0, 0, 0, 255]), None);
+    assert!(mean_red(&glowing) > mean_red(&dark) + 16.0);
+}
+
+#[test]
+fn color_maps_are_sampled_as_srgb() {
+    // mid grey in the image comes out as mid grey again on the sRGB target
+    let grey = wall("map_Ke", png([128, 128, 128, 255]), None);
+    let center = grey.get_pixel(32, 24).0[0];
+    assert!(center.abs_diff(128) <= 3, "{}", center);
+
+    // the same file as a color and as a data map is uploaded once for each
+    let obj = "mtllib wall.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvn 0 0 1\nusemtl wall\nf 1/1/1 2/1/1 3/1/1\n";
+    let mtl = "newmtl wall\nmap_Kd map.png\nmap_Bump map.png\n";
+    let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+    state.set_asset_source(MemoryAssets::from_iter([
+        ("wall.obj", obj.as_bytes().to_vec()),
+        ("wall.mtl", mtl.as_bytes().to_vec()),
+        ("map.png", png([128, 128, 255, 255])),
+    ]));
+    state.load_assets("wall", "wall.obj").unwrap();
+    assert_eq!(state.loaded_textures(), 2);
+}
+
+/// the wall turned around, its front faces away from the camera, lit from the camera's side
+fn back_of_wall(mtl: &str) -> RgbaImage {
+    let obj = "mtllib wall.mtl\nv 30 15 -13\nv 30 15 17\nv 30 -15 17\nv 30 -15 -13\nvn 1 0 0\nusemtl wall\nf 1//1 2//1 3//1 4//1\n";
+    let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+    state.set_asset_source(MemoryAssets::from_iter([
+        ("wall.obj", obj.to_string()),
+        (
+            "wall.mtl",
+            format!("newmtl wall\nKd 1 1 1\nKs 0 0 0\n{}", mtl),
+        ),
+    ]));
+    state.load_assets("wall", "wall.obj").unwrap();
+    state.spawn_instance("wall", Mat4::IDENTITY).unwrap();
+
+    assert!(state.remove_light(state.sun()));
+    state.add_light(Light::directional(Vec3::X, Vec3::ONE, 2.0));
+    frame(&mut state)
+}
+
+#[test]
+fn double_sided_materials_draw_and_light_back_faces() {
+    let clear = [0, 0, 25, 255];
+    let culled = back_of_wall("");
+    assert!(culled.pixels().all(|p| p.0 == clear));
+
+    // the back faces the light, so it is lit rather than black
+    let double_sided = back_of_wall("double_sided on\n");
+    let drawn: Vec<_> = double_sided.pixels().filter(|p| p.0 != clear).collect();
+    assert!(!drawn.is_empty());
+    assert!(drawn.iter().all(|p| p.0[0] > 128), "{:?}", drawn);
+}
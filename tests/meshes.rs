@@ -0,0 +1,225 @@
+//! Indexed mesh output: welded vertices, compact indices and cache-friendly triangle order,
+//! with tangents for normal maps.
+
+pub mod common;
+
+use common::{COMPANION_CUBE, MemoryAssets, SPACESHIP, temp_dir};
+use glam::{Mat4, Vec3};
+use project::renderer::backend::assets::DirectoryAssets;
+use project::renderer::backend::materials::MaterialRegistry;
+use project::renderer::backend::mesh_builder::{MeshData, ObjLoader};
+use project::renderer::backend::mesh_cache;
+use project::renderer::backend::mesh_optimizer::average_cache_miss_ratio;
+use project::renderer::renderer::RendererState;
+use std::path::Path;
+use std::sync::Arc;
+
+fn parse(filename: &str, optimize: bool) -> MeshData {
+    let mut loader = ObjLoader::new();
+    loader.set_optimize(optimize);
+    loader
+        .parse(filename, &mut MaterialRegistry::new(), &Mat4::IDENTITY)
+        .unwrap()
+}
+
+/// every triangle as its three corner positions, rotated so that the smallest comes first
+fn triangles(mesh: &MeshData) -> Vec<[[u32; 3]; 3]> {
+    let mut triangles: Vec<[[u32; 3]; 3]> = mesh
+        .indices
+        .chunks(3)
+        .map(|t| {
+            let corners = [0, 1, 2].map(|i| mesh.vertices[t[i] as usize].position.to_array());
+            let corners = corners.map(|p| p.map(f32::to_bits));
+            let first = (0..3).min_by_key(|i| corners[*i]).unwrap();
+            [0, 1, 2].map(|i| corners[(first + i) % 3])
+        })
+        .collect();
+    triangles.sort();
+    triangles
+}
+
+#[test]
+fn welds_shared_vertices() {
+    for filename in [COMPANION_CUBE, SPACESHIP] {
+        let mesh = parse(filename, false);
+        // even flat shaded faces share the corners along their diagonal
+        assert!(
+            mesh.vertices.len() < mesh.indices.len(),
+            "{filename}: {} vertices for {} indices",
+            mesh.vertices.len(),
+            mesh.indices.len()
+        );
+        assert_eq!(mesh.index_format(), wgpu::IndexFormat::Uint16);
+    }
+}
+
+#[test]
+fn optimizing_keeps_triangles_and_reduces_cache_misses() {
+    for filename in [COMPANION_CUBE, SPACESHIP] {
+        let plain = parse(filename, false);
+        let optimized = parse(filename, true);
+
+        assert_eq!(plain.vertices.len(), optimized.vertices.len());
+        assert!(triangles(&plain) == triangles(&optimized), "{filename}");
+
+        let before = average_cache_miss_ratio(&plain.indices, 16);
+        let after = average_cache_miss_ratio(&optimized.indices, 16);
+        assert!(after < before, "{filename}: ACMR {before} -> {after}");
+    }
+}
+
+fn parse_cached(filename: &str, cache_dir: &Path) -> MeshData {
+    let mut loader = ObjLoader::new();
+    loader.set_cache_dir(Some(cache_dir.to_path_buf()));
+    loader
+        .parse(filename, &mut MaterialRegistry::new(), &Mat4::IDENTITY)
+        .unwrap()
+}
+
+#[test]
+fn cached_mesh_matches_the_parsed_one() {
+    let cache_dir = temp_dir("mesh_cache_roundtrip");
+    let parsed = parse(COMPANION_CUBE, false);
+
+    let first = parse_cached(COMPANION_CUBE, &cache_dir);
+    assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 1);
+    let cached = parse_cached(COMPANION_CUBE, &cache_dir);
+
+    for mesh in [&first, &cached] {
+        assert_eq!(mesh.indices, parsed.indices);
+        assert!(triangles(mesh) == triangles(&parsed));
+        assert_eq!(mesh.bounds, parsed.bounds);
+        assert_eq!(mesh.submeshes.len(), parsed.submeshes.len());
+    }
+    std::fs::remove_dir_all(&cache_dir).ok();
+}
+
+#[test]
+fn editing_the_source_invalidates_the_cache() {
+    let cache_dir = temp_dir("mesh_cache_invalidate");
+    let source_dir = temp_dir("mesh_cache_invalidate_source");
+    let obj = source_dir.join("spaceship.obj");
+    std::fs::copy(SPACESHIP, &obj).unwrap();
+    std::fs::copy(
+        "assets/spaceship/spaceship.mtl",
+        source_dir.join("spaceship.mtl"),
+    )
+    .unwrap();
+    let obj = obj.to_str().unwrap();
+
+    let before = parse_cached(obj, &cache_dir);
+
+    // move every vertex up by one unit
+    let moved: String = std::fs::read_to_string(obj)
+        .unwrap()
+        .lines()
+        .map(|line| match line.strip_prefix("v ") {
+            Some(position) => {
+                let p: Vec<f32> = position
+                    .split_whitespace()
+                    .map(|x| x.parse().unwrap())
+                    .collect();
+                format!("v {} {} {}\n", p[0], p[1], p[2] + 1.0)
+            }
+            None => format!("{line}\n"),
+        })
+        .collect();
+    std::fs::write(obj, moved).unwrap();
+
+    let after = parse_cached(obj, &cache_dir);
+    assert!((after.bounds.aabb.max.z - before.bounds.aabb.max.z - 1.0).abs() < 1e-4);
+
+    std::fs::remove_dir_all(&cache_dir).ok();
+    std::fs::remove_dir_all(&source_dir).ok();
+}
+
+#[test]
+fn meshes_are_cached_below_their_asset_directory() {
+    let asset_dir = temp_dir("mesh_cache_asset_dir");
+    for file in [
+        "companion_cube.obj",
+        "companion_cube.mtl",
+        "companion_cube.png",
+    ] {
+        std::fs::copy(
+            Path::new("assets/companion_cube").join(file),
+            asset_dir.join(file),
+        )
+        .unwrap();
+    }
+
+    // nothing is cached unless asked for
+    let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+    state.set_asset_source(DirectoryAssets::new(&asset_dir));
+    state.load_assets("cube", "companion_cube.obj").unwrap();
+    assert!(!asset_dir.join(mesh_cache::CACHE_DIR).exists());
+
+    let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+    let assets = DirectoryAssets::new(&asset_dir);
+    state.set_mesh_cache_dir(Some(assets.mesh_cache_dir()));
+    state.set_asset_source(assets);
+    state.load_assets("cube", "companion_cube.obj").unwrap();
+    let cached = std::fs::read_dir(asset_dir.join(mesh_cache::CACHE_DIR)).unwrap();
+    assert_eq!(cached.count(), 1);
+
+    // sources that can't be read have nothing to hash, they are loaded without the cache
+    assert_eq!(
+        mesh_cache::source_hash(&MemoryAssets::new(), "missing.obj", &[]),
+        None
+    );
+
+    std::fs::remove_dir_all(&asset_dir).ok();
+}
+
+#[test]
+fn tangents_follow_the_texture_coordinates() {
+    for filename in [COMPANION_CUBE, SPACESHIP] {
+        for vertex in parse(filename, false).vertices {
+            let tangent = vertex.tangent.truncate();
+            assert!(
+                (tangent.length() - 1.0).abs() < 1e-3,
+                "{filename}: {tangent}"
+            );
+            assert!(
+                tangent.dot(vertex.normal).abs() < 1e-3,
+                "{filename}: {tangent}"
+            );
+            assert_eq!(vertex.tangent.w.abs(), 1.0);
+        }
+    }
+
+    // a quad facing +z, u along +x and v along +y, then with u mirrored
+    let quad = |u: [f32; 2]| {
+        format!(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt {} 0\nvt {} 0\nvt {} 1\nvt {} 1\nvn 0 0 1\nf 1/1/1 2/2/1 3/3/1 4/4/1\n",
+            u[0], u[1], u[1], u[0]
+        )
+    };
+    for (u, tangent) in [([0.0, 1.0], Vec3::X), ([1.0, 0.0], Vec3::NEG_X)] {
+        let mut loader = ObjLoader::new();
+        loader.set_asset_source(Arc::new(MemoryAssets::from_iter([("quad.obj", quad(u))])));
+        let mesh = loader
+            .parse("quad.obj", &mut MaterialRegistry::new(), &Mat4::IDENTITY)
+            .unwrap();
+        for vertex in &mesh.vertices {
+            assert!(vertex.tangent.truncate().abs_diff_eq(tangent, 1e-5));
+            // the bitangent points up the image, along +v, however u runs
+            let bitangent = vertex.tangent.w * vertex.normal.cross(tangent);
+            assert!(bitangent.abs_diff_eq(Vec3::Y, 1e-5));
+        }
+    }
+
+    // without texture coordinates any tangent perpendicular to the normal does
+    let mut loader = ObjLoader::new();
+    loader.set_asset_source(Arc::new(MemoryAssets::from_iter([(
+        "bare.obj",
+        "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1\n",
+    )])));
+    let mesh = loader
+        .parse("bare.obj", &mut MaterialRegistry::new(), &Mat4::IDENTITY)
+        .unwrap();
+    for vertex in &mesh.vertices {
+        assert!(vertex.tangent.truncate().is_normalized());
+        assert_eq!(vertex.tangent.z, 0.0);
+    }
+}
@@ -0,0 +1,515 @@
+//! The WGSL shaders agree with the layouts the Rust side builds their pipelines with.
+//! Only naga is involved, no device is created.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use naga::valid::{Capabilities, ModuleInfo, ValidationFlags, Validator};
+use project::renderer::backend::assets::{DirectoryAssets, normalize};
+use project::renderer::backend::definitions::{
+    AlphaMode, BindScope, InstanceData, MaterialFeatures, TransparencyMode, VertexData,
+};
+use project::renderer::backend::gpu_culling::{self, GpuCuller};
+use project::renderer::backend::hi_z::{self, PyramidBuilder};
+use project::renderer::backend::lights::{self, LightClusterer};
+use project::renderer::backend::pipeline;
+use project::renderer::backend::shader_preprocessor::preprocess;
+use project::renderer::backend::shadows;
+use project::renderer::backend::transparency::{self, WeightedBlendedOit};
+use project::renderer::renderer::{MODEL_SHADER, RendererState};
+
+const SHADER_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders");
+
+/// the defines of every variant of the model shader. the alpha modes don't depend on the
+/// maps, each of them is paired with a third of the map combinations
+fn variants() -> Vec<Vec<&'static str>> {
+    let alpha_modes = [AlphaMode::Opaque, AlphaMode::Mask, AlphaMode::Blend];
+    (0..128)
+        .map(|bits: u32| MaterialFeatures {
+            base_texture: bits & 1 != 0,
+            roughness_texture: bits & 2 != 0,
+            metallic_texture: bits & 4 != 0,
+            normal_texture: bits & 8 != 0,
+            specular_texture: bits & 16 != 0,
+            emissive_texture: bits & 32 != 0,
+            double_sided: bits & 64 != 0,
+            alpha_mode: alpha_modes[bits as usize % 3],
+        })
+        .flat_map(|features| {
+            [TransparencyMode::Sorted, TransparencyMode::WeightedBlended]
+                .map(|mode| RendererState::model_defines(features, mode))
+        })
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// parses and validates `source`, naga's errors are rendered against the source
+fn compile(name: &str, source: &str) -> (naga::Module, ModuleInfo) {
+    let module = naga::front::wgsl::parse_str(source)
+        .unwrap_or_else(|error| panic!("{}", error.emit_to_string_with_path(source, name)));
+    let info = Validator::new(ValidationFlags::all(), Capabilities::PUSH_CONSTANT)
+        .validate(&module)
+        .unwrap_or_else(|error| panic!("{}", error.emit_to_string_with_path(source, name)));
+    (module, info)
+}
+
+/// preprocesses and compiles `name` from the shaders embedded into the crate
+fn load(name: &str, defines: &[&str]) -> (naga::Module, ModuleInfo) {
+    let source = preprocess(&pipeline::embedded_shaders(), name, defines)
+        .unwrap_or_else(|error| panic!("{}", error))
+        .code;
+    compile(name, &source)
+}
+
+/// the value of the `u32` constant `name` declared in `module`
+fn constant(module: &naga::Module, name: &str) -> u32 {
+    let (_, constant) = module
+        .constants
+        .iter()
+        .find(|(_, constant)| constant.name.as_deref() == Some(name))
+        .unwrap_or_else(|| panic!("no constant {}", name));
+    match module.global_expressions[constant.init] {
+        naga::Expression::Literal(naga::Literal::U32(value)) => value,
+        ref init => panic!("{} is not a u32 literal: {:?}", name, init),
+    }
+}
+
+/// every shader below `dir`, includes too, relative to `SHADER_DIR`
+fn find_shaders(dir: &Path, found: &mut Vec<String>) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            find_shaders(&path, found);
+        } else if path.extension().is_some_and(|e| e == "wgsl") {
+            found.push(normalize(path.strip_prefix(SHADER_DIR).unwrap()));
+        }
+    }
+}
+
+fn stage(stage: naga::ShaderStage) -> wgpu::ShaderStages {
+    match stage {
+        naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+        naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+        naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+        other => panic!("unsupported stage {:?}", other),
+    }
+}
+
+/// the stages whose entry points use `global`
+fn used_by(
+    module: &naga::Module,
+    info: &ModuleInfo,
+    global: naga::Handle<naga::GlobalVariable>,
+) -> wgpu::ShaderStages {
+    module
+        .entry_points
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !info.get_entry_point(*i)[global].is_empty())
+        .fold(wgpu::ShaderStages::NONE, |stages, (_, entry)| {
+            stages | stage(entry.stage)
+        })
+}
+
+/// the vertex format a `@location` input of type `ty` needs
+fn vertex_format(module: &naga::Module, ty: naga::Handle<naga::Type>) -> wgpu::VertexFormat {
+    let float = |scalar: naga::Scalar| {
+        assert_eq!(
+            scalar,
+            naga::Scalar::F32,
+            "only f32 vertex inputs are uploaded"
+        );
+    };
+    match module.types[ty].inner {
+        naga::TypeInner::Scalar(scalar) => {
+            float(scalar);
+            wgpu::VertexFormat::Float32
+        }
+        naga::TypeInner::Vector { size, scalar } => {
+            float(scalar);
+            match size {
+                naga::VectorSize::Bi => wgpu::VertexFormat::Float32x2,
+                naga::VectorSize::Tri => wgpu::VertexFormat::Float32x3,
+                naga::VectorSize::Quad => wgpu::VertexFormat::Float32x4,
+            }
+        }
+        ref other => panic!("unsupported vertex input {:?}", other),
+    }
+}
+
+/// `@location`s of the vertex entry point `entry`, arguments and struct members alike
+fn vertex_inputs(module: &naga::Module, entry: &str) -> BTreeMap<u32, wgpu::VertexFormat> {
+    let entry = module
+        .entry_points
+        .iter()
+        .find(|e| e.name == entry && e.stage == naga::ShaderStage::Vertex)
+        .unwrap_or_else(|| panic!("no vertex entry point {}", entry));
+
+    let mut inputs = BTreeMap::new();
+    let mut add = |binding: &Option<naga::Binding>, ty| {
+        if let Some(naga::Binding::Location { location, .. }) = binding {
+            inputs.insert(*location, vertex_format(module, ty));
+        }
+    };
+    for argument in &entry.function.arguments {
+        match &module.types[argument.ty].inner {
+            naga::TypeInner::Struct { members, .. } => {
+                for member in members {
+                    add(&member.binding, member.ty);
+                }
+            }
+            _ => add(&argument.binding, argument.ty),
+        }
+    }
+    inputs
+}
+
+/// the texture format of the storage texture formats the shaders use
+fn storage_format(format: naga::StorageFormat) -> Option<wgpu::TextureFormat> {
+    match format {
+        naga::StorageFormat::R32Float => Some(wgpu::TextureFormat::R32Float),
+        naga::StorageFormat::Rgba8Unorm => Some(wgpu::TextureFormat::Rgba8Unorm),
+        naga::StorageFormat::Rgba16Float => Some(wgpu::TextureFormat::Rgba16Float),
+        naga::StorageFormat::Rgba32Float => Some(wgpu::TextureFormat::Rgba32Float),
+        _ => None,
+    }
+}
+
+/// true if a layout entry of type `layout` can bind `global`
+fn binding_matches(
+    module: &naga::Module,
+    global: &naga::GlobalVariable,
+    layout: &wgpu::BindingType,
+) -> bool {
+    use naga::{AddressSpace, ImageClass, ImageDimension, ScalarKind, StorageAccess, TypeInner};
+
+    match (global.space, &module.types[global.ty].inner, layout) {
+        (
+            AddressSpace::Uniform,
+            _,
+            wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                ..
+            },
+        ) => true,
+        (
+            AddressSpace::Storage { access },
+            _,
+            wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                ..
+            },
+        ) => *read_only != access.contains(StorageAccess::STORE),
+        (
+            AddressSpace::Handle,
+            TypeInner::Image {
+                dim: ImageDimension::D2,
+                arrayed: false,
+                class:
+                    ImageClass::Sampled {
+                        kind: ScalarKind::Float,
+                        multi,
+                    },
+            },
+            wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { .. },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled,
+            },
+        ) => multi == multisampled,
+        (
+            AddressSpace::Handle,
+            TypeInner::Image {
+                dim: ImageDimension::D2,
+                arrayed: true,
+                class: ImageClass::Depth { multi: false },
+            },
+            wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Depth,
+                view_dimension: wgpu::TextureViewDimension::D2Array,
+                multisampled: false,
+            },
+        ) => true,
+        (
+            AddressSpace::Handle,
+            TypeInner::Image {
+                dim: ImageDimension::D2,
+                arrayed: false,
+                class: ImageClass::Storage { format, access },
+            },
+            wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: layout_format,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+        ) => *access == StorageAccess::STORE && storage_format(*format) == Some(*layout_format),
+        (
+            AddressSpace::Handle,
+            TypeInner::Sampler { comparison: false },
+            wgpu::BindingType::Sampler(
+                wgpu::SamplerBindingType::Filtering | wgpu::SamplerBindingType::NonFiltering,
+            ),
+        ) => true,
+        (
+            AddressSpace::Handle,
+            TypeInner::Sampler { comparison: true },
+            wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+        ) => true,
+        _ => false,
+    }
+}
+
+/// checks every resource and push constant `name` declares against the layouts its pipelines
+/// are created with: `groups[i]` is bind group `i`, `push_constants` the pipeline's range
+fn check_resources(
+    name: &str,
+    defines: &[&str],
+    groups: &[&[wgpu::BindGroupLayoutEntry]],
+    push_constants: Option<&wgpu::PushConstantRange>,
+) {
+    let (module, info) = load(name, defines);
+    let name = format!("{} {:?}", name, defines);
+
+    let mut declared = vec![Vec::new(); groups.len()];
+    for (handle, global) in module.global_variables.iter() {
+        let global_name = global.name.as_deref().unwrap_or("?");
+        let stages = used_by(&module, &info, handle);
+
+        if global.space == naga::AddressSpace::PushConstant {
+            let range = push_constants
+                .unwrap_or_else(|| panic!("{}: {} has no push constant range", name, global_name));
+            let size = module.types[global.ty].inner.size(module.to_ctx());
+            assert!(
+                range.range.start == 0 && size <= range.range.end,
+                "{}: {} is {} bytes, the range is {:?}",
+                name,
+                global_name,
+                size,
+                range.range
+            );
+            assert!(
+                range.stages.contains(stages),
+                "{}: {} is used by {:?} but pushed to {:?}",
+                name,
+                global_name,
+                stages,
+                range.stages
+            );
+            continue;
+        }
+
+        let Some(binding) = &global.binding else {
+            continue;
+        };
+        let entry = groups
+            .get(binding.group as usize)
+            .and_then(|entries| entries.iter().find(|e| e.binding == binding.binding))
+            .unwrap_or_else(|| {
+                panic!(
+                    "{}: {} at @group({}) @binding({}) is missing from the layout",
+                    name, global_name, binding.group, binding.binding
+                )
+            });
+        assert!(
+            binding_matches(&module, global, &entry.ty),
+            "{}: {} doesn't match its layout entry {:?}",
+            name,
+            global_name,
+            entry.ty
+        );
+        assert!(
+            entry.visibility.contains(stages),
+            "{}: {} is used by {:?} but only visible to {:?}",
+            name,
+            global_name,
+            stages,
+            entry.visibility
+        );
+        declared[binding.group as usize].push(binding.binding);
+    }
+
+    // entries the shader doesn't declare point at a layout that drifted from the shader
+    for (group, entries) in groups.iter().enumerate() {
+        for entry in entries.iter() {
+            assert!(
+                declared[group].contains(&entry.binding),
+                "{}: layout entry @group({}) @binding({}) is not declared",
+                name,
+                group,
+                entry.binding
+            );
+        }
+    }
+}
+
+#[test]
+fn every_shader_parses_and_validates() {
+    let mut names = Vec::new();
+    find_shaders(Path::new(SHADER_DIR), &mut names);
+    names.sort();
+    assert!(names.iter().any(|name| name.starts_with("include/")));
+
+    // includes are complete modules too, so an error shows up in the file that has it
+    let shaders = DirectoryAssets::new(SHADER_DIR);
+    for name in &names {
+        let source = preprocess(&shaders, name, &[]).unwrap();
+        compile(name, &source.code);
+    }
+}
+
+#[test]
+fn vertex_inputs_match_the_vertex_and_instance_layouts() {
+    let layouts = [VertexData::get_layout(), InstanceData::get_layout()];
+    let provided: BTreeMap<u32, wgpu::VertexFormat> = layouts
+        .iter()
+        .flat_map(|layout| layout.attributes.iter())
+        .map(|attribute| (attribute.shader_location, attribute.format))
+        .collect();
+    assert_eq!(
+        provided.keys().copied().collect::<Vec<_>>(),
+        (0..=7).collect::<Vec<_>>()
+    );
+
+    for defines in variants() {
+        let (module, _) = load(MODEL_SHADER, &defines);
+        assert_eq!(vertex_inputs(&module, "vs_main"), provided, "{:?}", defines);
+    }
+    let (module, _) = load(shadows::SHADER, &[]);
+    assert_eq!(vertex_inputs(&module, "vs_main"), provided);
+}
+
+#[test]
+fn material_shaders_match_their_bind_groups_and_push_constants() {
+    let material = RendererState::bind_group_layout(BindScope::Material);
+    let frame = RendererState::bind_group_layout(BindScope::Frame);
+    for defines in variants() {
+        check_resources(
+            MODEL_SHADER,
+            &defines,
+            &[material.entries(), frame.entries()],
+            Some(&pipeline::PUSH_CONSTANT_RANGE),
+        );
+    }
+    // the shadow pass only needs the cascade's view-projection
+    check_resources(
+        shadows::SHADER,
+        &[],
+        &[],
+        Some(&pipeline::PUSH_CONSTANT_RANGE),
+    );
+    check_resources(
+        transparency::SHADER,
+        &[],
+        &[WeightedBlendedOit::bind_group_layout().entries()],
+        Some(&pipeline::PUSH_CONSTANT_RANGE),
+    );
+}
+
+#[test]
+fn shader_constants_match_the_rust_side() {
+    let (module, _) = load("include/lights.wgsl", &[]);
+    assert_eq!(
+        constant(&module, "CLUSTER_STRIDE") as u64,
+        lights::CLUSTER_STRIDE
+    );
+}
+
+#[test]
+fn compute_shaders_match_their_bind_groups() {
+    check_resources(
+        gpu_culling::SHADER,
+        &[],
+        &[GpuCuller::bind_group_layout().entries()],
+        None,
+    );
+    check_resources(
+        hi_z::SHADER,
+        &[],
+        &[PyramidBuilder::bind_group_layout().entries()],
+        None,
+    );
+    check_resources(
+        lights::SHADER,
+        &[],
+        &[LightClusterer::bind_group_layout().entries()],
+        None,
+    );
+}
+
+/// the GL backend compiles each stage from a copy of the module stripped down to what the
+/// stage uses, but looks the types of push constants up in the unstripped module. a type the
+/// stage doesn't use ahead of them shifts their handles and the matrix gets uploaded as a float
+#[test]
+fn push_constant_types_survive_the_gl_backend() {
+    let variants = variants()
+        .into_iter()
+        .map(|defines| (MODEL_SHADER, defines));
+    for (name, defines) in variants.chain([(shadows::SHADER, Vec::new())]) {
+        let (module, info) = load(name, &defines);
+
+        for entry in &module.entry_points {
+            let (stage_module, stage_info) = naga::back::pipeline_constants::process_overrides(
+                &module,
+                &info,
+                Some((entry.stage, &entry.name)),
+                &naga::back::PipelineConstants::default(),
+            )
+            .unwrap();
+
+            let mut glsl = String::new();
+            let reflection = naga::back::glsl::Writer::new(
+                &mut glsl,
+                &stage_module,
+                &stage_info,
+                &naga::back::glsl::Options::default(),
+                &naga::back::glsl::PipelineOptions {
+                    shader_stage: entry.stage,
+                    entry_point: entry.name.clone(),
+                    multiview: None,
+                },
+                naga::proc::BoundsCheckPolicies::default(),
+            )
+            .unwrap()
+            .write()
+            .unwrap();
+
+            for item in &reflection.push_constant_items {
+                assert_eq!(
+                    module.types[item.ty].inner, stage_module.types[item.ty].inner,
+                    "{} {:?} {}: {} changes type when the stage is compiled",
+                    name, defines, entry.name, item.access_path
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn drift_is_reported() {
+    // the material and frame groups swapped, a texture bound as a uniform
+    let (module, _) = load(MODEL_SHADER, &["BASE_TEXTURE"]);
+    let material = RendererState::bind_group_layout(BindScope::Material);
+    let frame = RendererState::bind_group_layout(BindScope::Frame);
+    let texture = module
+        .global_variables
+        .iter()
+        .find(|(_, global)| global.name.as_deref() == Some("base_texture"))
+        .unwrap()
+        .1;
+    assert!(!binding_matches(
+        &module,
+        texture,
+        &material.entries()[0].ty
+    ));
+
+    let result = std::panic::catch_unwind(|| {
+        check_resources(
+            MODEL_SHADER,
+            &["BASE_TEXTURE"],
+            &[frame.entries(), material.entries()],
+            Some(&pipeline::PUSH_CONSTANT_RANGE),
+        )
+    });
+    assert!(result.is_err());
+}
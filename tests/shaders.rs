@@ -0,0 +1,197 @@
+//! Shaders come embedded in the binary, and hot reloading survives broken edits.
+
+pub mod common;
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use common::{MemoryAssets, SPACESHIP, frame, temp_dir};
+use glam::{Mat4, Vec3};
+use project::renderer::backend::assets::DirectoryAssets;
+use project::renderer::backend::shader_preprocessor::preprocess;
+use project::renderer::renderer::RendererState;
+
+fn scene() -> RendererState {
+    common::scene(
+        "spaceship",
+        SPACESHIP,
+        Mat4::from_translation(Vec3::new(10.0, 0.0, 0.0)),
+    )
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    std::fs::create_dir_all(to).unwrap();
+    for entry in std::fs::read_dir(from).unwrap() {
+        let path = entry.unwrap().path();
+        let target = to.join(path.file_name().unwrap());
+        match path.is_dir() {
+            true => copy_dir(&path, &target),
+            false => {
+                std::fs::copy(&path, target).unwrap();
+            }
+        }
+    }
+}
+
+/// a copy of `src/shaders` that the test can edit, `name` keeps parallel tests apart
+fn shader_copy(name: &str) -> PathBuf {
+    let dir = temp_dir(name);
+    copy_dir(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("src/shaders"),
+        &dir,
+    );
+    dir
+}
+
+fn lines(code: &str) -> Vec<&str> {
+    code.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect()
+}
+
+/// overwrites a shader, moving its modification time forward so the watcher can't miss it
+fn edit(path: &Path, source: &str, step: u64) {
+    std::fs::write(path, source).unwrap();
+    let file = std::fs::File::options().write(true).open(path).unwrap();
+    file.set_modified(SystemTime::now() + Duration::from_secs(step))
+        .unwrap();
+}
+
+#[test]
+fn hot_reload_keeps_the_old_pipeline_on_errors() {
+    let dir = shader_copy("shaders");
+    let model = dir.join("model.wgsl");
+    let original = std::fs::read_to_string(&model).unwrap();
+
+    let mut state = scene();
+    state.enable_shader_hot_reload(&dir);
+    let before = frame(&mut state);
+
+    // broken WGSL: reported, and the frame is drawn with the previous pipeline
+    edit(&model, &original.replace("fn fs_main", "fn fs_main("), 1);
+    let broken = frame(&mut state);
+    assert!(state.shader_error("model.wgsl").is_some());
+    assert!(broken == before);
+
+    // fixed again, now painting everything red
+    let red = original.replace(
+        "return vec4<f32>(color, base.a);",
+        "return vec4<f32>(1.0, 0.0, 0.0, 1.0);",
+    );
+    edit(&model, &red, 2);
+    let after = frame(&mut state);
+    assert_eq!(state.shader_error("model.wgsl"), None);
+    assert!(after.pixels().any(|p| p.0 == [255, 0, 0, 255]));
+    assert!(!before.pixels().any(|p| p.0 == [255, 0, 0, 255]));
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn editing_an_include_rebuilds_the_shaders_using_it() {
+    let dir = shader_copy("includes");
+    let pbr = dir.join("include/pbr.wgsl");
+    let original = std::fs::read_to_string(&pbr).unwrap();
+
+    let mut state = scene();
+    state.enable_shader_hot_reload(&dir);
+    let before = frame(&mut state);
+
+    // the include is reported under its own name, both material shaders keep working
+    edit(&pbr, &original.replace("fn shade", "fn shade("), 1);
+    assert!(frame(&mut state) == before);
+    assert!(state.shader_error("include/pbr.wgsl").is_some());
+
+    // full light everywhere: the shading of the spaceship flattens out
+    let flat = original.replace("let n_dot_l = max(dot(n, l), 0.0);", "let n_dot_l = 1.0;");
+    edit(&pbr, &flat, 2);
+    let after = frame(&mut state);
+    assert_eq!(state.shader_error("include/pbr.wgsl"), None);
+    assert!(after != before);
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn variants_that_fail_to_compile_are_errors() {
+    let dir = shader_copy("variants");
+    let model = dir.join("model.wgsl");
+    let original = std::fs::read_to_string(&model).unwrap();
+    let broken = "normal = select(-normal, normal, front_facing)(;";
+    std::fs::write(
+        &model,
+        original.replace("normal = select(-normal, normal, front_facing);", broken),
+    )
+    .unwrap();
+
+    let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+    state.set_shader_source(DirectoryAssets::new(&dir));
+    let triangle = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+    state.set_asset_source(MemoryAssets::from_iter([
+        ("one.obj", format!("mtllib one.mtl\nusemtl one\n{triangle}")),
+        ("one.mtl", "newmtl one\nKd 1 1 1\n".to_string()),
+        ("two.obj", format!("mtllib two.mtl\nusemtl two\n{triangle}")),
+        (
+            "two.mtl",
+            "newmtl two\nKd 1 1 1\ndouble_sided on\n".to_string(),
+        ),
+    ]));
+
+    // only the double sided variant is broken, its model is left out
+    assert!(state.load_assets("two", "two.obj").is_err());
+    assert!(state.spawn_instance("two", Mat4::IDENTITY).is_err());
+    state.load_assets("one", "one.obj").unwrap();
+    state.spawn_instance("one", Mat4::IDENTITY).unwrap();
+    frame(&mut state);
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+#[test]
+fn includes_and_defines_are_resolved() {
+    let sources = MemoryAssets::from_iter([
+        (
+            "main.wgsl",
+            "#include \"lib/a.wgsl\"\n#include \"lib/b.wgsl\"\n#ifdef RED\nred\n#else\nblue\n#endif\n#ifndef RED\nnot red\n#endif",
+        ),
+        // b includes a again, which is skipped
+        ("lib/a.wgsl", "#define FROM_A\na"),
+        (
+            "lib/b.wgsl",
+            "#include \"a.wgsl\"\n#ifdef FROM_A\nb\n#endif",
+        ),
+    ]);
+
+    let plain = preprocess(&sources, "main.wgsl", &[]).unwrap();
+    assert_eq!(lines(&plain.code), ["a", "b", "blue", "not red"]);
+    assert_eq!(plain.files, ["main.wgsl", "lib/a.wgsl", "lib/b.wgsl"]);
+
+    let red = preprocess(&sources, "main.wgsl", &["RED"]).unwrap();
+    assert_eq!(lines(&red.code), ["a", "b", "red"]);
+}
+
+#[test]
+fn bad_directives_are_errors() {
+    let sources = MemoryAssets::from_iter([
+        ("missing.wgsl", "#include \"nowhere.wgsl\""),
+        ("unclosed.wgsl", "#ifdef RED\nred"),
+        ("unopened.wgsl", "red\n#endif"),
+        (
+            "twice.wgsl",
+            "#ifdef RED\nred\n#else\nblue\n#else\ngreen\n#endif",
+        ),
+        ("unknown.wgsl", "#pragma once"),
+    ]);
+
+    for (file, message) in [
+        ("missing.wgsl", "nowhere.wgsl"),
+        ("unclosed.wgsl", "without #endif"),
+        ("unopened.wgsl", "without #ifdef"),
+        ("twice.wgsl", "twice.wgsl:5: second #else"),
+        ("unknown.wgsl", "#pragma"),
+    ] {
+        let error = preprocess(&sources, file, &[]).err().unwrap();
+        assert!(error.contains(message), "{}: {}", file, error);
+    }
+}
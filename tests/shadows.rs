@@ -0,0 +1,218 @@
+//! The shadow cascades cover the view and hold still, and every instance casts a shadow
+//! whether the camera sees it or not. Cutouts cast the shadow of what is left of them, and
+//! blended surfaces cast none.
+
+pub mod common;
+
+use common::{COMPANION_CUBE, MemoryAssets, frame, frames_match, png};
+use glam::{Mat4, Vec3};
+use image::RgbaImage;
+use project::renderer::backend::definitions::{Camera, CullingMode};
+use project::renderer::backend::lights::{Light, LightHandle};
+use project::renderer::backend::shadows::{self, CASCADE_COUNT};
+use project::renderer::renderer::RendererState;
+
+/// slanted so the shadows land beside what casts them
+const LIGHT_DIRECTION: Vec3 = Vec3::new(1.0, -0.3, -0.1);
+
+/// a camera looking down and to the side, so no axis lines up with the light
+fn turned_camera() -> Camera {
+    let mut camera = Camera::new();
+    camera.position = Vec3::new(10.0, -20.0, 30.0);
+    camera.forwards = Vec3::new(0.6, 0.5, -0.3).normalize();
+    camera.right = camera.forwards.cross(Vec3::Z).normalize();
+    camera.up = camera.right.cross(camera.forwards);
+    camera
+}
+
+#[test]
+fn cascades_cover_their_slice_of_the_view() {
+    let camera = turned_camera();
+    let aspect = 4.0 / 3.0;
+    let cascades = shadows::fit_cascades(&camera, aspect, LIGHT_DIRECTION, 300.0, 1024);
+
+    assert!(cascades.splits.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!((cascades.splits[CASCADE_COUNT - 1] - 300.0).abs() < 1e-3);
+
+    // points of each slice of the view land inside that cascade's shadow map
+    let inverse_view = camera.view().inverse();
+    let tan_y = (80.0f32.to_radians() * 0.5).tan();
+    let mut start = 0.5;
+    for (i, end) in cascades.splits.iter().enumerate() {
+        for depth in [start, *end] {
+            for (x, y) in [
+                (-1.0, -1.0),
+                (1.0, -1.0),
+                (-1.0, 1.0),
+                (1.0, 1.0),
+                (0.0, 0.0),
+            ] {
+                let view = Vec3::new(x * tan_y * aspect * depth, y * tan_y * depth, -depth);
+                let world = inverse_view.transform_point3(view);
+                let clip = cascades.view_projections[i].project_point3(world);
+                assert!(
+                    clip.x.abs() <= 1.0 && clip.y.abs() <= 1.0 && (0.0..=1.0).contains(&clip.z),
+                    "cascade {} misses {} at depth {}",
+                    i,
+                    world,
+                    depth
+                );
+            }
+        }
+        start = *end;
+    }
+}
+
+#[test]
+fn cascades_move_in_whole_texels() {
+    let mut camera = turned_camera();
+    let map_size = 1024;
+    let before = shadows::fit_cascades(&camera, 1.0, LIGHT_DIRECTION, 300.0, map_size);
+    camera.position += Vec3::new(0.37, -0.21, 0.05);
+    let after = shadows::fit_cascades(&camera, 1.0, LIGHT_DIRECTION, 300.0, map_size);
+
+    // the same cascade size, and a fixed point only slides by whole texels, so shadow edges
+    // don't crawl while the camera moves
+    assert_eq!(before.texel_sizes, after.texel_sizes);
+    let point = Vec3::new(40.0, 10.0, 5.0);
+    for i in 0..CASCADE_COUNT {
+        let shift = (after.view_projections[i].project_point3(point)
+            - before.view_projections[i].project_point3(point))
+        .truncate()
+            * map_size as f32
+            * 0.5;
+        assert!(
+            (shift - shift.round()).abs().max_element() < 1e-2,
+            "cascade {} moved by {} texels",
+            i,
+            shift
+        );
+    }
+}
+
+/// a wall ~200 units across facing the camera, the shadowed light shining at it past the
+/// camera and no other light
+fn wall_scene() -> (RendererState, LightHandle) {
+    let mut state = pollster::block_on(RendererState::new_headless(128, 96));
+    state.load_assets("cube", COMPANION_CUBE).unwrap();
+    state
+        .spawn_instance(
+            "cube",
+            cube(Vec3::new(200.0, 0.0, 0.0), Vec3::new(0.1, 5.0, 5.0)),
+        )
+        .unwrap();
+
+    assert!(state.remove_light(state.sun()));
+    let mut light = Light::directional(LIGHT_DIRECTION, Vec3::ONE, 3.0);
+    light.cast_shadows = true;
+    let light = state.add_light(light);
+    (state, light)
+}
+
+/// the companion cube scaled by `scale` and centered on `center`, the OBJ's cube sits at y = -44
+fn cube(center: Vec3, scale: Vec3) -> Mat4 {
+    Mat4::from_translation(center)
+        * Mat4::from_scale(scale)
+        * Mat4::from_translation(Vec3::new(0.0, 44.0, 0.0))
+}
+
+/// pixels that are clearly darker in `shadowed` than in `lit`
+fn darkened_pixels(shadowed: &RgbaImage, lit: &RgbaImage) -> usize {
+    shadowed
+        .pixels()
+        .zip(lit.pixels())
+        .filter(|(s, l)| s.0[0] as i32 + 32 < l.0[0] as i32)
+        .count()
+}
+
+/// renders with the light's shadows on, then off
+fn frames_with_and_without_shadows(
+    state: &mut RendererState,
+    light: LightHandle,
+) -> (RgbaImage, RgbaImage) {
+    let mut toggled = state.light(light).unwrap();
+    toggled.cast_shadows = true;
+    state.set_light(light, toggled);
+    let with = frame(state);
+
+    toggled.cast_shadows = false;
+    state.set_light(light, toggled);
+    (with, frame(state))
+}
+
+#[test]
+fn shadows_follow_the_light_toggle() {
+    // a lit surface doesn't shadow itself
+    let (mut state, light) = wall_scene();
+    let (with, without) = frames_with_and_without_shadows(&mut state, light);
+    assert_eq!(darkened_pixels(&with, &without), 0);
+
+    state
+        .spawn_instance("cube", cube(Vec3::new(100.0, 40.0, 0.0), Vec3::splat(0.8)))
+        .unwrap();
+    let (with, without) = frames_with_and_without_shadows(&mut state, light);
+    assert!(darkened_pixels(&with, &without) > 50);
+}
+
+#[test]
+fn casters_out_of_view_still_cast_shadows() {
+    let (mut state, _) = wall_scene();
+    // behind the camera, between it and the light
+    state
+        .spawn_instance("cube", cube(Vec3::new(-60.0, 60.0, 20.0), Vec3::splat(0.8)))
+        .unwrap();
+
+    state.set_culling_mode(CullingMode::Off);
+    let unculled = frame(&mut state);
+    let mut lit = wall_scene().0;
+    assert!(darkened_pixels(&unculled, &frame(&mut lit)) > 50);
+
+    for mode in [CullingMode::Cpu, CullingMode::Gpu] {
+        state.set_culling_mode(mode);
+        assert!(frames_match(&frame(&mut state), &unculled), "{:?}", mode);
+    }
+
+    // the camera still only draws the wall
+    state.set_culling_mode(CullingMode::Cpu);
+    frame(&mut state);
+    assert_eq!(state.frame_stats().visible_instances, 1);
+}
+
+/// adds a 60 by 60 square between the light and the wall of `wall_scene`, in a material of
+/// `mtl` statements with `image` as `image.png`
+fn spawn_quad(state: &mut RendererState, mtl: &str, image: [u8; 4]) {
+    let mut files = MemoryAssets::new();
+    files.add_file(
+        "quad.obj",
+        "mtllib quad.mtl\nv 100 10 -30\nv 100 70 -30\nv 100 70 30\nv 100 10 30\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvn -1 0 0\nusemtl quad\nf 1/1/1 2/2/1 3/3/1 4/4/1\n",
+    );
+    files.add_file("quad.mtl", format!("newmtl quad\n{}\n", mtl));
+    files.add_file("image.png", png(image));
+    state.set_asset_source(files);
+    state.load_assets("quad", "quad.obj").unwrap();
+    state.spawn_instance("quad", Mat4::IDENTITY).unwrap();
+}
+
+#[test]
+fn cutouts_cast_the_shadow_of_what_is_left() {
+    let cutout = "Kd 1 1 1\nmap_Kd image.png\nmap_d image.png";
+
+    let (mut state, light) = wall_scene();
+    spawn_quad(&mut state, cutout, [255, 255, 255, 255]);
+    let (with, without) = frames_with_and_without_shadows(&mut state, light);
+    assert!(darkened_pixels(&with, &without) > 50);
+
+    // the same quad, cut out entirely
+    let (mut state, light) = wall_scene();
+    spawn_quad(&mut state, cutout, [255, 255, 255, 0]);
+    let (with, without) = frames_with_and_without_shadows(&mut state, light);
+    assert_eq!(darkened_pixels(&with, &without), 0);
+}
+
+#[test]
+fn blended_surfaces_cast_no_shadow() {
+    let (mut state, light) = wall_scene();
+    spawn_quad(&mut state, "Kd 1 1 1\nd 0.9", [255, 255, 255, 255]);
+    let (with, without) = frames_with_and_without_shadows(&mut state, light);
+    assert_eq!(darkened_pixels(&with, &without), 0);
+}
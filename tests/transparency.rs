@@ -0,0 +1,169 @@
+//! Blended materials show what is behind them in the right order, cutouts hide nothing where
+//! they are cut, and the MTL statements pick between them.
+
+pub mod common;
+
+use std::sync::Arc;
+
+use common::{MemoryAssets, frame, png};
+use glam::Mat4;
+use image::{Rgba, RgbaImage};
+use project::renderer::backend::definitions::{AlphaMode, TransparencyMode};
+use project::renderer::backend::materials::MaterialRegistry;
+use project::renderer::backend::mesh_builder::ObjLoader;
+use project::renderer::renderer::RendererState;
+
+/// a 20 by 20 square facing the camera, `distance` ahead of it, in a material of `mtl`
+/// statements. every quad needs its own `name`, meshes are cached by file name
+#[derive(Clone, Copy)]
+struct Quad {
+    name: &'static str,
+    distance: f32,
+    mtl: &'static str,
+}
+
+const RED_GLASS: Quad = Quad {
+    name: "red_glass",
+    distance: 20.0,
+    mtl: "Kd 0 0 0\nKe 1 0 0\nd 0.5",
+};
+const BLUE_GLASS: Quad = Quad {
+    name: "blue_glass",
+    distance: 30.0,
+    mtl: "Kd 0 0 0\nKe 0 0 1\nd 0.5",
+};
+
+/// renders `quads` spawned in their order, without lights, with `image` as `image.png`
+fn render(quads: &[Quad], image: Option<Vec<u8>>, mode: TransparencyMode) -> RgbaImage {
+    let mut files = MemoryAssets::new();
+    for quad in quads {
+        // the camera looks along +x from (-5, 0, 2), the screen's right is -y
+        let x = quad.distance - 5.0;
+        let obj = format!(
+            "mtllib {0}.mtl\nv {1} 10 -8\nv {1} -10 -8\nv {1} -10 12\nv {1} 10 12\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvn -1 0 0\nusemtl {0}\nf 1/1/1 2/2/1 3/3/1 4/4/1\n",
+            quad.name, x
+        );
+        let mtl = format!("newmtl {}\n{}\n", quad.name, quad.mtl);
+        files.add_file(&format!("{}.obj", quad.name), obj);
+        files.add_file(&format!("{}.mtl", quad.name), mtl);
+    }
+    if let Some(image) = image {
+        files.add_file("image.png", image);
+    }
+
+    let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+    state.set_asset_source(files);
+    state.set_transparency_mode(mode);
+    assert!(state.remove_light(state.sun()));
+    for quad in quads {
+        state
+            .load_assets(quad.name, &format!("{}.obj", quad.name))
+            .unwrap();
+        state.spawn_instance(quad.name, Mat4::IDENTITY).unwrap();
+    }
+
+    frame(&mut state)
+}
+
+fn center(image: &RgbaImage) -> Rgba<u8> {
+    *image.get_pixel(image.width() / 2, image.height() / 2)
+}
+
+#[test]
+fn blended_surfaces_are_drawn_back_to_front() {
+    // the far quad is spawned last, drawing in spawn order would put it over the near one
+    let frame = render(&[RED_GLASS, BLUE_GLASS], None, TransparencyMode::Sorted);
+    let [r, _, b, _] = center(&frame).0;
+    assert!(r > b + 32, "{:?}", center(&frame));
+    assert!(b > 32, "{:?}", center(&frame));
+
+    let swapped = render(&[BLUE_GLASS, RED_GLASS], None, TransparencyMode::Sorted);
+    assert!(frame == swapped);
+}
+
+#[test]
+fn weighted_blended_transparency_needs_no_order() {
+    let frame = render(
+        &[RED_GLASS, BLUE_GLASS],
+        None,
+        TransparencyMode::WeightedBlended,
+    );
+    let swapped = render(
+        &[BLUE_GLASS, RED_GLASS],
+        None,
+        TransparencyMode::WeightedBlended,
+    );
+    assert!(frame == swapped);
+
+    // the nearer surface weighs more, the one behind still shows
+    let [r, _, b, _] = center(&frame).0;
+    assert!(r > b, "{:?}", center(&frame));
+    assert!(b > 16, "{:?}", center(&frame));
+
+    // where only one surface is, it covers as much as it does when sorted
+    let single = render(&[RED_GLASS], None, TransparencyMode::WeightedBlended);
+    let sorted = render(&[RED_GLASS], None, TransparencyMode::Sorted);
+    let difference = (center(&single).0[0] as i32 - center(&sorted).0[0] as i32).abs();
+    assert!(
+        difference <= 2,
+        "{:?} {:?}",
+        center(&single),
+        center(&sorted)
+    );
+}
+
+#[test]
+fn cutouts_hide_nothing_where_they_are_cut() {
+    let cutout = Quad {
+        name: "green_cutout",
+        distance: 20.0,
+        mtl: "Kd 1 1 1\nKe 0 1 0\nmap_Kd image.png\nmap_d image.png",
+    };
+    let wall = Quad {
+        name: "red_wall",
+        distance: 30.0,
+        mtl: "Kd 0 0 0\nKe 1 0 0",
+    };
+
+    // drawn first, the cut out quad must not have written depth over the wall
+    let cut = render(
+        &[cutout, wall],
+        Some(png([255, 255, 255, 0])),
+        TransparencyMode::Sorted,
+    );
+    let [r, g, _, _] = center(&cut).0;
+    assert!(r > 200 && g < 8, "{:?}", center(&cut));
+
+    let solid = render(
+        &[cutout, wall],
+        Some(png([255, 255, 255, 255])),
+        TransparencyMode::Sorted,
+    );
+    let [r, g, _, _] = center(&solid).0;
+    assert!(g > 200 && r < 8, "{:?}", center(&solid));
+}
+
+#[test]
+fn dissolve_picks_the_alpha_mode() {
+    let obj = "mtllib m.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvn 0 0 1\nusemtl glass\nf 1/1/1 2/1/1 3/1/1\nusemtl leaf\nf 1/1/1 3/1/1 2/1/1\nusemtl stone\nf 1/1/1 2/1/1 3/1/1\n";
+    let mtl = "newmtl glass\nTr 0.5\nnewmtl leaf\nd 1\nmap_Kd leaf.png\nmap_d leaf.png\nnewmtl stone\nd 1\n";
+    let mut loader = ObjLoader::new();
+    loader.set_asset_source(Arc::new(MemoryAssets::from_iter([
+        ("m.obj", obj),
+        ("m.mtl", mtl),
+    ])));
+
+    let mut materials = MaterialRegistry::new();
+    let model = loader
+        .parse("m.obj", &mut materials, &Mat4::IDENTITY)
+        .unwrap();
+    let modes: Vec<AlphaMode> = model
+        .submeshes
+        .iter()
+        .map(|submesh| materials.get(submesh.material_id).features.alpha_mode)
+        .collect();
+    assert_eq!(
+        modes,
+        [AlphaMode::Blend, AlphaMode::Mask, AlphaMode::Opaque]
+    );
+}
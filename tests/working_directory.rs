@@ -0,0 +1,32 @@
+//! Shaders load from wherever the binary is started. Alone in its own test binary, since it
+//! changes the working directory of the whole process.
+
+use glam::{Mat4, Vec3};
+use project::renderer::backend::definitions::Camera;
+use project::renderer::renderer::RendererState;
+
+const SPACESHIP: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/assets/spaceship/spaceship.obj"
+);
+
+#[test]
+fn shaders_load_from_any_working_directory() {
+    let elsewhere = std::env::temp_dir().join(format!("elsewhere_{}", std::process::id()));
+    std::fs::create_dir_all(&elsewhere).unwrap();
+    std::env::set_current_dir(&elsewhere).unwrap();
+
+    let mut state = pollster::block_on(RendererState::new_headless(64, 48));
+    state.load_assets("spaceship", SPACESHIP).unwrap();
+    state
+        .spawn_instance(
+            "spaceship",
+            Mat4::from_translation(Vec3::new(10.0, 0.0, 0.0)),
+        )
+        .unwrap();
+    state.render(&Camera::new()).unwrap();
+    let image = state.read_frame().unwrap();
+    assert!(image.pixels().any(|p| p.0 != image.get_pixel(0, 0).0));
+
+    std::fs::remove_dir_all(&elsewhere).ok();
+}